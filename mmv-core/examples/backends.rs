@@ -0,0 +1,45 @@
+//! Time building plans of growing sizes with each of the backends, as in
+//! `cargo run --release -p mmv-core --example backends`.
+
+use std::{env, path::PathBuf, time::Instant};
+
+use mmv_core::{Backend, Plan};
+
+fn main() {
+	let max = env::args()
+		.nth(1)
+		.and_then(|n| n.parse().ok())
+		.unwrap_or(4_000_000);
+	println!(
+		"{:>10}  {:>10}  {:>10}  {:>12}  {:>12}",
+		"pairs", "trie", "sorted", "trie mem", "sorted mem"
+	);
+	let mut n = 1000;
+	while n <= max {
+		/* Files spread over a thousand directories, all renamed in place */
+		let pairs = (0..n)
+			.map(|i| {
+				let dir = format!("/srv/data/dir-{}", i % 1000);
+				(
+					PathBuf::from(format!("{dir}/file-{i}")),
+					PathBuf::from(format!("{dir}/renamed-file-{i}")),
+				)
+			})
+			.collect::<Vec<_>>();
+		let mut row = Vec::new();
+		for b in [Backend::Trie, Backend::Sorted] {
+			let pairs = pairs.clone();
+			let start = Instant::now();
+			let plan = Plan::from_pairs_with("/srv/data/.stage", pairs, b).unwrap();
+			row.push((start.elapsed(), plan.memory_usage()));
+		}
+		println!(
+			"{n:>10}  {:>8.1}ms  {:>8.1}ms  {:>10}KiB  {:>10}KiB",
+			row[0].0.as_secs_f64() * 1000.0,
+			row[1].0.as_secs_f64() * 1000.0,
+			row[0].1 / 1024,
+			row[1].1 / 1024
+		);
+		n *= 4;
+	}
+}
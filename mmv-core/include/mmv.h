@@ -0,0 +1,31 @@
+/* C interface to the mmv-core planner; see src/capi.rs for documentation. */
+
+#ifndef MMV_H
+#define MMV_H
+
+#include <stddef.h>
+
+#define MMV_OK                     0
+#define MMV_DUPLICATE_SOURCE       1
+#define MMV_DUPLICATE_DESTINATION  2
+#define MMV_INVALID               -1
+
+#define MMV_MISSING_SOURCE         1
+#define MMV_EXISTING_DESTINATION   2
+#define MMV_NESTED_DESTINATION     3
+#define MMV_SPLIT_SOURCE           4
+#define MMV_CASE_COLLISION         5
+
+typedef struct MmvPlan MmvPlan;
+
+MmvPlan *mmv_plan_new(const char *dir);
+int mmv_plan_add(MmvPlan *plan, const char *src, const char *dst);
+size_t mmv_plan_ops(MmvPlan *plan);
+int mmv_plan_op(MmvPlan *plan, size_t i, const char **from, const char **to);
+size_t mmv_plan_validate(MmvPlan *plan);
+int mmv_plan_issue(MmvPlan *plan, size_t i, int *kind, const char **path,
+                   const char **other);
+void mmv_plan_clear(MmvPlan *plan);
+void mmv_plan_free(MmvPlan *plan);
+
+#endif /* !MMV_H */
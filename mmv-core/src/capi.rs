@@ -0,0 +1,415 @@
+//! A C interface to [`Plan`], enabled by the `capi` feature.  Build it as a
+//! library C programs can link against with
+//!
+//! ```sh
+//! cargo rustc -p mmv-core --features capi --release --crate-type cdylib
+//! ```
+//!
+//! The matching declarations are in `include/mmv.h`.  All strings are
+//! NUL-terminated byte strings; paths need not be valid UTF-8.
+
+use std::{
+	ffi::{c_char, c_int, CStr, CString, OsStr},
+	os::unix::ffi::OsStrExt,
+	path::{Path, PathBuf},
+	ptr,
+};
+
+use crate::{
+	plan::{Error, Issue, Plan},
+	vfs,
+};
+
+/// The pair was added to the plan.
+pub const MMV_OK: c_int = 0;
+/// The source of the pair is already part of the plan.
+pub const MMV_DUPLICATE_SOURCE: c_int = 1;
+/// The destination of the pair is already part of the plan.
+pub const MMV_DUPLICATE_DESTINATION: c_int = 2;
+/// An argument was a null pointer, or the plan can no longer be modified.
+pub const MMV_INVALID: c_int = -1;
+
+/// The source does not exist.
+pub const MMV_MISSING_SOURCE: c_int = 1;
+/// The destination already exists without being a source of the plan.
+pub const MMV_EXISTING_DESTINATION: c_int = 2;
+/// The destination lies inside of another destination that is not a directory.
+pub const MMV_NESTED_DESTINATION: c_int = 3;
+/// The source lies inside of another source that has entries left behind.
+pub const MMV_SPLIT_SOURCE: c_int = 4;
+/// The two destinations only differ in case.
+pub const MMV_CASE_COLLISION: c_int = 5;
+
+/// A plan along with the ordered list of its operations, which is built the
+/// first time it is asked for.  After that the plan can no longer be added to
+/// until it is cleared.  The issues found by the last validation are kept as
+/// their kind and the one or two paths they concern.
+pub struct MmvPlan {
+	plan: Plan,
+	ops: Option<Vec<(CString, CString)>>,
+	issues: Vec<(c_int, CString, Option<CString>)>,
+}
+
+unsafe fn path(s: *const c_char) -> Option<PathBuf> {
+	if s.is_null() {
+		return None;
+	}
+	Some(Path::new(OsStr::from_bytes(CStr::from_ptr(s).to_bytes())).to_owned())
+}
+
+fn cstring(p: &Path) -> CString {
+	/* Paths coming from C cannot contain NUL bytes */
+	CString::new(p.as_os_str().as_bytes()).unwrap()
+}
+
+/// Create a plan staging its files in the directory `dir`, or return null if
+/// `dir` is null.
+///
+/// # Safety
+///
+/// `dir` must be null or point to a NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn mmv_plan_new(dir: *const c_char) -> *mut MmvPlan {
+	match path(dir) {
+		Some(dir) => Box::into_raw(Box::new(MmvPlan {
+			plan: Plan::new(dir),
+			ops: None,
+			issues: Vec::new(),
+		})),
+		None => ptr::null_mut(),
+	}
+}
+
+/// Add the move of `src` to `dst` to the plan, returning one of the `MMV_*`
+/// status codes.
+///
+/// # Safety
+///
+/// `plan` must be null or have been returned by [`mmv_plan_new`] and not yet
+/// freed, and `src` and `dst` must be null or point to NUL-terminated strings.
+#[no_mangle]
+pub unsafe extern "C" fn mmv_plan_add(
+	plan: *mut MmvPlan,
+	src: *const c_char,
+	dst: *const c_char,
+) -> c_int {
+	let (Some(plan), Some(src), Some(dst)) = (plan.as_mut(), path(src), path(dst)) else {
+		return MMV_INVALID;
+	};
+	if plan.ops.is_some() {
+		return MMV_INVALID;
+	}
+	match plan.plan.add(src, dst) {
+		Ok(_) => MMV_OK,
+		Err(Error::DuplicateSource(_)) => MMV_DUPLICATE_SOURCE,
+		Err(Error::DuplicateDestination(_)) => MMV_DUPLICATE_DESTINATION,
+	}
+}
+
+/// The number of operations needed to execute the plan, which is twice the
+/// number of pairs in it, or 0 if `plan` is null.
+///
+/// # Safety
+///
+/// As for [`mmv_plan_add`].
+#[no_mangle]
+pub unsafe extern "C" fn mmv_plan_ops(plan: *mut MmvPlan) -> usize {
+	let Some(plan) = plan.as_mut() else {
+		return 0;
+	};
+	ops(plan).len()
+}
+
+/// Store the paths of the `i`th operation of the plan in `from` and `to`,
+/// returning [`MMV_OK`], or [`MMV_INVALID`] if there is no such operation.
+/// Performing every operation in order as a rename (or copy and removal)
+/// executes the plan.  The strings remain valid until the plan is freed.
+///
+/// # Safety
+///
+/// As for [`mmv_plan_add`], and `from` and `to` must be valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn mmv_plan_op(
+	plan: *mut MmvPlan,
+	i: usize,
+	from: *mut *const c_char,
+	to: *mut *const c_char,
+) -> c_int {
+	let Some(plan) = plan.as_mut() else {
+		return MMV_INVALID;
+	};
+	if from.is_null() || to.is_null() {
+		return MMV_INVALID;
+	}
+	match ops(plan).get(i) {
+		Some((f, t)) => {
+			*from = f.as_ptr();
+			*to = t.as_ptr();
+			MMV_OK
+		}
+		None => MMV_INVALID,
+	}
+}
+
+/// Check the plan against the files as they are now, returning the number of
+/// issues found, or 0 if `plan` is null.  The issues are read with
+/// [`mmv_plan_issue`] and replace those of any earlier validation.
+///
+/// # Safety
+///
+/// As for [`mmv_plan_add`].
+#[no_mangle]
+pub unsafe extern "C" fn mmv_plan_validate(plan: *mut MmvPlan) -> usize {
+	let Some(plan) = plan.as_mut() else {
+		return 0;
+	};
+	plan.issues = plan
+		.plan
+		.validate(&vfs::Std)
+		.into_iter()
+		.map(|i| match i {
+			Issue::MissingSource(p) => (MMV_MISSING_SOURCE, cstring(&p), None),
+			Issue::ExistingDestination(p) => (MMV_EXISTING_DESTINATION, cstring(&p), None),
+			Issue::NestedDestination { dst, outer } => {
+				(MMV_NESTED_DESTINATION, cstring(&dst), Some(cstring(&outer)))
+			}
+			Issue::SplitSource { src, outer } => {
+				(MMV_SPLIT_SOURCE, cstring(&src), Some(cstring(&outer)))
+			}
+			Issue::CaseCollision(a, b) => (MMV_CASE_COLLISION, cstring(&a), Some(cstring(&b))),
+		})
+		.collect();
+	plan.issues.len()
+}
+
+/// Store the `i`th issue found by [`mmv_plan_validate`] in `kind`, one of the
+/// `MMV_*` issue kinds, and `path`, the path it concerns, and in `other` the
+/// outer path or the other destination it concerns, or null if there is none.
+/// Returns [`MMV_OK`], or [`MMV_INVALID`] if there is no such issue.  The
+/// strings remain valid until the plan is validated again, cleared, or freed.
+///
+/// # Safety
+///
+/// As for [`mmv_plan_add`], and `kind`, `path`, and `other` must be valid for
+/// writes.
+#[no_mangle]
+pub unsafe extern "C" fn mmv_plan_issue(
+	plan: *mut MmvPlan,
+	i: usize,
+	kind: *mut c_int,
+	path: *mut *const c_char,
+	other: *mut *const c_char,
+) -> c_int {
+	let Some(plan) = plan.as_mut() else {
+		return MMV_INVALID;
+	};
+	if kind.is_null() || path.is_null() || other.is_null() {
+		return MMV_INVALID;
+	}
+	match plan.issues.get(i) {
+		Some((k, p, o)) => {
+			*kind = *k;
+			*path = p.as_ptr();
+			*other = o.as_ref().map_or(ptr::null(), |o| o.as_ptr());
+			MMV_OK
+		}
+		None => MMV_INVALID,
+	}
+}
+
+/// Remove every pair from the plan, which can then be added to again, keeping
+/// the memory allocated for them.  The strings of its operations and issues
+/// are freed.  Passing null does nothing.
+///
+/// # Safety
+///
+/// As for [`mmv_plan_free`].
+#[no_mangle]
+pub unsafe extern "C" fn mmv_plan_clear(plan: *mut MmvPlan) {
+	if let Some(plan) = plan.as_mut() {
+		plan.plan.clear();
+		plan.ops = None;
+		plan.issues.clear();
+	}
+}
+
+/// Free the plan.  Passing null does nothing.
+///
+/// # Safety
+///
+/// `plan` must be null or have been returned by [`mmv_plan_new`] and not yet
+/// freed.
+#[no_mangle]
+pub unsafe extern "C" fn mmv_plan_free(plan: *mut MmvPlan) {
+	if !plan.is_null() {
+		drop(Box::from_raw(plan));
+	}
+}
+
+fn ops(plan: &mut MmvPlan) -> &[(CString, CString)] {
+	plan.ops.get_or_insert_with(|| {
+		let ps = plan.plan.pairs();
+		let stage = ps.iter().map(|p| (cstring(&p.src), cstring(&p.stage)));
+		let unstage = ps
+			.iter()
+			.rev()
+			.map(|p| (cstring(&p.stage), cstring(&p.dst)));
+		stage.chain(unstage).collect()
+	})
+}
+
+#[cfg(test)]
+mod tests {
+	use std::{env, fs, process};
+
+	use super::*;
+
+	fn c(s: &str) -> CString {
+		CString::new(s).unwrap()
+	}
+
+	unsafe fn op(plan: *mut MmvPlan, i: usize) -> Option<(String, String)> {
+		let (mut from, mut to) = (ptr::null(), ptr::null());
+		match mmv_plan_op(plan, i, &mut from, &mut to) {
+			MMV_OK => Some((
+				CStr::from_ptr(from).to_str().unwrap().to_string(),
+				CStr::from_ptr(to).to_str().unwrap().to_string(),
+			)),
+			_ => None,
+		}
+	}
+
+	#[test]
+	fn plans_moves() {
+		unsafe {
+			let plan = mmv_plan_new(c("/stage").as_ptr());
+			assert!(!plan.is_null());
+			assert_eq!(
+				mmv_plan_add(plan, c("/a").as_ptr(), c("/b").as_ptr()),
+				MMV_OK
+			);
+			assert_eq!(
+				mmv_plan_add(plan, c("/a").as_ptr(), c("/c").as_ptr()),
+				MMV_DUPLICATE_SOURCE
+			);
+			assert_eq!(
+				mmv_plan_add(plan, c("/d").as_ptr(), c("/b").as_ptr()),
+				MMV_DUPLICATE_DESTINATION
+			);
+			assert_eq!(mmv_plan_ops(plan), 2);
+			assert_eq!(op(plan, 0), Some(("/a".into(), "/stage/0".into())));
+			assert_eq!(op(plan, 1), Some(("/stage/0".into(), "/b".into())));
+			assert_eq!(op(plan, 2), None);
+			assert_eq!(op(plan, usize::MAX), None);
+
+			/* Once the operations are out the plan is fixed until cleared */
+			assert_eq!(
+				mmv_plan_add(plan, c("/e").as_ptr(), c("/f").as_ptr()),
+				MMV_INVALID
+			);
+			mmv_plan_clear(plan);
+			assert_eq!(
+				mmv_plan_add(plan, c("/d").as_ptr(), c("/b").as_ptr()),
+				MMV_OK
+			);
+			assert_eq!(mmv_plan_ops(plan), 2);
+			assert_eq!(op(plan, 0), Some(("/d".into(), "/stage/0".into())));
+			mmv_plan_free(plan);
+		}
+	}
+
+	#[test]
+	fn refuses_null_pointers() {
+		unsafe {
+			let null = ptr::null_mut();
+			assert!(mmv_plan_new(ptr::null()).is_null());
+			assert_eq!(
+				mmv_plan_add(null, c("/a").as_ptr(), c("/b").as_ptr()),
+				MMV_INVALID
+			);
+			assert_eq!(mmv_plan_ops(null), 0);
+			assert_eq!(mmv_plan_validate(null), 0);
+			let (mut from, mut to, mut kind) = (ptr::null(), ptr::null(), 0);
+			assert_eq!(mmv_plan_op(null, 0, &mut from, &mut to), MMV_INVALID);
+			assert_eq!(
+				mmv_plan_issue(null, 0, &mut kind, &mut from, &mut to),
+				MMV_INVALID
+			);
+			mmv_plan_clear(null);
+			mmv_plan_free(null);
+
+			let plan = mmv_plan_new(c("/stage").as_ptr());
+			assert_eq!(
+				mmv_plan_add(plan, ptr::null(), c("/b").as_ptr()),
+				MMV_INVALID
+			);
+			assert_eq!(
+				mmv_plan_add(plan, c("/a").as_ptr(), ptr::null()),
+				MMV_INVALID
+			);
+			assert_eq!(
+				mmv_plan_add(plan, c("/a").as_ptr(), c("/b").as_ptr()),
+				MMV_OK
+			);
+			assert_eq!(mmv_plan_op(plan, 0, ptr::null_mut(), &mut to), MMV_INVALID);
+			assert_eq!(
+				mmv_plan_op(plan, 0, &mut from, ptr::null_mut()),
+				MMV_INVALID
+			);
+			mmv_plan_validate(plan);
+			assert_eq!(
+				mmv_plan_issue(plan, 0, ptr::null_mut(), &mut from, &mut to),
+				MMV_INVALID
+			);
+			mmv_plan_free(plan);
+		}
+	}
+
+	#[test]
+	fn validates() {
+		let dir = env::temp_dir().join(format!("mmv-capi-{}", process::id()));
+		fs::create_dir_all(&dir).unwrap();
+		fs::write(dir.join("a"), "").unwrap();
+		fs::write(dir.join("b"), "").unwrap();
+		let p = |n: &str| c(dir.join(n).to_str().unwrap());
+		unsafe {
+			let plan = mmv_plan_new(c("/stage").as_ptr());
+			mmv_plan_add(plan, p("a").as_ptr(), p("b").as_ptr());
+			mmv_plan_add(plan, p("x").as_ptr(), p("y").as_ptr());
+			assert_eq!(mmv_plan_validate(plan), 2);
+
+			let (mut path, mut other, mut kind) = (ptr::null(), ptr::null(), 0);
+			let mut issues = Vec::new();
+			for i in 0..2 {
+				assert_eq!(
+					mmv_plan_issue(plan, i, &mut kind, &mut path, &mut other),
+					MMV_OK
+				);
+				assert!(other.is_null());
+				issues.push((kind, CStr::from_ptr(path).to_owned()));
+			}
+			issues.sort();
+			assert_eq!(
+				issues,
+				[
+					(MMV_MISSING_SOURCE, p("x")),
+					(MMV_EXISTING_DESTINATION, p("b"))
+				]
+			);
+			assert_eq!(
+				mmv_plan_issue(plan, 2, &mut kind, &mut path, &mut other),
+				MMV_INVALID
+			);
+
+			mmv_plan_clear(plan);
+			assert_eq!(
+				mmv_plan_issue(plan, 0, &mut kind, &mut path, &mut other),
+				MMV_INVALID
+			);
+			mmv_plan_add(plan, p("a").as_ptr(), p("c").as_ptr());
+			assert_eq!(mmv_plan_validate(plan), 0);
+			mmv_plan_free(plan);
+		}
+		fs::remove_dir_all(dir).unwrap();
+	}
+}
@@ -0,0 +1,21 @@
+//! The planning and filesystem layer of mmv, for tools that want to move
+//! batches of files around without pulling in its command-line interface.
+//!
+//! A [`Plan`] pairs up source and destination paths and orders them so that
+//! every file can be moved by way of a staging directory without overwriting
+//! a file that has yet to be moved, which makes swaps and cycles safe.  The
+//! [`vfs::Filesystem`] trait is what the moves themselves are performed
+//! through, and what [`Plan::validate`] checks a plan against before it is
+//! executed.
+//!
+//! Everything exported from this crate follows semantic versioning.
+
+#[cfg(feature = "capi")]
+pub mod capi;
+pub mod plan;
+mod sha256;
+mod sorted;
+mod trie;
+pub mod vfs;
+
+pub use plan::{Backend, Change, Error, Issue, Pair, Phase, Plan, Wave};
@@ -0,0 +1,934 @@
+use std::{
+	cmp::Reverse,
+	collections::{hash_map::Entry, BinaryHeap, HashMap},
+	error, fmt, mem,
+	os::unix::ffi::OsStrExt,
+	path::{Path, PathBuf},
+};
+
+use crate::{sha256::Sha256, sorted::PairIndex, trie::PathSet, vfs::Filesystem};
+
+/// A source file, the destination it is to be moved to, and the staging file
+/// it passes through on the way.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Pair {
+	pub src: PathBuf,
+	pub stage: PathBuf,
+	pub dst: PathBuf,
+}
+
+/// Why a pair could not be added to a [`Plan`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Error {
+	/// The source is already part of the plan.
+	DuplicateSource(PathBuf),
+	/// The destination is already part of the plan.
+	DuplicateDestination(PathBuf),
+}
+
+impl fmt::Display for Error {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			Error::DuplicateSource(p) => {
+				write!(
+					f,
+					"Input file “{}” specified more than once",
+					p.to_string_lossy()
+				)
+			}
+			Error::DuplicateDestination(p) => {
+				write!(
+					f,
+					"Output file “{}” specified more than once",
+					p.to_string_lossy()
+				)
+			}
+		}
+	}
+}
+
+impl error::Error for Error {}
+
+/// A problem with a [`Plan`] found by [`Plan::validate`].  Which of these are
+/// worth refusing to execute a plan over is up to the caller.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Issue {
+	/// The source does not exist.
+	MissingSource(PathBuf),
+	/// The destination already exists without being a source of the plan,
+	/// so the file moved there replaces it.
+	ExistingDestination(PathBuf),
+	/// The destination lies inside of `outer`, another destination whose
+	/// source is not a directory.
+	NestedDestination { dst: PathBuf, outer: PathBuf },
+	/// The source lies inside of `outer`, another source, which has other
+	/// entries that stay behind in it.  Sources are staged after the sources
+	/// inside of them, so this works, but it splits the directory up.
+	SplitSource { src: PathBuf, outer: PathBuf },
+	/// The two destinations only differ in case, so they are the same file
+	/// on case-insensitive filesystems.
+	CaseCollision(PathBuf, PathBuf),
+}
+
+impl fmt::Display for Issue {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			Issue::MissingSource(p) => {
+				write!(f, "Input file “{}” does not exist", p.to_string_lossy())
+			}
+			Issue::ExistingDestination(p) => {
+				write!(f, "Output file “{}” already exists", p.to_string_lossy())
+			}
+			Issue::NestedDestination { dst, outer } => {
+				write!(
+					f,
+					"Output file “{}” is inside of the output file “{}”, which is not a directory",
+					dst.to_string_lossy(),
+					outer.to_string_lossy()
+				)
+			}
+			Issue::SplitSource { src, outer } => {
+				write!(
+					f,
+					"Input file “{}” is moved out of “{}”, which is moved as well",
+					src.to_string_lossy(),
+					outer.to_string_lossy()
+				)
+			}
+			Issue::CaseCollision(a, b) => {
+				write!(
+					f,
+					"Output files “{}” and “{}” only differ in case",
+					a.to_string_lossy(),
+					b.to_string_lossy()
+				)
+			}
+		}
+	}
+}
+
+/// A difference between two plans found by [`Plan::diff`], by source.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Change {
+	/// The source is only moved by the other plan.
+	Added { src: PathBuf, dst: PathBuf },
+	/// The source is only moved by this plan.
+	Removed { src: PathBuf, dst: PathBuf },
+	/// The source is moved to `old` by this plan and to `new` by the other.
+	Moved {
+		src: PathBuf,
+		old: PathBuf,
+		new: PathBuf,
+	},
+}
+
+/// Which half of the execution of a [`Plan`] a [`Wave`] belongs to.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Phase {
+	/// Sources are moved to their staging files.
+	Stage,
+	/// Staging files are moved to their destinations.
+	Unstage,
+}
+
+/// A group of moves of a [`Plan`] which do not depend on each other, and which
+/// may hence be performed in any order or all at once.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Wave<'a> {
+	pub phase: Phase,
+	pub pairs: Vec<&'a Pair>,
+}
+
+/// How a [`Plan`] keeps track of the paths that are part of it, which only
+/// affects how fast it is built and how much memory it takes.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Backend {
+	/// A tree of path components, which is quick to add single pairs to and
+	/// stores the directories shared by the paths only once.
+	Trie,
+	/// The pairs sorted by path, which finds the duplicates among a large
+	/// batch of pairs with a single sort.  Adding single pairs to it later
+	/// takes time linear in the size of the plan.
+	Sorted,
+}
+
+/// The number of pairs from which [`Plan::from_pairs`] switches to the
+/// [`Backend::Sorted`] backend.
+pub const SORTED_THRESHOLD: usize = 100_000;
+
+/// A batch of moves by way of a staging directory.
+///
+/// Executing a plan happens in two phases: first every source is moved to
+/// its staging file in the order given by [`Plan::pairs`], and then every
+/// staging file is moved to its destination in the reverse order.  As no
+/// destination is written to before every source has been moved out of the
+/// way, the destinations of a plan may freely overlap with its sources.
+#[derive(Clone, Debug)]
+pub struct Plan {
+	dir: PathBuf,
+	pairs: Vec<Pair>,
+	srcs: Set,
+	dsts: Set,
+}
+
+#[derive(Clone, Debug)]
+enum Set {
+	Trie(PathSet),
+	Sorted(PairIndex),
+}
+
+impl Set {
+	fn clear(&mut self) {
+		match self {
+			Set::Trie(s) => s.clear(),
+			Set::Sorted(s) => s.clear(),
+		}
+	}
+
+	fn contains(&self, pairs: &[Pair], p: &Path) -> bool {
+		match self {
+			Set::Trie(s) => s.contains(p),
+			Set::Sorted(s) => s.contains(pairs, p),
+		}
+	}
+
+	/* Add the path of the pair at position ‘i’ that the set is of, given the
+	same path as well for the trie */
+	fn insert(&mut self, pairs: &[Pair], i: usize, p: &Path) {
+		match self {
+			Set::Trie(s) => s.insert(p),
+			Set::Sorted(s) => s.insert(pairs, i),
+		};
+	}
+
+	/* Replace the contents of the set by the paths of ‘pairs’, on which there
+	are to be no duplicates */
+	fn rebuild(&mut self, pairs: &[Pair], key: fn(&Pair) -> &Path) {
+		match self {
+			Set::Trie(s) => {
+				s.clear();
+				for p in pairs.iter() {
+					s.insert(key(p));
+				}
+			}
+			Set::Sorted(s) => *s = PairIndex::build(pairs, key).0,
+		}
+	}
+
+	fn memory_usage(&self) -> usize {
+		match self {
+			Set::Trie(s) => s.memory_usage(),
+			Set::Sorted(s) => s.memory_usage(),
+		}
+	}
+}
+
+fn src_of(p: &Pair) -> &Path {
+	&p.src
+}
+
+fn dst_of(p: &Pair) -> &Path {
+	&p.dst
+}
+
+impl Plan {
+	/// Create an empty plan staging files in the directory `dir`, which must
+	/// be empty and exist for as long as the plan is being executed.
+	pub fn new(dir: impl Into<PathBuf>) -> Plan {
+		Plan::with_capacity(dir, 0)
+	}
+
+	/// Like [`Plan::new`], but with room for `n` pairs.
+	pub fn with_capacity(dir: impl Into<PathBuf>, n: usize) -> Plan {
+		Plan {
+			dir: dir.into(),
+			pairs: Vec::with_capacity(n),
+			srcs: Set::Trie(PathSet::with_capacity(n)),
+			dsts: Set::Trie(PathSet::with_capacity(n)),
+		}
+	}
+
+	/// Create a plan of the moves of `pairs`, which fails with the same error
+	/// as adding the pairs one by one with [`Plan::add`] would.  Plans of at
+	/// least [`SORTED_THRESHOLD`] pairs use the [`Backend::Sorted`] backend,
+	/// and smaller ones the [`Backend::Trie`] backend.
+	pub fn from_pairs(
+		dir: impl Into<PathBuf>,
+		pairs: Vec<(PathBuf, PathBuf)>,
+	) -> Result<Plan, Error> {
+		let backend = if pairs.len() >= SORTED_THRESHOLD {
+			Backend::Sorted
+		} else {
+			Backend::Trie
+		};
+		Plan::from_pairs_with(dir, pairs, backend)
+	}
+
+	/// Like [`Plan::from_pairs`], but with the backend given by `backend`.
+	pub fn from_pairs_with(
+		dir: impl Into<PathBuf>,
+		pairs: Vec<(PathBuf, PathBuf)>,
+		backend: Backend,
+	) -> Result<Plan, Error> {
+		let dir = dir.into();
+		if backend == Backend::Trie {
+			let mut plan = Plan::with_capacity(dir, pairs.len());
+			for (src, dst) in pairs {
+				plan.add(src, dst)?;
+			}
+			return Ok(plan);
+		}
+
+		let pairs = pairs
+			.into_iter()
+			.enumerate()
+			.map(|(i, (src, dst))| Pair {
+				src,
+				stage: dir.join(i.to_string()),
+				dst,
+			})
+			.collect::<Vec<_>>();
+		let (srcs, s) = PairIndex::build(&pairs, src_of);
+		let (dsts, d) = PairIndex::build(&pairs, dst_of);
+
+		/* add() would fail at the first pair with either path duplicated, and
+		check its source first */
+		match (s, d) {
+			(Some(i), Some(j)) if j < i => Err(Error::DuplicateDestination(pairs[j].dst.clone())),
+			(Some(i), _) => Err(Error::DuplicateSource(pairs[i].src.clone())),
+			(None, Some(j)) => Err(Error::DuplicateDestination(pairs[j].dst.clone())),
+			(None, None) => Ok(Plan {
+				dir,
+				pairs,
+				srcs: Set::Sorted(srcs),
+				dsts: Set::Sorted(dsts),
+			}),
+		}
+	}
+
+	/// Remove every pair from the plan, keeping the memory allocated for them
+	/// for the next batch of pairs, such as the next batch of files to appear
+	/// in a watched directory.  The staging directory stays the same, and must
+	/// be empty again before the plan is next executed.
+	pub fn clear(&mut self) {
+		self.pairs.clear();
+		self.srcs.clear();
+		self.dsts.clear();
+	}
+
+	/// The staging directory of the plan.
+	pub fn dir(&self) -> &Path {
+		&self.dir
+	}
+
+	/// Add the move of `src` to `dst`, both of which must be absolute and
+	/// normalized so that different spellings of the same path are caught.
+	pub fn add(&mut self, src: PathBuf, dst: PathBuf) -> Result<&Pair, Error> {
+		if self.srcs.contains(&self.pairs, &src) {
+			return Err(Error::DuplicateSource(src));
+		}
+		if self.dsts.contains(&self.pairs, &dst) {
+			return Err(Error::DuplicateDestination(dst));
+		}
+
+		/* Staging files are named by their position in the input, which unlike
+		a hash of the path cannot collide. */
+		let i = self.pairs.len();
+		let stage = self.dir.join(i.to_string());
+		self.pairs.push(Pair { src, stage, dst });
+		let p = &self.pairs[i];
+		self.srcs.insert(&self.pairs, i, &p.src);
+		self.dsts.insert(&self.pairs, i, &p.dst);
+		Ok(&self.pairs[i])
+	}
+
+	/// The number of pairs in the plan.
+	pub fn len(&self) -> usize {
+		self.pairs.len()
+	}
+
+	/// Whether the plan has no pairs.
+	pub fn is_empty(&self) -> bool {
+		self.pairs.is_empty()
+	}
+
+	/// Check the plan against the current state of `fs`, returning every
+	/// issue found in the order of the pairs they concern.  Nothing is
+	/// modified, and as the files may change before the plan is executed, an
+	/// empty list is no guarantee that executing it succeeds.
+	pub fn validate(&self, fs: &dyn Filesystem) -> Vec<Issue> {
+		let mut issues = Vec::new();
+		let mut dirs = HashMap::with_capacity(self.pairs.len());
+		for p in self.pairs.iter() {
+			let data = fs.symlink_metadata(&p.src);
+			if data.is_err() {
+				issues.push(Issue::MissingSource(p.src.clone()));
+			}
+			dirs.insert(p.dst.as_path(), data.is_ok_and(|d| d.is_dir));
+		}
+
+		for p in self.pairs.iter() {
+			if !self.srcs.contains(&self.pairs, &p.dst) && fs.symlink_metadata(&p.dst).is_ok() {
+				issues.push(Issue::ExistingDestination(p.dst.clone()));
+			}
+			if let Some(a) = p
+				.dst
+				.ancestors()
+				.skip(1)
+				.find(|a| dirs.get(a) == Some(&false))
+			{
+				issues.push(Issue::NestedDestination {
+					dst: p.dst.clone(),
+					outer: a.to_owned(),
+				});
+			}
+		}
+
+		/* Moving every entry out of a directory before moving the directory
+		itself is no split */
+		let mut split: HashMap<&Path, Vec<&Path>> = HashMap::new();
+		for p in self.pairs.iter() {
+			if let Some(a) = p
+				.src
+				.ancestors()
+				.skip(1)
+				.find(|a| self.srcs.contains(&self.pairs, a))
+			{
+				split.entry(a).or_default().push(&p.src);
+			}
+		}
+		for p in self.pairs.iter() {
+			let Some(srcs) = split.get(p.src.as_path()) else {
+				continue;
+			};
+			let moved = srcs.iter().filter(|s| s.parent() == Some(&p.src)).count();
+			if fs.read_dir(&p.src).is_ok_and(|es| es.len() == moved) {
+				continue;
+			}
+			issues.extend(srcs.iter().map(|s| Issue::SplitSource {
+				src: s.to_path_buf(),
+				outer: p.src.clone(),
+			}));
+		}
+
+		let mut folded: HashMap<String, &Path> = HashMap::with_capacity(self.pairs.len());
+		for p in self.pairs.iter() {
+			match folded.entry(p.dst.to_string_lossy().to_lowercase()) {
+				Entry::Occupied(e) => {
+					issues.push(Issue::CaseCollision(e.get().to_path_buf(), p.dst.clone()))
+				}
+				Entry::Vacant(e) => {
+					e.insert(&p.dst);
+				}
+			}
+		}
+		issues
+	}
+
+	/// Replace the pairs moving every entry of a directory to the same name in
+	/// another directory by a single pair moving the directory itself, which
+	/// renames a whole tree at once instead of each of the files in it.  A
+	/// directory is only moved as a whole if the directory its entries go to
+	/// is empty, which renaming replaces, if no other pair moves anything into
+	/// or out of either of them, and if `keep` does not return true for it.
+	///
+	/// Unlike moving its entries, moving a directory does not leave it behind
+	/// empty.  The pairs moving directories are returned along with the
+	/// sources of the pairs they replaced, for the caller to check or back up
+	/// those instead.  The digest of the plan changes accordingly.
+	pub fn collapse_dirs(
+		&mut self,
+		fs: &dyn Filesystem,
+		keep: impl Fn(&Path) -> bool,
+	) -> Vec<(Pair, Vec<PathBuf>)> {
+		/* The number of sources and destinations inside of each directory,
+		which tells whether the entries being moved are all there is */
+		let mut srcs_in: HashMap<PathBuf, usize> = HashMap::with_capacity(self.pairs.len());
+		let mut dsts_in: HashMap<PathBuf, usize> = HashMap::with_capacity(self.pairs.len());
+		for p in self.pairs.iter() {
+			for a in p.src.ancestors().skip(1) {
+				*srcs_in.entry(a.to_owned()).or_default() += 1;
+			}
+			for a in p.dst.ancestors().skip(1) {
+				*dsts_in.entry(a.to_owned()).or_default() += 1;
+			}
+		}
+
+		/* The original sources that each pair stands for */
+		let mut covered = self
+			.pairs
+			.iter()
+			.map(|p| vec![p.src.clone()])
+			.collect::<Vec<_>>();
+
+		/* Sorted sets look paths up in the pairs, which are taken apart below */
+		let orig = match self.srcs {
+			Set::Trie(_) => Vec::new(),
+			Set::Sorted(_) => self.pairs.clone(),
+		};
+		let mut pairs = mem::take(&mut self.pairs)
+			.into_iter()
+			.map(Some)
+			.collect::<Vec<_>>();
+		let mut children: HashMap<PathBuf, Vec<usize>> = HashMap::with_capacity(pairs.len());
+		for (i, p) in pairs.iter().flatten().enumerate() {
+			if let Some(d) = p.src.parent() {
+				children.entry(d.to_owned()).or_default().push(i);
+			}
+		}
+		let mut parents = children.keys().cloned().collect::<Vec<_>>();
+		parents.sort_by_key(|d| Reverse(d.components().count()));
+
+		let mut collapsed = vec![false; pairs.len()];
+		for dir in parents {
+			let Some(is) = children.get(&dir) else {
+				continue;
+			};
+			let ps = is
+				.iter()
+				.map(|&i| pairs[i].as_ref().unwrap())
+				.collect::<Vec<_>>();
+			let n = is.iter().map(|&i| covered[i].len()).sum::<usize>();
+			let Some(target) = ps[0].dst.parent() else {
+				continue;
+			};
+			let moved_whole = ps
+				.iter()
+				.all(|p| p.dst.parent() == Some(target) && p.dst.file_name() == p.src.file_name());
+			let count = |m: &HashMap<PathBuf, usize>, d: &Path| m.get(d).copied().unwrap_or(0);
+			if !moved_whole
+				|| keep(&dir)
+				|| target.starts_with(&dir)
+				|| self.srcs.contains(&orig, &dir)
+				|| self.dsts.contains(&orig, &dir)
+				|| self.srcs.contains(&orig, target)
+				|| self.dsts.contains(&orig, target)
+				|| count(&srcs_in, &dir) != n
+				|| count(&dsts_in, target) != n
+				|| count(&dsts_in, &dir) != 0
+				|| !fs
+					.symlink_metadata(&dir)
+					.is_ok_and(|m| m.is_dir && !m.is_symlink)
+				|| fs.read_dir(&dir).map_or(true, |es| es.len() != ps.len())
+			{
+				continue;
+			}
+			let empty = |d: &Path| {
+				fs.symlink_metadata(d)
+					.is_ok_and(|m| m.is_dir && !m.is_symlink)
+					&& fs.read_dir(d).is_ok_and(|es| es.is_empty())
+			};
+			if !empty(target) {
+				continue;
+			}
+
+			/* The collapsed pair takes the place and staging file of the first
+			of the pairs it replaces, so that the order of the plan is kept */
+			let is = children.remove(&dir).unwrap();
+			let first = is[0];
+			let stage = pairs[first].as_ref().unwrap().stage.clone();
+			let target = target.to_owned();
+			let mut srcs = Vec::with_capacity(n);
+			for &i in is.iter() {
+				pairs[i] = None;
+				collapsed[i] = false;
+				srcs.append(&mut covered[i]);
+			}
+			if let Some(d) = dir.parent() {
+				children.entry(d.to_owned()).or_default().push(first);
+			}
+			pairs[first] = Some(Pair {
+				src: dir,
+				stage,
+				dst: target,
+			});
+			covered[first] = srcs;
+			collapsed[first] = true;
+		}
+
+		self.pairs = Vec::with_capacity(pairs.len());
+		let mut dirs = Vec::new();
+		for ((p, c), srcs) in pairs.into_iter().zip(collapsed).zip(covered) {
+			let Some(p) = p else {
+				continue;
+			};
+			if c {
+				dirs.push((p.clone(), srcs));
+			}
+			self.pairs.push(p);
+		}
+		self.srcs.rebuild(&self.pairs, src_of);
+		self.dsts.rebuild(&self.pairs, dst_of);
+		dirs
+	}
+
+	/// A SHA-256 hash of the sources and destinations of the plan in the
+	/// order they were added in, which stays the same across runs, versions,
+	/// and machines.  The staging directory is left out, as it is usually a
+	/// new temporary directory every time, so a plan that was reviewed can be
+	/// checked to be the very one being executed.
+	pub fn digest(&self) -> [u8; 32] {
+		/* Every path is preceded by its length, so that no two different
+		lists of paths can be made to hash the same bytes */
+		let mut h = Sha256::new();
+		h.update(&(self.pairs.len() as u64).to_be_bytes());
+		for p in self.pairs.iter() {
+			for path in [&p.src, &p.dst] {
+				let bs = path.as_os_str().as_bytes();
+				h.update(&(bs.len() as u64).to_be_bytes());
+				h.update(bs);
+			}
+		}
+		h.finish()
+	}
+
+	/// How `other` differs from this plan, as the sources it moves somewhere
+	/// else, adds, or leaves out.  Changes are given in the order of the
+	/// pairs of this plan, followed by the sources only `other` moves in the
+	/// order of its pairs.
+	pub fn diff(&self, other: &Plan) -> Vec<Change> {
+		let theirs: HashMap<&Path, &Path> = other
+			.pairs
+			.iter()
+			.map(|p| (p.src.as_path(), p.dst.as_path()))
+			.collect();
+
+		let mut changes = Vec::new();
+		for p in self.pairs.iter() {
+			match theirs.get(p.src.as_path()) {
+				None => changes.push(Change::Removed {
+					src: p.src.clone(),
+					dst: p.dst.clone(),
+				}),
+				Some(&d) if d != p.dst => changes.push(Change::Moved {
+					src: p.src.clone(),
+					old: p.dst.clone(),
+					new: d.to_owned(),
+				}),
+				Some(_) => {}
+			}
+		}
+		for p in other
+			.pairs
+			.iter()
+			.filter(|p| !self.srcs.contains(&self.pairs, &p.src))
+		{
+			changes.push(Change::Added {
+				src: p.src.clone(),
+				dst: p.dst.clone(),
+			});
+		}
+		changes
+	}
+
+	/// The approximate number of bytes of memory held by the plan, for
+	/// keeping an eye on plans of millions of files.  The sets of sources and
+	/// destinations used to catch duplicates store the components the paths
+	/// share only once, so this mostly grows with the pairs themselves.
+	pub fn memory_usage(&self) -> usize {
+		let path = |p: &PathBuf| p.capacity();
+		mem::size_of::<Plan>()
+			+ path(&self.dir)
+			+ self.pairs.capacity() * mem::size_of::<Pair>()
+			+ self
+				.pairs
+				.iter()
+				.map(|p| path(&p.src) + path(&p.stage) + path(&p.dst))
+				.sum::<usize>()
+			+ self.srcs.memory_usage()
+			+ self.dsts.memory_usage()
+	}
+
+	/// The pairs of the plan in the order in which their sources are to be
+	/// staged.  Deeper paths come first, so that the contents of a directory
+	/// are moved out of it before the directory itself is.
+	pub fn pairs(&self) -> Vec<&Pair> {
+		let mut ps = self.pairs.iter().collect::<Vec<_>>();
+		ps.sort_by_key(|p| Reverse(p.src.components().count()));
+		ps
+	}
+
+	/// The moves of the plan as waves, each of which must be done with before
+	/// the next one is started.  The staging waves all come before the
+	/// unstaging ones.  Sources are staged after every source inside of them,
+	/// and destinations are unstaged after every destination they are inside
+	/// of; beyond that, the pairs of a wave are in the order they were added
+	/// in.  This allows executors to move files concurrently, with a barrier
+	/// between waves.
+	pub fn waves(&self) -> impl Iterator<Item = Wave<'_>> {
+		let srcs = self.levels(|p| &p.src, true);
+		let dsts = self.levels(|p| &p.dst, false);
+		let group = |phase, levels: Vec<usize>| {
+			let n = levels.iter().max().map_or(0, |n| n + 1);
+			let mut waves = vec![Vec::new(); n];
+			for (p, l) in self.pairs.iter().zip(levels) {
+				waves[l].push(p);
+			}
+			waves.into_iter().map(move |pairs| Wave { phase, pairs })
+		};
+		group(Phase::Stage, srcs).chain(group(Phase::Unstage, dsts))
+	}
+
+	/* The wave of each pair, by the paths of ‘path’ that are inside of one
+	another.  With ‘inner_first’ a path goes after all of those inside of it,
+	and otherwise after all of those it is inside of. */
+	fn levels(&self, path: impl Fn(&Pair) -> &PathBuf, inner_first: bool) -> Vec<usize> {
+		let index: HashMap<&Path, usize> = self
+			.pairs
+			.iter()
+			.enumerate()
+			.map(|(i, p)| (path(p).as_path(), i))
+			.collect();
+		let mut order = (0..self.pairs.len()).collect::<Vec<_>>();
+		order.sort_by_key(|&i| path(&self.pairs[i]).components().count());
+		if inner_first {
+			order.reverse();
+		}
+
+		let mut levels = vec![0; self.pairs.len()];
+		for i in order {
+			for a in path(&self.pairs[i]).ancestors().skip(1) {
+				if let Some(&j) = index.get(a) {
+					if inner_first {
+						levels[j] = levels[j].max(levels[i] + 1);
+					} else {
+						levels[i] = levels[i].max(levels[j] + 1);
+					}
+				}
+			}
+		}
+		levels
+	}
+
+	/// Like [`Plan::pairs`], but consuming the plan.
+	pub fn into_pairs(self) -> Vec<Pair> {
+		let mut ps = self.pairs;
+		ps.sort_by_key(|p| Reverse(p.src.components().count()));
+		ps
+	}
+
+	/// Like [`Plan::into_pairs`], but ordered by `key` instead of by depth,
+	/// with pairs of equal keys left in the order they were added in.  The
+	/// contents of a directory are still staged before the directory itself,
+	/// so every source inside of another source comes before it regardless of
+	/// its key.
+	pub fn into_pairs_by<K: Ord>(self, mut key: impl FnMut(&Pair) -> K) -> Vec<Pair> {
+		let mut order = (0..self.pairs.len()).collect::<Vec<_>>();
+		let keys = self.pairs.iter().map(&mut key).collect::<Vec<_>>();
+		order.sort_by(|&a, &b| keys[a].cmp(&keys[b]));
+		let mut rank = vec![0; order.len()];
+		for (r, &i) in order.iter().enumerate() {
+			rank[i] = r;
+		}
+
+		/* Topologically sort the pairs by way of the sources they are inside
+		of, always picking the best ranked pair that is free to go next */
+		let index: HashMap<&Path, usize> = self
+			.pairs
+			.iter()
+			.enumerate()
+			.map(|(i, p)| (p.src.as_path(), i))
+			.collect();
+		let mut outer = vec![Vec::new(); self.pairs.len()];
+		let mut blocked = vec![0; self.pairs.len()];
+		for (i, p) in self.pairs.iter().enumerate() {
+			for a in p.src.ancestors().skip(1) {
+				if let Some(&j) = index.get(a) {
+					outer[i].push(j);
+					blocked[j] += 1;
+				}
+			}
+		}
+		let mut ready = (0..self.pairs.len())
+			.filter(|&i| blocked[i] == 0)
+			.map(|i| Reverse(rank[i]))
+			.collect::<BinaryHeap<_>>();
+		let mut sorted = Vec::with_capacity(self.pairs.len());
+		while let Some(Reverse(r)) = ready.pop() {
+			let i = order[r];
+			sorted.push(i);
+			for &j in outer[i].iter() {
+				blocked[j] -= 1;
+				if blocked[j] == 0 {
+					ready.push(Reverse(rank[j]));
+				}
+			}
+		}
+
+		let mut pairs = self.pairs.into_iter().map(Some).collect::<Vec<_>>();
+		sorted
+			.into_iter()
+			.map(|i| pairs[i].take().unwrap())
+			.collect()
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use std::{collections::HashSet, path::PathBuf};
+
+	use super::*;
+
+	/* Staging files used to be named by a hash of the source path, so that two
+	sources hashing alike were staged to the same file and one of them lost.
+	Naming them by position makes the name independent of the path. */
+	#[test]
+	fn stages_by_position() {
+		let pairs = (0..1000)
+			.map(|i| {
+				(
+					PathBuf::from(format!("/src/{}/{i}", i % 7)),
+					PathBuf::from(format!("/dst/{i}")),
+				)
+			})
+			.collect::<Vec<_>>();
+		for backend in [Backend::Trie, Backend::Sorted] {
+			let plan = Plan::from_pairs_with("/stage", pairs.clone(), backend).unwrap();
+			let stages = plan
+				.pairs()
+				.iter()
+				.map(|p| &p.stage)
+				.collect::<HashSet<_>>();
+			assert_eq!(stages.len(), pairs.len());
+			for (i, (src, _)) in pairs.iter().enumerate() {
+				let p = plan.pairs.iter().find(|p| &p.src == src).unwrap();
+				assert_eq!(p.stage, Path::new("/stage").join(i.to_string()));
+			}
+		}
+	}
+
+	fn plan(pairs: &[(&str, &str)]) -> Plan {
+		let mut plan = Plan::new("/stage");
+		for (s, d) in pairs {
+			plan.add(PathBuf::from(s), PathBuf::from(d)).unwrap();
+		}
+		plan
+	}
+
+	/* The waves of ‘plan’ by their phase and the sources of their pairs */
+	fn waves(plan: &Plan) -> Vec<(Phase, Vec<&str>)> {
+		plan.waves()
+			.map(|w| {
+				let srcs = w.pairs.iter().map(|p| p.src.to_str().unwrap());
+				(w.phase, srcs.collect())
+			})
+			.collect()
+	}
+
+	#[test]
+	fn stages_nested_sources_first() {
+		let plan = plan(&[("/a", "/x"), ("/a/b", "/y"), ("/c", "/z"), ("/a/b/c", "/w")]);
+		assert_eq!(
+			waves(&plan),
+			[
+				(Phase::Stage, vec!["/c", "/a/b/c"]),
+				(Phase::Stage, vec!["/a/b"]),
+				(Phase::Stage, vec!["/a"]),
+				(Phase::Unstage, vec!["/a", "/a/b", "/c", "/a/b/c"]),
+			]
+		);
+	}
+
+	#[test]
+	fn unstages_outer_destinations_first() {
+		let plan = plan(&[("/r", "/d/e/f"), ("/p", "/d"), ("/q", "/d/e"), ("/s", "/g")]);
+		assert_eq!(
+			waves(&plan),
+			[
+				(Phase::Stage, vec!["/r", "/p", "/q", "/s"]),
+				(Phase::Unstage, vec!["/p", "/s"]),
+				(Phase::Unstage, vec!["/q"]),
+				(Phase::Unstage, vec!["/r"]),
+			]
+		);
+	}
+
+	/* Staging breaks cycles, so swapping files takes a single wave each way,
+	even for a directory swapped with a file inside of it */
+	#[test]
+	fn stages_cycles_whole() {
+		let swap = plan(&[("/a", "/b"), ("/b", "/a")]);
+		assert_eq!(
+			waves(&swap),
+			[
+				(Phase::Stage, vec!["/a", "/b"]),
+				(Phase::Unstage, vec!["/a", "/b"]),
+			]
+		);
+		let nested = plan(&[("/d", "/d/x"), ("/d/x", "/d")]);
+		assert_eq!(
+			waves(&nested),
+			[
+				(Phase::Stage, vec!["/d/x"]),
+				(Phase::Stage, vec!["/d"]),
+				(Phase::Unstage, vec!["/d/x"]),
+				(Phase::Unstage, vec!["/d"]),
+			]
+		);
+	}
+
+	#[test]
+	fn diffs_plans() {
+		let old = plan(&[("/a", "/x"), ("/b", "/y"), ("/c", "/z")]);
+		let new = plan(&[("/d", "/w"), ("/c", "/z"), ("/a", "/v"), ("/e", "/u")]);
+		let p = PathBuf::from;
+		assert_eq!(
+			old.diff(&new),
+			[
+				Change::Moved {
+					src: p("/a"),
+					old: p("/x"),
+					new: p("/v"),
+				},
+				Change::Removed {
+					src: p("/b"),
+					dst: p("/y"),
+				},
+				Change::Added {
+					src: p("/d"),
+					dst: p("/w"),
+				},
+				Change::Added {
+					src: p("/e"),
+					dst: p("/u"),
+				},
+			]
+		);
+		assert!(old.diff(&old).is_empty());
+		assert!(Plan::new("/stage")
+			.diff(&old)
+			.iter()
+			.all(|c| matches!(c, Change::Added { .. })));
+	}
+
+	#[test]
+	fn reuses_cleared_plans() {
+		let pairs = [("/a", "/b"), ("/b", "/a"), ("/d/e", "/d/f")];
+		for backend in [Backend::Trie, Backend::Sorted] {
+			let mut plan = Plan::from_pairs_with(
+				"/stage",
+				pairs
+					.iter()
+					.map(|&(s, d)| (PathBuf::from(s), PathBuf::from(d)))
+					.collect(),
+				backend,
+			)
+			.unwrap();
+			let full = plan.memory_usage();
+			plan.clear();
+			assert!(plan.pairs().is_empty());
+			assert!(plan.memory_usage() < full);
+			for (s, d) in pairs {
+				plan.add(PathBuf::from(s), PathBuf::from(d)).unwrap();
+			}
+			assert_eq!(plan.pairs().len(), pairs.len());
+			assert!(matches!(
+				plan.add(PathBuf::from("/a"), PathBuf::from("/c")),
+				Err(Error::DuplicateSource(_))
+			));
+		}
+	}
+}
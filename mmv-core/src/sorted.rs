@@ -0,0 +1,67 @@
+use std::{mem, os::unix::ffi::OsStrExt, path::Path};
+
+use crate::plan::Pair;
+
+/* A set of the sources or the destinations of the pairs of a plan, stored as
+the positions of those pairs sorted by the path.  Where PathSet chases a pointer
+for every component of every path, building this is one sort of the pairs and
+finding the duplicates among them one scan for neighbours that are equal, both
+of which walk memory in order.  That pays off for plans of millions of pairs,
+and as the paths themselves are not copied it takes a word per path too.  Paths
+are compared as strings rather than by component, which is only the same thing
+for the normalized paths that plans are made of. */
+#[derive(Clone, Debug)]
+pub(crate) struct PairIndex {
+	key: fn(&Pair) -> &Path,
+	order: Vec<usize>,
+}
+
+impl PairIndex {
+	/* Index ‘pairs’ by ‘key’, returning along with the index the position of
+	the first pair whose key is that of an earlier pair, if any */
+	pub fn build(pairs: &[Pair], key: fn(&Pair) -> &Path) -> (PairIndex, Option<usize>) {
+		let mut order = (0..pairs.len()).collect::<Vec<_>>();
+
+		/* The sort is stable, so of the pairs sharing a key the first is the
+		one that came first, and the second the one that add() would refuse */
+		let bytes = |i: usize| key(&pairs[i]).as_os_str().as_bytes();
+		order.sort_by(|&a, &b| bytes(a).cmp(bytes(b)));
+		let dup = order
+			.windows(2)
+			.filter(|w| bytes(w[0]) == bytes(w[1]))
+			.map(|w| w[1])
+			.min();
+		(PairIndex { key, order }, dup)
+	}
+
+	pub fn clear(&mut self) {
+		self.order.clear();
+	}
+
+	pub fn contains(&self, pairs: &[Pair], p: &Path) -> bool {
+		self.find(pairs, p).is_ok()
+	}
+
+	/* Add the pair at position ‘i’, returning whether its key was not part of
+	the set yet */
+	pub fn insert(&mut self, pairs: &[Pair], i: usize) -> bool {
+		match self.find(pairs, (self.key)(&pairs[i])) {
+			Ok(_) => false,
+			Err(j) => {
+				self.order.insert(j, i);
+				true
+			}
+		}
+	}
+
+	/* The number of bytes of memory allocated for the set */
+	pub fn memory_usage(&self) -> usize {
+		self.order.capacity() * mem::size_of::<usize>()
+	}
+
+	fn find(&self, pairs: &[Pair], p: &Path) -> Result<usize, usize> {
+		let p = p.as_os_str().as_bytes();
+		self.order
+			.binary_search_by(|&i| (self.key)(&pairs[i]).as_os_str().as_bytes().cmp(p))
+	}
+}
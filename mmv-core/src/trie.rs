@@ -0,0 +1,100 @@
+use std::{mem, os::unix::ffi::OsStrExt, path::Path};
+
+/* A set of paths stored as a tree of their components, so that the components
+shared by many paths, such as those of the directories a batch of files lives
+in, are only stored once.  Plans are made of paths that mostly differ in their
+last few components, which makes this far smaller than a set of whole paths.
+The names of all of the components live in a single buffer, and the children
+of each node are kept sorted by name for binary searching. */
+#[derive(Clone, Debug)]
+pub(crate) struct PathSet {
+	names: Vec<u8>,
+	nodes: Vec<Node>,
+}
+
+#[derive(Clone, Debug)]
+struct Node {
+	name: (usize, usize),
+	children: Vec<usize>,
+	member: bool,
+}
+
+impl PathSet {
+	pub fn with_capacity(n: usize) -> PathSet {
+		let mut nodes = Vec::with_capacity(n);
+		nodes.push(Node {
+			name: (0, 0),
+			children: Vec::new(),
+			member: false,
+		});
+		PathSet {
+			names: Vec::new(),
+			nodes,
+		}
+	}
+
+	/* Remove every path from the set, keeping the memory of the root node and
+	of the names for reuse */
+	pub fn clear(&mut self) {
+		self.names.clear();
+		self.nodes.truncate(1);
+		self.nodes[0].children.clear();
+	}
+
+	pub fn contains(&self, p: &Path) -> bool {
+		let mut n = 0;
+		for c in p.components() {
+			match self.child(n, c.as_os_str().as_bytes()) {
+				Ok(i) => n = self.nodes[n].children[i],
+				Err(_) => return false,
+			}
+		}
+		self.nodes[n].member
+	}
+
+	/* Add ‘p’ to the set, returning whether it was not part of it yet */
+	pub fn insert(&mut self, p: &Path) -> bool {
+		let mut n = 0;
+		for c in p.components() {
+			let name = c.as_os_str().as_bytes();
+			n = match self.child(n, name) {
+				Ok(i) => self.nodes[n].children[i],
+				Err(i) => {
+					let start = self.names.len();
+					self.names.extend_from_slice(name);
+					self.nodes.push(Node {
+						name: (start, name.len()),
+						children: Vec::new(),
+						member: false,
+					});
+					let m = self.nodes.len() - 1;
+					self.nodes[n].children.insert(i, m);
+					m
+				}
+			};
+		}
+		!mem::replace(&mut self.nodes[n].member, true)
+	}
+
+	/* The number of bytes of memory allocated for the set */
+	pub fn memory_usage(&self) -> usize {
+		self.names.capacity()
+			+ self.nodes.capacity() * mem::size_of::<Node>()
+			+ self
+				.nodes
+				.iter()
+				.map(|n| n.children.capacity() * mem::size_of::<usize>())
+				.sum::<usize>()
+	}
+
+	fn child(&self, n: usize, name: &[u8]) -> Result<usize, usize> {
+		self.nodes[n]
+			.children
+			.binary_search_by(|&c| self.name(c).cmp(name))
+	}
+
+	fn name(&self, n: usize) -> &[u8] {
+		let (start, len) = self.nodes[n].name;
+		&self.names[start..start + len]
+	}
+}
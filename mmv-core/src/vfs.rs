@@ -0,0 +1,736 @@
+use std::{
+	cell::Cell,
+	ffi::OsStr,
+	fs::{self, File},
+	io,
+	os::unix::{self, ffi::OsStrExt, fs::PermissionsExt},
+	path::{Path, PathBuf},
+	thread,
+	time::Duration,
+};
+
+/// The subset of file metadata the executor cares about.  This is our own type
+/// instead of fs::Metadata, which cannot be constructed outside of the standard
+/// library.
+pub struct Metadata {
+	pub is_dir: bool,
+	/// Whether the file is a symbolic link, which is only ever the case for
+	/// metadata from [`Filesystem::symlink_metadata`].
+	pub is_symlink: bool,
+	/// The permission bits of the file, including the setuid, setgid, and
+	/// sticky bits.
+	pub mode: u32,
+}
+
+impl From<fs::Metadata> for Metadata {
+	fn from(data: fs::Metadata) -> Self {
+		Metadata {
+			is_dir: data.is_dir(),
+			is_symlink: data.file_type().is_symlink(),
+			mode: data.permissions().mode() & 0o7777,
+		}
+	}
+}
+
+/// Every operation the executor performs on the files it moves, copies, links,
+/// or removes goes through this trait, so that a backend other than the local
+/// filesystem (an in-memory one for tests, a remote one, etc.) can be dropped
+/// in without touching the planning logic.
+pub trait Filesystem {
+	fn metadata(&self, path: &Path) -> io::Result<Metadata>;
+	fn symlink_metadata(&self, path: &Path) -> io::Result<Metadata>;
+	fn create_dir(&self, path: &Path) -> io::Result<()>;
+	fn create_dir_all(&self, path: &Path) -> io::Result<()>;
+	fn set_mode(&self, path: &Path, mode: u32) -> io::Result<()>;
+	fn read_dir(&self, path: &Path) -> io::Result<Vec<PathBuf>>;
+	fn read_link(&self, path: &Path) -> io::Result<PathBuf>;
+	fn copy(&self, from: &Path, to: &Path) -> io::Result<u64>;
+	fn rename(&self, from: &Path, to: &Path) -> io::Result<()>;
+	fn hard_link(&self, from: &Path, to: &Path) -> io::Result<()>;
+	fn symlink(&self, from: &Path, to: &Path) -> io::Result<()>;
+	fn remove_file(&self, path: &Path) -> io::Result<()>;
+	fn remove_dir(&self, path: &Path) -> io::Result<()>;
+	fn remove_dir_all(&self, path: &Path) -> io::Result<()>;
+
+	/// Whether [`Filesystem::rename`] can work at all, which decides whether
+	/// copies are written under a temporary name and renamed into place.
+	fn renames(&self) -> bool {
+		true
+	}
+}
+
+/// The local filesystem, as exposed by std::fs.
+pub struct Std;
+
+impl Filesystem for Std {
+	fn metadata(&self, path: &Path) -> io::Result<Metadata> {
+		fs::metadata(path).map(Metadata::from)
+	}
+
+	fn symlink_metadata(&self, path: &Path) -> io::Result<Metadata> {
+		fs::symlink_metadata(path).map(Metadata::from)
+	}
+
+	fn create_dir(&self, path: &Path) -> io::Result<()> {
+		fs::create_dir(path)
+	}
+
+	fn create_dir_all(&self, path: &Path) -> io::Result<()> {
+		fs::create_dir_all(path)
+	}
+
+	fn set_mode(&self, path: &Path, mode: u32) -> io::Result<()> {
+		fs::set_permissions(path, fs::Permissions::from_mode(mode))
+	}
+
+	fn read_dir(&self, path: &Path) -> io::Result<Vec<PathBuf>> {
+		fs::read_dir(path)?.map(|e| e.map(|e| e.path())).collect()
+	}
+
+	fn read_link(&self, path: &Path) -> io::Result<PathBuf> {
+		fs::read_link(path)
+	}
+
+	fn copy(&self, from: &Path, to: &Path) -> io::Result<u64> {
+		#[cfg(target_os = "linux")]
+		if let Some(n) = copy_anonymous(from, to)? {
+			return Ok(n);
+		}
+
+		/* Unlike fs::copy(), never leave a truncated file behind when running
+		out of space (or anything else) part way through. */
+		let mut r = File::open(from)?;
+		let perm = r.metadata()?.permissions();
+		let mut w = File::create(to)?;
+		let n = io::copy(&mut r, &mut w)
+			.and_then(|n| w.set_permissions(perm).map(|_| n))
+			.and_then(|n| copy_xattrs(&r, &w).map(|_| n))
+			.inspect_err(|_| {
+				let _ = fs::remove_file(to);
+			})?;
+		Ok(n)
+	}
+
+	fn rename(&self, from: &Path, to: &Path) -> io::Result<()> {
+		fs::rename(from, to)
+	}
+
+	fn hard_link(&self, from: &Path, to: &Path) -> io::Result<()> {
+		fs::hard_link(from, to)
+	}
+
+	fn symlink(&self, from: &Path, to: &Path) -> io::Result<()> {
+		unix::fs::symlink(from, to)
+	}
+
+	fn remove_file(&self, path: &Path) -> io::Result<()> {
+		fs::remove_file(path)
+	}
+
+	fn remove_dir(&self, path: &Path) -> io::Result<()> {
+		fs::remove_dir(path)
+	}
+
+	fn remove_dir_all(&self, path: &Path) -> io::Result<()> {
+		fs::remove_dir_all(path)
+	}
+}
+
+/* Copy ‘from’ into an anonymous file in the directory of ‘to’ and only link it
+into place once all of the data has been written, so that a partial copy is
+never visible at ‘to’.  Returns None when the filesystem has no support for
+anonymous files, in which case the caller should copy by other means. */
+#[cfg(target_os = "linux")]
+fn copy_anonymous(from: &Path, to: &Path) -> io::Result<Option<u64>> {
+	use rustix::{
+		fs::{linkat, openat, AtFlags, Mode, OFlags, CWD},
+		io::Errno,
+	};
+	use std::os::fd::AsRawFd;
+
+	let dir = match to.parent() {
+		Some(p) if !p.as_os_str().is_empty() => p,
+		_ => Path::new("."),
+	};
+	let mut r = File::open(from)?;
+	let perm = r.metadata()?.permissions();
+	let fd = match openat(
+		CWD,
+		dir,
+		OFlags::WRONLY | OFlags::TMPFILE | OFlags::CLOEXEC,
+		Mode::from_raw_mode(perm.mode() & 0o7777),
+	) {
+		Ok(fd) => fd,
+		Err(e) if e == Errno::OPNOTSUPP || e == Errno::ISDIR => return Ok(None),
+		Err(e) => return Err(e.into()),
+	};
+	let mut w = File::from(fd);
+	let n = io::copy(&mut r, &mut w)?;
+	w.set_permissions(perm)?;
+	copy_xattrs(&r, &w)?;
+
+	/* The file can only be linked to a name that does not yet exist, so to
+	replace an existing file it is linked beside it and renamed over it. */
+	let proc = format!("/proc/self/fd/{}", w.as_raw_fd());
+	let link = |p: &Path| linkat(CWD, proc.as_str(), CWD, p, AtFlags::SYMLINK_FOLLOW);
+	match link(to) {
+		Ok(()) => return Ok(Some(n)),
+		Err(e) if e == Errno::NOENT && fs::metadata(dir).is_ok() => return Ok(None),
+		Err(e) if e != Errno::EXIST => return Err(e.into()),
+		Err(_) => {}
+	}
+	link_over(link, dir, to).map(|_| Some(n))
+}
+
+/* Replace ‘to’ in ‘dir’ with the file that ‘link’ links to the path it is
+given, by linking it to the first free name of the form ‘.{name}.mmv-{i}’ and
+renaming that over ‘to’. */
+#[cfg(target_os = "linux")]
+fn link_over(
+	link: impl Fn(&Path) -> rustix::io::Result<()>,
+	dir: &Path,
+	to: &Path,
+) -> io::Result<()> {
+	use rustix::io::Errno;
+
+	let name = to.file_name().unwrap_or_default().to_string_lossy();
+	for i in 0.. {
+		let tmp = dir.join(format!(".{name}.mmv-{i}"));
+		match link(&tmp) {
+			Ok(()) => {
+				return fs::rename(&tmp, to).inspect_err(|_| {
+					let _ = fs::remove_file(&tmp);
+				})
+			}
+			Err(e) if e == Errno::EXIST => continue,
+			Err(e) => return Err(e.into()),
+		}
+	}
+	unreachable!()
+}
+
+/* Carry the extended attributes of ‘from’ over to ‘to’.  On macOS these hold
+Finder tags and info, resource forks, and the quarantine flag, without which
+copied files would behave differently in Finder than the originals.
+Attributes the destination filesystem or our privileges do not allow for are
+skipped, as cp(1) does. */
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+fn copy_xattrs(from: &File, to: &File) -> io::Result<()> {
+	use rustix::{
+		fs::{fgetxattr, flistxattr, fsetxattr, XattrFlags},
+		io::Errno,
+	};
+	let skip = |e: Errno| e == Errno::NOTSUP || e == Errno::PERM || e == Errno::ACCESS;
+
+	let mut names = Vec::new();
+	loop {
+		let n = match flistxattr(from, &mut []) {
+			Ok(n) => n,
+			Err(e) if skip(e) => return Ok(()),
+			Err(e) => return Err(e.into()),
+		};
+		names.resize(n, 0);
+		match flistxattr(from, &mut names) {
+			Ok(n) => {
+				names.truncate(n);
+				break;
+			}
+			/* Attributes were added in between */
+			Err(Errno::RANGE) => continue,
+			Err(e) => return Err(e.into()),
+		}
+	}
+
+	let mut value = Vec::new();
+	for name in names.split(|&c| c == 0).filter(|n| !n.is_empty()) {
+		/* The list is made of c_chars, which are unsigned on some systems */
+		#[allow(clippy::unnecessary_cast)]
+		let name = name.iter().map(|&c| c as u8).collect::<Vec<_>>();
+		let name = OsStr::from_bytes(&name);
+		let n = fgetxattr(from, name, &mut [])?;
+		value.resize(n, 0);
+		let n = fgetxattr(from, name, &mut value)?;
+		match fsetxattr(to, name, &value[..n], XattrFlags::empty()) {
+			Err(e) if skip(e) => {}
+			res => res?,
+		}
+	}
+	Ok(())
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos")))]
+fn copy_xattrs(_: &File, _: &File) -> io::Result<()> {
+	Ok(())
+}
+
+/// The local filesystem, but relying on nothing beyond opening, streaming, and
+/// removing whole files.  FUSE mounts of MTP devices and the like fail in
+/// exotic ways when renaming or setting permissions, so this backend never
+/// renames and never carries over any metadata.
+pub struct Conservative;
+
+impl Filesystem for Conservative {
+	fn metadata(&self, path: &Path) -> io::Result<Metadata> {
+		Std.metadata(path)
+	}
+
+	fn symlink_metadata(&self, path: &Path) -> io::Result<Metadata> {
+		Std.symlink_metadata(path)
+	}
+
+	fn create_dir(&self, path: &Path) -> io::Result<()> {
+		Std.create_dir(path)
+	}
+
+	fn create_dir_all(&self, path: &Path) -> io::Result<()> {
+		Std.create_dir_all(path)
+	}
+
+	fn set_mode(&self, _: &Path, _: u32) -> io::Result<()> {
+		Ok(())
+	}
+
+	fn read_dir(&self, path: &Path) -> io::Result<Vec<PathBuf>> {
+		Std.read_dir(path)
+	}
+
+	fn read_link(&self, path: &Path) -> io::Result<PathBuf> {
+		Std.read_link(path)
+	}
+
+	fn copy(&self, from: &Path, to: &Path) -> io::Result<u64> {
+		let mut r = File::open(from)?;
+		let mut w = File::create(to)?;
+		let n = io::copy(&mut r, &mut w).inspect_err(|_| {
+			let _ = fs::remove_file(to);
+		})?;
+		Ok(n)
+	}
+
+	fn rename(&self, _: &Path, _: &Path) -> io::Result<()> {
+		Err(io::ErrorKind::CrossesDevices.into())
+	}
+
+	fn hard_link(&self, from: &Path, to: &Path) -> io::Result<()> {
+		Std.hard_link(from, to)
+	}
+
+	fn symlink(&self, from: &Path, to: &Path) -> io::Result<()> {
+		Std.symlink(from, to)
+	}
+
+	fn remove_file(&self, path: &Path) -> io::Result<()> {
+		Std.remove_file(path)
+	}
+
+	fn remove_dir(&self, path: &Path) -> io::Result<()> {
+		Std.remove_dir(path)
+	}
+
+	fn remove_dir_all(&self, path: &Path) -> io::Result<()> {
+		Std.remove_dir_all(path)
+	}
+
+	fn renames(&self) -> bool {
+		false
+	}
+}
+
+/// Another filesystem, but retrying operations that fail with the errors that
+/// network filesystems such as NFS and SMB report for transient problems.  The
+/// delay between attempts doubles after every attempt.
+pub struct Retrying<'a> {
+	inner: &'a dyn Filesystem,
+	retries: u32,
+	delay: Duration,
+	retried: Cell<usize>,
+}
+
+impl<'a> Retrying<'a> {
+	pub fn new(inner: &'a dyn Filesystem, retries: u32, delay: Duration) -> Self {
+		Retrying {
+			inner,
+			retries,
+			delay,
+			retried: Cell::new(0),
+		}
+	}
+
+	/// The number of operations that had to be retried at least once.
+	pub fn retried(&self) -> usize {
+		self.retried.get()
+	}
+
+	fn retry<T>(&self, f: impl Fn(&dyn Filesystem) -> io::Result<T>) -> io::Result<T> {
+		self.retry_unless(f, |_| None)
+	}
+
+	/* An operation that failed may still have taken effect, as with a rename
+	whose reply got lost, and repeating it would then fail as the file is gone.
+	Before every retry ‘done’ is asked whether the last attempt did its job
+	after all, and its result is taken as that of the operation if any. */
+	fn retry_unless<T>(
+		&self,
+		f: impl Fn(&dyn Filesystem) -> io::Result<T>,
+		done: impl Fn(&dyn Filesystem) -> Option<T>,
+	) -> io::Result<T> {
+		let mut delay = self.delay;
+		for i in 0.. {
+			match f(self.inner) {
+				Err(e) if i < self.retries && is_transient(&e) => {
+					if i == 0 {
+						self.retried.set(self.retried.get() + 1);
+					}
+					thread::sleep(delay);
+					delay = delay.saturating_mul(2);
+					if let Some(v) = done(self.inner) {
+						return Ok(v);
+					}
+				}
+				res => return res,
+			}
+		}
+		unreachable!()
+	}
+}
+
+fn is_transient(e: &io::Error) -> bool {
+	use rustix::io::Errno;
+	matches!(
+		e.raw_os_error().map(Errno::from_raw_os_error),
+		Some(Errno::IO | Errno::STALE)
+	)
+}
+
+fn gone(fs: &dyn Filesystem, path: &Path) -> bool {
+	fs.symlink_metadata(path)
+		.is_err_and(|e| e.kind() == io::ErrorKind::NotFound)
+}
+
+/* Reading, copying over, and changing the mode of files can be repeated as
+often as need be, but creating, renaming, and removing them cannot */
+impl Filesystem for Retrying<'_> {
+	fn metadata(&self, path: &Path) -> io::Result<Metadata> {
+		self.retry(|fs| fs.metadata(path))
+	}
+
+	fn symlink_metadata(&self, path: &Path) -> io::Result<Metadata> {
+		self.retry(|fs| fs.symlink_metadata(path))
+	}
+
+	fn create_dir(&self, path: &Path) -> io::Result<()> {
+		self.retry_unless(
+			|fs| fs.create_dir(path),
+			|fs| {
+				fs.symlink_metadata(path)
+					.is_ok_and(|m| m.is_dir)
+					.then_some(())
+			},
+		)
+	}
+
+	fn create_dir_all(&self, path: &Path) -> io::Result<()> {
+		self.retry(|fs| fs.create_dir_all(path))
+	}
+
+	fn set_mode(&self, path: &Path, mode: u32) -> io::Result<()> {
+		self.retry(|fs| fs.set_mode(path, mode))
+	}
+
+	fn read_dir(&self, path: &Path) -> io::Result<Vec<PathBuf>> {
+		self.retry(|fs| fs.read_dir(path))
+	}
+
+	fn read_link(&self, path: &Path) -> io::Result<PathBuf> {
+		self.retry(|fs| fs.read_link(path))
+	}
+
+	fn copy(&self, from: &Path, to: &Path) -> io::Result<u64> {
+		self.retry(|fs| fs.copy(from, to))
+	}
+
+	fn rename(&self, from: &Path, to: &Path) -> io::Result<()> {
+		self.retry_unless(
+			|fs| fs.rename(from, to),
+			|fs| (gone(fs, from) && !gone(fs, to)).then_some(()),
+		)
+	}
+
+	fn hard_link(&self, from: &Path, to: &Path) -> io::Result<()> {
+		self.retry_unless(
+			|fs| fs.hard_link(from, to),
+			|fs| (!gone(fs, to)).then_some(()),
+		)
+	}
+
+	fn symlink(&self, from: &Path, to: &Path) -> io::Result<()> {
+		self.retry_unless(
+			|fs| fs.symlink(from, to),
+			|fs| fs.read_link(to).is_ok_and(|l| l == from).then_some(()),
+		)
+	}
+
+	fn remove_file(&self, path: &Path) -> io::Result<()> {
+		self.retry_unless(|fs| fs.remove_file(path), |fs| gone(fs, path).then_some(()))
+	}
+
+	fn remove_dir(&self, path: &Path) -> io::Result<()> {
+		self.retry_unless(|fs| fs.remove_dir(path), |fs| gone(fs, path).then_some(()))
+	}
+
+	fn remove_dir_all(&self, path: &Path) -> io::Result<()> {
+		self.retry_unless(
+			|fs| fs.remove_dir_all(path),
+			|fs| gone(fs, path).then_some(()),
+		)
+	}
+
+	fn renames(&self) -> bool {
+		self.inner.renames()
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use std::{cell::RefCell, collections::HashSet};
+
+	use super::*;
+
+	/* A filesystem of plain files failing the next ‘fails’ operations that
+	change it with EIO, which still take effect if it is ‘lossy’, as when only
+	the reply to a request was lost */
+	struct Flaky {
+		files: RefCell<HashSet<PathBuf>>,
+		fails: Cell<u32>,
+		lossy: bool,
+		calls: Cell<u32>,
+	}
+
+	impl Flaky {
+		fn new(files: &[&str], fails: u32, lossy: bool) -> Self {
+			Flaky {
+				files: RefCell::new(files.iter().map(PathBuf::from).collect()),
+				fails: Cell::new(fails),
+				lossy,
+				calls: Cell::new(0),
+			}
+		}
+
+		fn change(
+			&self,
+			f: impl FnOnce(&mut HashSet<PathBuf>) -> io::Result<()>,
+		) -> io::Result<()> {
+			self.calls.set(self.calls.get() + 1);
+			let mut files = self.files.borrow_mut();
+			if self.fails.get() == 0 {
+				return f(&mut files);
+			}
+			self.fails.set(self.fails.get() - 1);
+			if self.lossy {
+				f(&mut files)?;
+			}
+			Err(io::Error::from_raw_os_error(
+				rustix::io::Errno::IO.raw_os_error(),
+			))
+		}
+
+		fn files(&self) -> Vec<PathBuf> {
+			let mut fs = self.files.borrow().iter().cloned().collect::<Vec<_>>();
+			fs.sort();
+			fs
+		}
+	}
+
+	fn missing() -> io::Error {
+		io::ErrorKind::NotFound.into()
+	}
+
+	impl Filesystem for Flaky {
+		fn metadata(&self, path: &Path) -> io::Result<Metadata> {
+			self.symlink_metadata(path)
+		}
+
+		fn symlink_metadata(&self, path: &Path) -> io::Result<Metadata> {
+			match self.files.borrow().contains(path) {
+				true => Ok(Metadata {
+					is_dir: false,
+					is_symlink: false,
+					mode: 0o644,
+				}),
+				false => Err(missing()),
+			}
+		}
+
+		fn create_dir(&self, _: &Path) -> io::Result<()> {
+			unimplemented!()
+		}
+
+		fn create_dir_all(&self, _: &Path) -> io::Result<()> {
+			unimplemented!()
+		}
+
+		fn set_mode(&self, _: &Path, _: u32) -> io::Result<()> {
+			unimplemented!()
+		}
+
+		fn read_dir(&self, _: &Path) -> io::Result<Vec<PathBuf>> {
+			unimplemented!()
+		}
+
+		fn read_link(&self, _: &Path) -> io::Result<PathBuf> {
+			unimplemented!()
+		}
+
+		fn copy(&self, from: &Path, to: &Path) -> io::Result<u64> {
+			self.change(|fs| match fs.contains(from) {
+				true => {
+					fs.insert(to.to_owned());
+					Ok(())
+				}
+				false => Err(missing()),
+			})
+			.map(|_| 0)
+		}
+
+		fn rename(&self, from: &Path, to: &Path) -> io::Result<()> {
+			self.change(|fs| match fs.remove(from) {
+				true => {
+					fs.insert(to.to_owned());
+					Ok(())
+				}
+				false => Err(missing()),
+			})
+		}
+
+		fn hard_link(&self, _: &Path, _: &Path) -> io::Result<()> {
+			unimplemented!()
+		}
+
+		fn symlink(&self, _: &Path, _: &Path) -> io::Result<()> {
+			unimplemented!()
+		}
+
+		fn remove_file(&self, path: &Path) -> io::Result<()> {
+			self.change(|fs| fs.remove(path).then_some(()).ok_or_else(missing))
+		}
+
+		fn remove_dir(&self, _: &Path) -> io::Result<()> {
+			unimplemented!()
+		}
+
+		fn remove_dir_all(&self, _: &Path) -> io::Result<()> {
+			unimplemented!()
+		}
+	}
+
+	fn p(s: &str) -> &Path {
+		Path::new(s)
+	}
+
+	#[test]
+	fn retries_transient_errors() {
+		let fs = Flaky::new(&["/a"], 2, false);
+		let r = Retrying::new(&fs, 3, Duration::ZERO);
+		r.rename(p("/a"), p("/b")).unwrap();
+		assert_eq!(fs.files(), [p("/b")]);
+		assert_eq!((fs.calls.get(), r.retried()), (3, 1));
+		r.copy(p("/b"), p("/c")).unwrap();
+		assert_eq!(fs.files(), [p("/b"), p("/c")]);
+	}
+
+	#[test]
+	fn gives_up_after_retries() {
+		let fs = Flaky::new(&["/a"], 5, false);
+		let r = Retrying::new(&fs, 2, Duration::ZERO);
+		let e = r.rename(p("/a"), p("/b")).unwrap_err();
+		assert!(is_transient(&e));
+		assert_eq!(fs.calls.get(), 3);
+		assert_eq!(fs.files(), [p("/a")]);
+	}
+
+	#[test]
+	fn does_not_retry_other_errors() {
+		let fs = Flaky::new(&[], 0, false);
+		let r = Retrying::new(&fs, 3, Duration::ZERO);
+		let e = r.remove_file(p("/a")).unwrap_err();
+		assert_eq!(e.kind(), io::ErrorKind::NotFound);
+		assert_eq!((fs.calls.get(), r.retried()), (1, 0));
+	}
+
+	/* Repeating these would fail as the file is no longer there, turning a
+	success into a failure */
+	#[test]
+	fn does_not_repeat_what_took_effect() {
+		let fs = Flaky::new(&["/a", "/b"], 1, true);
+		let r = Retrying::new(&fs, 3, Duration::ZERO);
+		r.rename(p("/a"), p("/c")).unwrap();
+		assert_eq!(fs.calls.get(), 1);
+		fs.fails.set(1);
+		r.remove_file(p("/b")).unwrap();
+		assert_eq!(fs.calls.get(), 2);
+		assert_eq!(fs.files(), [p("/c")]);
+		assert_eq!(r.retried(), 2);
+	}
+
+	/* An empty directory of our own under ‘root’ */
+	#[cfg(target_os = "linux")]
+	fn scratch(root: &Path, name: &str) -> PathBuf {
+		let dir = root.join(format!("mmv-vfs-{}-{name}", std::process::id()));
+		let _ = fs::remove_dir_all(&dir);
+		fs::create_dir_all(&dir).unwrap();
+		dir
+	}
+
+	#[cfg(target_os = "linux")]
+	fn entries(dir: &Path) -> Vec<PathBuf> {
+		let mut es = Std.read_dir(dir).unwrap();
+		es.sort();
+		es
+	}
+
+	/* Copies from another filesystem are what copying is for, so the source
+	is on /dev/shm where there is one */
+	#[cfg(target_os = "linux")]
+	#[test]
+	fn copies_anonymously() {
+		let (shm, tmp) = (Path::new("/dev/shm"), std::env::temp_dir());
+		let src = scratch(if shm.is_dir() { shm } else { &tmp }, "src");
+		let dst = scratch(&tmp, "anonymous");
+		let (from, to) = (src.join("a"), dst.join("a"));
+		fs::write(&from, "data").unwrap();
+		Std.set_mode(&from, 0o751).unwrap();
+
+		/* Without support for anonymous files there is nothing to test */
+		if let Some(n) = copy_anonymous(&from, &to).unwrap() {
+			assert_eq!(n, 4);
+			assert_eq!(fs::read_to_string(&to).unwrap(), "data");
+			assert_eq!(Std.metadata(&to).unwrap().mode & 0o7777, 0o751);
+			assert_eq!(entries(&dst), [to.as_path()]);
+
+			fs::write(&from, "other data").unwrap();
+			Std.set_mode(&from, 0o600).unwrap();
+			assert_eq!(copy_anonymous(&from, &to).unwrap(), Some(10));
+			assert_eq!(fs::read_to_string(&to).unwrap(), "other data");
+			assert_eq!(Std.metadata(&to).unwrap().mode & 0o7777, 0o600);
+			assert_eq!(entries(&dst), [to]);
+		}
+		fs::remove_dir_all(src).unwrap();
+		fs::remove_dir_all(dst).unwrap();
+	}
+
+	#[cfg(target_os = "linux")]
+	#[test]
+	fn links_over_taken_names() {
+		let dir = scratch(&std::env::temp_dir(), "link-over");
+		let (new, to, taken) = (dir.join("new"), dir.join("a"), dir.join(".a.mmv-0"));
+		fs::write(&new, "new").unwrap();
+		fs::write(&to, "old").unwrap();
+		fs::write(&taken, "taken").unwrap();
+
+		link_over(|p| rustix::fs::link(&new, p), &dir, &to).unwrap();
+		assert_eq!(fs::read_to_string(&to).unwrap(), "new");
+		assert_eq!(fs::read_to_string(&taken).unwrap(), "taken");
+		assert_eq!(entries(&dir), [taken, to, new]);
+		fs::remove_dir_all(dir).unwrap();
+	}
+}
@@ -0,0 +1,70 @@
+use std::{
+	fs::{self, File},
+	io::{self, Read},
+	os::unix::ffi::OsStrExt,
+	path::Path,
+};
+
+/* What a file looked like, so that it can be compared to what ended up at its
+destination.  Directories are described by the names, sizes, and contents of
+everything in them.  Symbolic links inside them count by name only, as
+--fix-symlinks may well change where they point.  The hash is FNV-1a, which
+is no use against anyone tampering with files but plenty to notice a copy gone
+wrong. */
+#[derive(PartialEq)]
+pub struct Digest {
+	pub size: u64,
+	pub hash: Option<u64>,
+}
+
+const FNV_OFFSET: u64 = 0xCBF29CE484222325;
+const FNV_PRIME: u64 = 0x100000001B3;
+
+pub fn digest(path: &Path, contents: bool, follow: bool) -> io::Result<Digest> {
+	let mut d = Digest {
+		size: 0,
+		hash: contents.then_some(FNV_OFFSET),
+	};
+	walk(path, follow, &mut d)?;
+	Ok(d)
+}
+
+fn walk(path: &Path, follow: bool, d: &mut Digest) -> io::Result<()> {
+	let md = if follow {
+		fs::metadata(path)?
+	} else {
+		fs::symlink_metadata(path)?
+	};
+
+	if md.is_dir() {
+		let mut names = fs::read_dir(path)?
+			.map(|e| e.map(|e| e.file_name()))
+			.collect::<io::Result<Vec<_>>>()?;
+		names.sort();
+		for name in names {
+			mix(d, name.as_bytes());
+			walk(&path.join(name), false, d)?;
+		}
+	} else if !md.is_symlink() {
+		d.size += md.len();
+		if d.hash.is_some() {
+			let mut f = File::open(path)?;
+			let mut buf = [0; 8192];
+			loop {
+				match f.read(&mut buf)? {
+					0 => break,
+					n => mix(d, &buf[..n]),
+				}
+			}
+		}
+	}
+	Ok(())
+}
+
+fn mix(d: &mut Digest, bs: &[u8]) {
+	if let Some(h) = d.hash.as_mut() {
+		for &b in bs {
+			*h = (*h ^ b as u64).wrapping_mul(FNV_PRIME);
+		}
+	}
+}
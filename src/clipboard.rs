@@ -0,0 +1,97 @@
+use std::{
+	env,
+	io::{self, Write},
+	process::{Command, Stdio},
+};
+
+/* The system clipboard is only reachable through the display server, which
+each desktop has tools of its own for talking to.  These are tried in order,
+skipping the Wayland and X11 ones without a display of their kind to talk to,
+as well as any that are not installed. */
+const PASTE: &[(&str, &[&str])] = &[
+	("wl-paste", &["--no-newline"]),
+	("xclip", &["-selection", "clipboard", "-out"]),
+	("xsel", &["--clipboard", "--output"]),
+	("pbpaste", &[]),
+];
+const COPY: &[(&str, &[&str])] = &[
+	("wl-copy", &[]),
+	("xclip", &["-selection", "clipboard", "-in"]),
+	("xsel", &["--clipboard", "--input"]),
+	("pbcopy", &[]),
+];
+
+/* The contents of the clipboard, for --from-clipboard */
+pub fn paste() -> io::Result<Vec<u8>> {
+	for (cmd, args) in PASTE.iter().filter(|(c, _)| usable(c)) {
+		let out = match Command::new(cmd)
+			.args(*args)
+			.stdin(Stdio::null())
+			.stderr(Stdio::piped())
+			.output()
+		{
+			Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+			r => r.map_err(|e| io::Error::new(e.kind(), format!("{cmd}: {e}")))?,
+		};
+		if !out.status.success() {
+			return Err(failed(cmd, out.status, &out.stderr));
+		}
+		return Ok(out.stdout);
+	}
+	Err(none())
+}
+
+/* Replace the contents of the clipboard by ‘data’, for --to-clipboard.  The
+copying tools on X11 stay around in the background to serve the clipboard to
+whoever asks for it, so they are not waited on for longer than it takes them
+to fork. */
+pub fn copy(data: &[u8]) -> io::Result<()> {
+	for (cmd, args) in COPY.iter().filter(|(c, _)| usable(c)) {
+		let mut child = match Command::new(cmd)
+			.args(*args)
+			.stdin(Stdio::piped())
+			.stdout(Stdio::null())
+			.stderr(Stdio::null())
+			.spawn()
+		{
+			Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+			r => r.map_err(|e| io::Error::new(e.kind(), format!("{cmd}: {e}")))?,
+		};
+		child
+			.stdin
+			.take()
+			.unwrap()
+			.write_all(data)
+			.map_err(|e| io::Error::new(e.kind(), format!("{cmd}: {e}")))?;
+		let status = child.wait()?;
+		if !status.success() {
+			return Err(failed(cmd, status, &[]));
+		}
+		return Ok(());
+	}
+	Err(none())
+}
+
+fn usable(cmd: &str) -> bool {
+	let set = |v| env::var_os(v).is_some_and(|v| !v.is_empty());
+	match cmd {
+		"wl-paste" | "wl-copy" => set("WAYLAND_DISPLAY"),
+		"xclip" | "xsel" => set("DISPLAY"),
+		_ => cfg!(target_os = "macos"),
+	}
+}
+
+fn failed(cmd: &str, status: std::process::ExitStatus, stderr: &[u8]) -> io::Error {
+	let msg = String::from_utf8_lossy(stderr);
+	match msg.trim_end() {
+		"" => io::Error::other(format!("{cmd} failed with {status}")),
+		m => io::Error::other(format!("{cmd} failed with {status}: {m}")),
+	}
+}
+
+fn none() -> io::Error {
+	io::Error::new(
+		io::ErrorKind::NotFound,
+		"no clipboard found; install wl-clipboard, xclip, or xsel",
+	)
+}
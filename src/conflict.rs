@@ -0,0 +1,101 @@
+use std::{
+	fmt::Write,
+	fs::{self, Metadata},
+	path::Path,
+	time::UNIX_EPOCH,
+};
+
+use crate::nest;
+
+/* Files larger than this are not diffed, and at most this many lines of the
+diff are shown */
+const MAX_TEXT: u64 = 64 * 1024;
+const MAX_LINES: usize = 10;
+
+/* A comparison of the input file ‘src’ with the existing output file ‘dst’ it
+is about to replace, for --interactive: their sizes and modification times,
+and for text files the lines that differ once the lines the two files start
+and end with alike are taken off.  Lines removed from ‘dst’ are marked with a
+‘-’ and lines added from ‘src’ with a ‘+’. */
+pub fn compare(src: &Path, dst: &Path) -> String {
+	let mut out = String::new();
+	let (ms, md) = (fs::symlink_metadata(src), fs::symlink_metadata(dst));
+	for (label, p, m) in [("input", src, &ms), ("output", dst, &md)] {
+		let desc = match m {
+			Ok(m) => describe(m),
+			Err(e) => e.to_string(),
+		};
+		let _ = writeln!(out, "  {label:<6}  {}: {desc}", p.to_string_lossy());
+	}
+	if let (Ok(ms), Ok(md)) = (ms, md) {
+		if let Some(diff) = diff(src, &ms, dst, &md) {
+			out.push_str(&diff);
+		}
+	}
+	out
+}
+
+fn describe(m: &Metadata) -> String {
+	let kind = match m {
+		m if m.is_dir() => "directory",
+		m if m.is_symlink() => "symbolic link",
+		_ => "file",
+	};
+	let mut s = format!("{kind}, {} bytes, modified ", m.len());
+	match m
+		.modified()
+		.ok()
+		.and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+	{
+		Some(d) => nest::strftime(&mut s, "%Y-%m-%d %H:%M:%S UTC", d.as_secs() as i64),
+		None => s.push_str("at an unknown time"),
+	}
+	s
+}
+
+fn diff(src: &Path, ms: &Metadata, dst: &Path, md: &Metadata) -> Option<String> {
+	if !ms.is_file() || !md.is_file() || ms.len() > MAX_TEXT || md.len() > MAX_TEXT {
+		return None;
+	}
+	let text = |p| {
+		let bs = fs::read(p).ok()?;
+		String::from_utf8(bs).ok().filter(|s| !s.contains('\0'))
+	};
+	let (new, old) = (text(src)?, text(dst)?);
+	if new == old {
+		return Some("  the contents are identical\n".to_string());
+	}
+	let (new, old) = (
+		new.lines().collect::<Vec<_>>(),
+		old.lines().collect::<Vec<_>>(),
+	);
+	let pre = new
+		.iter()
+		.zip(old.iter())
+		.take_while(|(a, b)| a == b)
+		.count();
+	let suf = new[pre..]
+		.iter()
+		.rev()
+		.zip(old[pre..].iter().rev())
+		.take_while(|(a, b)| a == b)
+		.count();
+	let lines = old[pre..old.len() - suf]
+		.iter()
+		.map(|l| format!("  -{l}"))
+		.chain(new[pre..new.len() - suf].iter().map(|l| format!("  +{l}")))
+		.collect::<Vec<_>>();
+	if lines.is_empty() {
+		return Some("  the contents only differ in the final newline\n".to_string());
+	}
+
+	let mut s = format!("  @@ line {} @@\n", pre + 1);
+	for l in lines.iter().take(MAX_LINES) {
+		s.push_str(l);
+		s.push('\n');
+	}
+	if lines.len() > MAX_LINES {
+		let _ = writeln!(s, "  … and {} more line(s)", lines.len() - MAX_LINES);
+	}
+	Some(s)
+}
@@ -0,0 +1,98 @@
+use std::{io, path::Path};
+
+use rustix::fs::{utimensat, AtFlags, Timespec, Timestamps, CWD};
+
+/* Metadata to give to a file once it is moved, read with --metadata from the
+tab-separated ‘key=value’ fields following its output file:
+
+	mode=644                   the mode of the file, in octal
+	mtime=2024-01-01           the access and modification times of the file,
+	mtime=2024-01-01T12:30:00  as a date and optionally a time of day in UTC,
+	mtime=@1704067200          or as seconds since the epoch */
+#[derive(Default)]
+pub struct Directives {
+	pub mode: Option<u32>,
+	pub mtime: Option<i64>,
+}
+
+impl Directives {
+	pub fn is_empty(&self) -> bool {
+		self.mode.is_none() && self.mtime.is_none()
+	}
+}
+
+/* Split the directives off of the output file ‘line’ */
+pub fn split(line: &str) -> Result<(&str, Directives), String> {
+	let mut fields = line.split('\t');
+	let dst = fields.next().unwrap_or_default();
+	let mut ds = Directives::default();
+	for f in fields {
+		let bad = || format!("invalid directive ‘{f}’");
+		match f.split_once('=').ok_or_else(bad)? {
+			("mode", v) => ds.mode = Some(crate::parse_mode(v).map_err(|_| bad())?),
+			("mtime", v) => ds.mtime = Some(parse_time(v).ok_or_else(bad)?),
+			(k, _) => return Err(format!("unknown directive ‘{k}’")),
+		}
+	}
+	Ok((dst, ds))
+}
+
+pub fn apply(path: &Path, ds: &Directives) -> io::Result<()> {
+	if let Some(mode) = ds.mode {
+		rustix::fs::chmod(path, rustix::fs::Mode::from_raw_mode(mode))?;
+	}
+	if let Some(t) = ds.mtime {
+		let t = Timespec {
+			tv_sec: t,
+			tv_nsec: 0,
+		};
+		let ts = Timestamps {
+			last_access: t,
+			last_modification: t,
+		};
+		utimensat(CWD, path, &ts, AtFlags::empty())?;
+	}
+	Ok(())
+}
+
+fn parse_time(s: &str) -> Option<i64> {
+	if let Some(n) = s.strip_prefix('@') {
+		return n.parse().ok();
+	}
+	let (date, time) = s.split_once('T').unwrap_or((s, "00:00:00"));
+	let num = |s: &str| {
+		s.parse::<i64>()
+			.ok()
+			.filter(|_| s.chars().all(|c| c.is_ascii_digit()))
+	};
+
+	let mut it = date.splitn(3, '-');
+	let (y, m, d) = (num(it.next()?)?, num(it.next()?)?, num(it.next()?)?);
+	let mut it = time.splitn(3, ':');
+	let (hh, mm) = (num(it.next()?)?, num(it.next()?)?);
+	let ss = it.next().map_or(Some(0), num)?;
+	if !(1..=12).contains(&m) || d < 1 || d > days_in_month(y, m) || hh > 23 || mm > 59 || ss > 60 {
+		return None;
+	}
+	Some(days_from_civil(y, m, d) * 86400 + hh * 3600 + mm * 60 + ss)
+}
+
+fn days_in_month(y: i64, m: i64) -> i64 {
+	match m {
+		2 if y % 4 == 0 && (y % 100 != 0 || y % 400 == 0) => 29,
+		2 => 28,
+		4 | 6 | 9 | 11 => 30,
+		_ => 31,
+	}
+}
+
+/* The number of days from 1970-01-01 to the given date of the proleptic
+Gregorian calendar, after Howard Hinnant’s algorithm of the same name */
+pub fn days_from_civil(y: i64, m: i64, d: i64) -> i64 {
+	let y = if m <= 2 { y - 1 } else { y };
+	let era = y.div_euclid(400);
+	let yoe = y - era * 400;
+	let doy = (153 * (m + if m > 2 { -3 } else { 9 }) + 2) / 5 + d - 1;
+	let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+	era * 146097 + doe - 719468
+}
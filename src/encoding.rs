@@ -0,0 +1,325 @@
+use std::{fmt::Write, iter::Peekable, str::Chars};
+
+use crate::report;
+
+/* The ways of escaping filenames so that each one fits on a single line.  The
+first version only escapes what is needed to get rid of newlines, which leaves
+the other control characters (and with them, terminal escape sequences) as
+they are.  The second one escapes every control character:
+
+	\\      a backslash
+	\n      a newline
+	\t      a tab
+	\r      a carriage return
+	\xHH    any other byte below 0x20, or 0x7F
+
+Any other backslash in the output of the command is an error.
+
+The remaining ones are the formats of other tools, so that those tools can be
+used as the command as they are:
+
+	ls-quote    a word quoted like ‘ls --quoting-style=shell-escape’ does
+	uri         a percent-encoded path, as a ‘file://’ URI if it is absolute
+	json-lines  a JSON string */
+#[derive(Clone, Copy, PartialEq)]
+pub enum Encoding {
+	V1,
+	V2,
+	LsQuote,
+	Uri,
+	JsonLines,
+}
+
+pub fn parse(s: &str) -> Result<Encoding, &'static str> {
+	match s {
+		"1" => Ok(Encoding::V1),
+		"2" => Ok(Encoding::V2),
+		_ => Err("expected one of ‘1’ or ‘2’"),
+	}
+}
+
+pub fn parse_format(s: &str) -> Result<Encoding, &'static str> {
+	match s {
+		"ls-quote" => Ok(Encoding::LsQuote),
+		"uri" => Ok(Encoding::Uri),
+		"json-lines" => Ok(Encoding::JsonLines),
+		_ => Err("expected one of ‘ls-quote’, ‘uri’, or ‘json-lines’"),
+	}
+}
+
+/* The name of the encoding, as given to the command in $MMV_ENCODED */
+pub fn name(enc: Encoding) -> &'static str {
+	match enc {
+		Encoding::V1 => "1",
+		Encoding::V2 => "2",
+		Encoding::LsQuote => "ls-quote",
+		Encoding::Uri => "uri",
+		Encoding::JsonLines => "json-lines",
+	}
+}
+
+pub fn encode(enc: Encoding, s: &str) -> String {
+	match enc {
+		Encoding::LsQuote => return ls_quote(s),
+		Encoding::Uri => return uri(s),
+		Encoding::JsonLines => return report::string(s),
+		Encoding::V1 | Encoding::V2 => {}
+	}
+	let mut out = String::with_capacity(s.len());
+	for c in s.chars() {
+		match (enc, c) {
+			(_, '\\') => out.push_str("\\\\"),
+			(_, '\n') => out.push_str("\\n"),
+			(Encoding::V1, c) => out.push(c),
+			(Encoding::V2, '\t') => out.push_str("\\t"),
+			(Encoding::V2, '\r') => out.push_str("\\r"),
+			(Encoding::V2, c) if c.is_ascii_control() => {
+				write!(out, "\\x{:02X}", c as u32).unwrap();
+			}
+			(_, c) => out.push(c),
+		}
+	}
+	out
+}
+
+pub fn decode(enc: Encoding, s: &str) -> Result<String, String> {
+	match enc {
+		Encoding::LsQuote => return ls_unquote(s),
+		Encoding::Uri => return uri_decode(s),
+		Encoding::JsonLines => return json_string(s),
+		Encoding::V1 | Encoding::V2 => {}
+	}
+	let mut out = String::with_capacity(s.len());
+	let mut it = s.chars();
+	while let Some(c) = it.next() {
+		if c != '\\' {
+			out.push(c);
+			continue;
+		}
+		match (enc, it.next()) {
+			(_, Some('\\')) => out.push('\\'),
+			(_, Some('n')) => out.push('\n'),
+			(Encoding::V2, Some('t')) => out.push('\t'),
+			(Encoding::V2, Some('r')) => out.push('\r'),
+			(Encoding::V2, Some('x')) => out.push(hex(&mut it)?),
+			(_, Some(c)) => return Err(format!("invalid escape sequence ‘\\{c}’")),
+			/* The first version always ignored a trailing backslash */
+			(Encoding::V1, None) => {}
+			(_, None) => return Err("trailing backslash".to_string()),
+		}
+	}
+	Ok(out)
+}
+
+fn hex(it: &mut Chars) -> Result<char, String> {
+	let ds = it.by_ref().take(2).collect::<String>();
+	match u8::from_str_radix(&ds, 16) {
+		Ok(b)
+			if ds.len() == 2
+				&& ds.chars().all(|c| c.is_ascii_hexdigit())
+				&& b.is_ascii_control() =>
+		{
+			Ok(b as char)
+		}
+		_ => Err(format!("invalid escape sequence ‘\\x{ds}’")),
+	}
+}
+
+/* Quote the way GNU ls(1) does: names with nothing special in them are left as
+they are, others are single-quoted, and control characters are written in the
+‘$'…'’ form of ksh(1) and bash(1). */
+fn ls_quote(s: &str) -> String {
+	let safe = |c: char| !c.is_ascii() || c.is_ascii_alphanumeric() || "%+,-./:=@_^".contains(c);
+	if !s.is_empty() && s.chars().all(safe) {
+		return s.to_string();
+	}
+
+	let mut out = String::from("'");
+	for c in s.chars() {
+		match c {
+			'\'' => out.push_str("'\\''"),
+			c if c.is_ascii_control() => {
+				out.push_str("'$'");
+				match c {
+					'\t' => out.push_str("\\t"),
+					'\n' => out.push_str("\\n"),
+					'\r' => out.push_str("\\r"),
+					c => write!(out, "\\{:03o}", c as u32).unwrap(),
+				}
+				out.push('\'');
+				out.push('\'');
+			}
+			c => out.push(c),
+		}
+	}
+	out.push('\'');
+
+	/* Drop the empty quotes left around control characters at either end */
+	let mut out = out.as_str();
+	if out.len() > 2 {
+		out = out.strip_prefix("''").unwrap_or(out);
+		out = out.strip_suffix("''").unwrap_or(out);
+	}
+	out.to_string()
+}
+
+/* Parse a single word of sh(1), plus the ‘$'…'’ quotes of ksh(1) and bash(1),
+without any of the expansions. */
+fn ls_unquote(s: &str) -> Result<String, String> {
+	let unterminated = || "unterminated quote".to_string();
+	let mut out = String::with_capacity(s.len());
+	let mut it = s.chars().peekable();
+
+	while let Some(c) = it.next() {
+		match c {
+			'\'' => loop {
+				match it.next().ok_or_else(unterminated)? {
+					'\'' => break,
+					c => out.push(c),
+				}
+			},
+			'"' => loop {
+				match it.next().ok_or_else(unterminated)? {
+					'"' => break,
+					'\\' => match it.next().ok_or_else(unterminated)? {
+						c @ ('"' | '\\' | '$' | '`') => out.push(c),
+						c => out.extend(['\\', c]),
+					},
+					c => out.push(c),
+				}
+			},
+			'$' if it.next_if_eq(&'\'').is_some() => loop {
+				match it.next().ok_or_else(unterminated)? {
+					'\'' => break,
+					'\\' => out.push(ansi_c(&mut it)?),
+					c => out.push(c),
+				}
+			},
+			'\\' => out.push(it.next().ok_or("trailing backslash")?),
+			c if c.is_ascii_whitespace() => {
+				return Err("unquoted whitespace".to_string());
+			}
+			c => out.push(c),
+		}
+	}
+	Ok(out)
+}
+
+fn ansi_c(it: &mut Peekable<Chars>) -> Result<char, String> {
+	let c = it.next().ok_or("unterminated quote")?;
+	let (radix, len) = match c {
+		'x' => (16, 2),
+		'0'..='7' => (8, 2),
+		'a' => return Ok('\x07'),
+		'b' => return Ok('\x08'),
+		'e' | 'E' => return Ok('\x1B'),
+		'f' => return Ok('\x0C'),
+		'n' => return Ok('\n'),
+		'r' => return Ok('\r'),
+		't' => return Ok('\t'),
+		'v' => return Ok('\x0B'),
+		'\\' | '\'' | '"' | '?' => return Ok(c),
+		c => return Err(format!("invalid escape sequence ‘\\{c}’")),
+	};
+
+	let mut n = c.to_digit(8).unwrap_or(0);
+	for _ in 0..len {
+		match it.peek().and_then(|d| d.to_digit(radix)) {
+			Some(d) => n = n * radix + d,
+			None => break,
+		}
+		it.next();
+	}
+	/* Bytes above 0x7F would be parts of UTF-8 sequences, which we do not
+	reassemble */
+	match char::from_u32(n).filter(char::is_ascii) {
+		Some(c) => Ok(c),
+		None => Err(format!("escape sequence for non-ASCII byte {n:#04X}")),
+	}
+}
+
+/* Everything but the unreserved characters of RFC 3986 and the slashes between
+components is percent-encoded, byte by byte. */
+fn uri(s: &str) -> String {
+	let mut out = String::from(if s.starts_with('/') { "file://" } else { "" });
+	for b in s.bytes() {
+		match b {
+			b'/' | b'-' | b'.' | b'_' | b'~' => out.push(b as char),
+			b if b.is_ascii_alphanumeric() => out.push(b as char),
+			b => write!(out, "%{b:02X}").unwrap(),
+		}
+	}
+	out
+}
+
+fn uri_decode(s: &str) -> Result<String, String> {
+	let s = match s.strip_prefix("file://") {
+		Some(s) => s.strip_prefix("localhost").unwrap_or(s),
+		None => s,
+	};
+
+	let mut out = Vec::with_capacity(s.len());
+	let mut it = s.chars();
+	while let Some(c) = it.next() {
+		if c != '%' {
+			out.extend(c.to_string().bytes());
+			continue;
+		}
+		let ds = it.by_ref().take(2).collect::<String>();
+		match u8::from_str_radix(&ds, 16) {
+			Ok(b) if ds.len() == 2 && ds.chars().all(|c| c.is_ascii_hexdigit()) => out.push(b),
+			_ => return Err(format!("invalid percent-encoding ‘%{ds}’")),
+		}
+	}
+	String::from_utf8(out).map_err(|_| "path is not valid UTF-8".to_string())
+}
+
+fn json_string(s: &str) -> Result<String, String> {
+	let bad = || "expected a JSON string".to_string();
+	let inner = s
+		.trim()
+		.strip_prefix('"')
+		.and_then(|s| s.strip_suffix('"'))
+		.ok_or_else(bad)?;
+
+	let mut out = String::with_capacity(inner.len());
+	let mut it = inner.chars();
+	while let Some(c) = it.next() {
+		match c {
+			'"' => return Err(bad()),
+			'\\' => out.push(match it.next().ok_or_else(bad)? {
+				c @ ('"' | '\\' | '/') => c,
+				'b' => '\x08',
+				'f' => '\x0C',
+				'n' => '\n',
+				'r' => '\r',
+				't' => '\t',
+				'u' => {
+					let mut units = vec![utf16(&mut it)?];
+					if (0xD800..0xDC00).contains(&units[0]) && it.as_str().starts_with("\\u") {
+						it.nth(1);
+						units.push(utf16(&mut it)?);
+					}
+					match char::decode_utf16(units)
+						.collect::<Result<Vec<_>, _>>()
+						.as_deref()
+					{
+						Ok(&[c]) => c,
+						_ => return Err("invalid UTF-16 escape sequence".to_string()),
+					}
+				}
+				c => return Err(format!("invalid escape sequence ‘\\{c}’")),
+			}),
+			c => out.push(c),
+		}
+	}
+	Ok(out)
+}
+
+fn utf16(it: &mut Chars) -> Result<u16, String> {
+	let ds = it.by_ref().take(4).collect::<String>();
+	match u16::from_str_radix(&ds, 16) {
+		Ok(n) if ds.len() == 4 && ds.chars().all(|c| c.is_ascii_hexdigit()) => Ok(n),
+		_ => Err(format!("invalid escape sequence ‘\\u{ds}’")),
+	}
+}
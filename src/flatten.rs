@@ -0,0 +1,91 @@
+use std::{
+	collections::HashSet,
+	fs, io,
+	path::{Path, PathBuf},
+};
+
+/* Map every file found under the trees ‘inputs’ to a file of the same name
+directly inside of ‘dir’, for --flatten.  Files already in ‘dir’ keep their
+names, and the others are numbered like ‘name-2.ext’ in the order they are
+found in when their names are taken, be it by another file being moved or by
+one in ‘dir’ that is not. */
+pub fn plan(dir: &Path, inputs: &[String]) -> io::Result<(Vec<String>, Vec<String>)> {
+	let canon = fs::canonicalize(dir)?;
+	if !canon.is_dir() {
+		return Err(io::Error::new(
+			io::ErrorKind::NotADirectory,
+			"not a directory",
+		));
+	}
+
+	let mut srcs = Vec::new();
+	for i in inputs {
+		walk(Path::new(i), &mut srcs)?;
+	}
+	let inside = |s: &Path| {
+		let p = s.parent().filter(|p| !p.as_os_str().is_empty());
+		fs::canonicalize(p.unwrap_or(Path::new("."))).is_ok_and(|p| p == canon)
+	};
+
+	let mut taken = fs::read_dir(&canon)?
+		.map(|e| e.map(|e| e.file_name()))
+		.collect::<Result<HashSet<_>, _>>()?;
+	let mut dsts = vec![None; srcs.len()];
+	for (s, d) in srcs.iter().zip(dsts.iter_mut()) {
+		if inside(s) {
+			*d = Some(dir.join(s.file_name().unwrap()));
+		}
+	}
+	for (s, d) in srcs
+		.iter()
+		.zip(dsts.iter_mut())
+		.filter(|(_, d)| d.is_none())
+	{
+		let name = s.file_name().unwrap().to_string_lossy();
+		let (stem, ext) = match name.rfind('.') {
+			Some(0) | None => (&name[..], ""),
+			Some(i) => name.split_at(i),
+		};
+		let name = (1..)
+			.map(|n| match n {
+				1 => name.to_string(),
+				n => format!("{stem}-{n}{ext}"),
+			})
+			.find(|n| !taken.contains(Path::new(n).as_os_str()))
+			.unwrap();
+		taken.insert(name.clone().into());
+		*d = Some(dir.join(name));
+	}
+
+	let string = |p: PathBuf| {
+		p.into_os_string().into_string().map_err(|p| {
+			io::Error::new(
+				io::ErrorKind::InvalidData,
+				format!("{}: invalid UTF-8", p.to_string_lossy()),
+			)
+		})
+	};
+	Ok((
+		srcs.into_iter().map(string).collect::<Result<_, _>>()?,
+		dsts.into_iter()
+			.map(|d| string(d.unwrap()))
+			.collect::<Result<_, _>>()?,
+	))
+}
+
+/* Collect the files under ‘p’, or ‘p’ itself if it is not a directory, in the
+order of their names.  Symbolic links are not followed. */
+fn walk(p: &Path, out: &mut Vec<PathBuf>) -> io::Result<()> {
+	if !fs::symlink_metadata(p)?.is_dir() {
+		out.push(p.to_owned());
+		return Ok(());
+	}
+	let mut es = fs::read_dir(p)?
+		.map(|e| e.map(|e| e.path()))
+		.collect::<Result<Vec<_>, _>>()?;
+	es.sort();
+	for e in es {
+		walk(&e, out)?;
+	}
+	Ok(())
+}
@@ -0,0 +1,242 @@
+use std::{
+	collections::HashSet,
+	env,
+	ffi::OsString,
+	fs::{self, File},
+	io::{self, BufWriter, Write},
+	os::unix::ffi::{OsStrExt, OsStringExt},
+	path::{Path, PathBuf},
+	process,
+};
+
+use crate::report::warning;
+use crate::staging::{escape, record as write_record, unescape, verify};
+
+const MAGIC: &str = "mmv-history 2";
+const MAGIC_1: &str = "mmv-history 1";
+const COMMIT: &str = "commit";
+
+/* How many runs are kept, the oldest being forgotten as new ones come in */
+const MAX_RUNS: usize = 1000;
+
+/* A run of mmv that moved files, kept so that it can be undone later.  Runs
+are recorded in files numbered in the order they finished in, inside of the
+history directory, in the same format as the staging manifest:
+
+	mmv-history 2
+	time	1704067200	760c78ef
+	cwd	/path/to/directory	284979d1
+	command	mmv sed s/foo/bar/	7d4abce0
+	session	name	2f014447
+	/path/to/source	/path/to/destination	902000c6
+	…
+	commit	1	06ba2833
+
+The ‘session’ line is only there for runs given a session with --session.
+Like in the manifest, every record ends in its CRC-32, and the ‘commit’ record
+closes the list of pairs with their number, so that a run damaged on disk or
+cut short is noticed before --undo acts upon it.  Runs are written in full and
+synced under a temporary name before being linked into place.  Runs of the
+first version have no checksums and no ‘commit’ record. */
+pub struct Run {
+	pub id: u64,
+	pub time: i64,
+	pub cwd: PathBuf,
+	pub command: String,
+	pub session: Option<String>,
+	pub pairs: Vec<(PathBuf, PathBuf)>,
+}
+
+impl Run {
+	/* Whether undoing one of the runs could touch a file that the other one
+	moved, which is when any of their paths is the same as or inside of the
+	other’s */
+	pub fn conflicts(&self, other: &Run) -> bool {
+		let paths = |r: &Run| {
+			r.pairs
+				.iter()
+				.flat_map(|(s, d)| [s.clone(), d.clone()])
+				.collect::<HashSet<_>>()
+		};
+		let (ours, theirs) = (paths(self), paths(other));
+		let inside = |a: &HashSet<PathBuf>, b: &HashSet<PathBuf>| {
+			a.iter().any(|p| p.ancestors().any(|x| b.contains(x)))
+		};
+		inside(&ours, &theirs) || inside(&theirs, &ours)
+	}
+}
+
+/* The directory the history is kept in */
+pub fn dir() -> io::Result<PathBuf> {
+	let name = option_env!("MMV_NAME").unwrap_or(crate::MMV_DEFAULT_NAME);
+	let base = match env::var_os("XDG_DATA_HOME") {
+		Some(s) if !s.is_empty() => PathBuf::from(s),
+		_ => match env::var_os("HOME") {
+			Some(s) => Path::new(&s).join(".local/share"),
+			None => {
+				return Err(io::Error::new(
+					io::ErrorKind::NotFound,
+					"One of the XDG_DATA_HOME or HOME variables must be set",
+				))
+			}
+		},
+	};
+	Ok(base.join(name).join("history"))
+}
+
+/* Record that the files ‘pairs’ were moved, under the next free number, and
+forget the oldest runs beyond MAX_RUNS */
+pub fn record(
+	time: i64,
+	command: &str,
+	session: Option<&str>,
+	pairs: &[(&Path, &Path)],
+) -> io::Result<u64> {
+	let dir = dir()?;
+	fs::create_dir_all(&dir)?;
+	let tmp = dir.join(format!(".new-{}", process::id()));
+	let mut f = BufWriter::new(File::create(&tmp)?);
+	let cwd = env::current_dir()?;
+	writeln!(f, "{MAGIC}")?;
+	write_record(&mut f, &[b"time", time.to_string().as_bytes()])?;
+	write_record(&mut f, &[b"cwd", &escape(cwd.as_os_str().as_bytes())])?;
+	write_record(&mut f, &[b"command", &escape(command.as_bytes())])?;
+	if let Some(s) = session {
+		write_record(&mut f, &[b"session", &escape(s.as_bytes())])?;
+	}
+	for (s, d) in pairs {
+		write_record(
+			&mut f,
+			&[
+				&escape(s.as_os_str().as_bytes()),
+				&escape(d.as_os_str().as_bytes()),
+			],
+		)?;
+	}
+	write_record(
+		&mut f,
+		&[COMMIT.as_bytes(), pairs.len().to_string().as_bytes()],
+	)?;
+	f.flush()?;
+	f.get_ref().sync_all()?;
+
+	/* Linking fails where renaming would replace a run that another mmv
+	recorded under the same number in the meantime */
+	let mut id = ids(&dir)?.last().map_or(1, |n| n + 1);
+	let res = loop {
+		match fs::hard_link(&tmp, dir.join(id.to_string())) {
+			Err(e) if e.kind() == io::ErrorKind::AlreadyExists => id += 1,
+			res => break res,
+		}
+	};
+	fs::remove_file(&tmp)?;
+	res?;
+	File::open(&dir)?.sync_all()?;
+
+	let ids = ids(&dir)?;
+	for old in ids.iter().take(ids.len().saturating_sub(MAX_RUNS)) {
+		forget(*old)?;
+	}
+	Ok(id)
+}
+
+/* Every recorded run, oldest first.  Runs that cannot be read are skipped with
+a warning, so that a single damaged one leaves the others to be undone. */
+pub fn runs() -> io::Result<Vec<Run>> {
+	let dir = dir()?;
+	if !dir.exists() {
+		return Ok(Vec::new());
+	}
+	let mut runs = Vec::new();
+	for id in ids(&dir)? {
+		match read(&dir, id) {
+			Ok(r) => runs.push(r),
+			Err(e) => warning!(
+				"history",
+				["file" => &dir.join(id.to_string())],
+				"Skipped run {id}: {e}"
+			),
+		}
+	}
+	Ok(runs)
+}
+
+pub fn forget(id: u64) -> io::Result<()> {
+	fs::remove_file(dir()?.join(id.to_string()))
+}
+
+fn ids(dir: &Path) -> io::Result<Vec<u64>> {
+	let mut ids = Vec::new();
+	for e in fs::read_dir(dir)? {
+		if let Some(id) = e?.file_name().to_str().and_then(|s| s.parse().ok()) {
+			ids.push(id);
+		}
+	}
+	ids.sort_unstable();
+	Ok(ids)
+}
+
+fn read(dir: &Path, id: u64) -> io::Result<Run> {
+	let path = dir.join(id.to_string());
+	let bad = || {
+		io::Error::new(
+			io::ErrorKind::InvalidData,
+			format!("{}: malformed history record", path.display()),
+		)
+	};
+
+	let data = fs::read(&path)?;
+	let mut lines = data.split(|&b| b == b'\n').filter(|l| !l.is_empty());
+	let (lines, n) = match lines.next() {
+		Some(l) if l == MAGIC.as_bytes() => {
+			let mut ls = lines
+				.map(verify)
+				.collect::<Option<Vec<_>>>()
+				.ok_or_else(bad)?;
+			let n = ls
+				.pop()
+				.and_then(|l| l.strip_prefix(COMMIT.as_bytes())?.strip_prefix(b"\t"))
+				.and_then(|n| std::str::from_utf8(n).ok()?.parse::<usize>().ok())
+				.ok_or_else(bad)?;
+			(ls, Some(n))
+		}
+		Some(l) if l == MAGIC_1.as_bytes() => (lines.collect(), None),
+		_ => return Err(bad()),
+	};
+	let mut lines = lines.into_iter().peekable();
+	let mut field = |k: &str| {
+		let l = lines.next_if(|l| l.starts_with(k.as_bytes()))?;
+		unescape(l[k.len()..].strip_prefix(b"\t")?)
+	};
+	let time = field("time")
+		.and_then(|t| String::from_utf8(t).ok()?.parse().ok())
+		.ok_or_else(bad)?;
+	let cwd = PathBuf::from(OsString::from_vec(field("cwd").ok_or_else(bad)?));
+	let command = String::from_utf8_lossy(&field("command").ok_or_else(bad)?).into_owned();
+	let session = field("session").map(|s| String::from_utf8_lossy(&s).into_owned());
+
+	let mut pairs = Vec::new();
+	for l in lines {
+		let fields = l
+			.split(|&b| b == b'\t')
+			.map(unescape)
+			.collect::<Option<Vec<_>>>()
+			.ok_or_else(bad)?;
+		let [s, d] = <[Vec<u8>; 2]>::try_from(fields).map_err(|_| bad())?;
+		pairs.push((
+			PathBuf::from(OsString::from_vec(s)),
+			PathBuf::from(OsString::from_vec(d)),
+		));
+	}
+	if n.is_some_and(|n| n != pairs.len()) {
+		return Err(bad());
+	}
+	Ok(Run {
+		id,
+		time,
+		cwd,
+		command,
+		session,
+		pairs,
+	})
+}
@@ -0,0 +1,170 @@
+use std::{fs, io, path::Path};
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Format {
+	Git,
+	Rename,
+	Tsv,
+}
+
+pub fn parse_format(s: &str) -> Result<Format, &'static str> {
+	match s {
+		"git" => Ok(Format::Git),
+		"rename" => Ok(Format::Rename),
+		"tsv" => Ok(Format::Tsv),
+		_ => Err("expected one of ‘tsv’, ‘rename’, or ‘git’"),
+	}
+}
+
+/* Read a plan that some other tool wrote, or that was written by hand, as
+pairs of sources and destinations.  The supported formats are:
+
+	tsv     source⇥destination, with ‘\\’, ‘\t’, and ‘\n’ escaped
+	rename  old new, with words quoted and escaped like in sh(1)
+	git     the output of ‘git diff --name-status -M’, of which only the
+			renames are used
+
+Blank lines are skipped, as are lines starting with ‘#’ in the formats
+entered by hand. */
+pub fn read(format: Format, path: &Path) -> io::Result<Vec<(String, String)>> {
+	let data = fs::read(path)?;
+	let mut pairs = Vec::new();
+
+	for (i, line) in data.split(|&b| b == b'\n').enumerate() {
+		let bad = |what: &str| {
+			io::Error::new(
+				io::ErrorKind::InvalidData,
+				format!("line {}: {what}", i + 1),
+			)
+		};
+		if line.is_empty() || (format != Format::Git && line[0] == b'#') {
+			continue;
+		}
+
+		let fields = match format {
+			Format::Tsv => line
+				.split(|&b| b == b'\t')
+				.map(unescape)
+				.collect::<Option<Vec<_>>>()
+				.ok_or_else(|| bad("invalid escape sequence"))?,
+			Format::Rename => words(line).ok_or_else(|| bad("unterminated quote"))?,
+			Format::Git => {
+				let mut fields = line.split(|&b| b == b'\t');
+				match fields.next() {
+					Some([b'R', ..]) => fields
+						.map(unquote)
+						.collect::<Option<Vec<_>>>()
+						.ok_or_else(|| bad("invalid quoted path"))?,
+					Some(_) => continue,
+					None => unreachable!(),
+				}
+			}
+		};
+		let [src, dst] = <[Vec<u8>; 2]>::try_from(fields)
+			.map_err(|_| bad("expected exactly a source and a destination"))?;
+		let utf8 = |x| String::from_utf8(x).map_err(|_| bad("path is not valid UTF-8"));
+		pairs.push((utf8(src)?, utf8(dst)?));
+	}
+
+	Ok(pairs)
+}
+
+/* The inverse of unescape(), for writing plans in the tsv format */
+pub fn escape(s: &str) -> String {
+	s.replace('\\', "\\\\")
+		.replace('\t', "\\t")
+		.replace('\n', "\\n")
+}
+
+fn unescape(bs: &[u8]) -> Option<Vec<u8>> {
+	let mut out = Vec::with_capacity(bs.len());
+	let mut it = bs.iter();
+	while let Some(&b) = it.next() {
+		out.push(match b {
+			b'\\' => match it.next()? {
+				b'\\' => b'\\',
+				b't' => b'\t',
+				b'n' => b'\n',
+				_ => return None,
+			},
+			_ => b,
+		});
+	}
+	Some(out)
+}
+
+/* Split a line into words the way sh(1) would, minus all the expansions */
+fn words(bs: &[u8]) -> Option<Vec<Vec<u8>>> {
+	let mut words = Vec::new();
+	let mut word: Option<Vec<u8>> = None;
+	let mut it = bs.iter();
+
+	while let Some(&b) = it.next() {
+		match b {
+			b' ' | b'\t' | b'\r' => {
+				words.extend(word.take());
+			}
+			b'\'' => loop {
+				let w = word.get_or_insert_with(Vec::new);
+				match *it.next()? {
+					b'\'' => break,
+					c => w.push(c),
+				}
+			},
+			b'"' => loop {
+				let w = word.get_or_insert_with(Vec::new);
+				match *it.next()? {
+					b'"' => break,
+					b'\\' => match *it.next()? {
+						c @ (b'"' | b'\\' | b'$' | b'`') => w.push(c),
+						c => w.extend([b'\\', c]),
+					},
+					c => w.push(c),
+				}
+			},
+			b'\\' => word.get_or_insert_with(Vec::new).push(*it.next()?),
+			c => word.get_or_insert_with(Vec::new).push(c),
+		}
+	}
+	words.extend(word);
+	Some(words)
+}
+
+/* Git quotes paths containing unusual characters in double quotes, using the
+escape sequences of C and octal escapes for all other bytes. */
+fn unquote(bs: &[u8]) -> Option<Vec<u8>> {
+	let bs = match bs {
+		[b'"', inner @ .., b'"'] => inner,
+		_ => return Some(bs.to_vec()),
+	};
+	let mut out = Vec::with_capacity(bs.len());
+	let mut it = bs.iter().copied();
+	while let Some(b) = it.next() {
+		if b != b'\\' {
+			out.push(b);
+			continue;
+		}
+		out.push(match it.next()? {
+			b'a' => 0x07,
+			b'b' => 0x08,
+			b't' => b'\t',
+			b'n' => b'\n',
+			b'v' => 0x0B,
+			b'f' => 0x0C,
+			b'r' => b'\r',
+			c @ (b'"' | b'\\') => c,
+			c @ b'0'..=b'3' => {
+				let mut n = c - b'0';
+				for _ in 0..2 {
+					match it.next()? {
+						d @ b'0'..=b'7' => n = n * 8 + (d - b'0'),
+						_ => return None,
+					}
+				}
+				n
+			}
+			_ => return None,
+		});
+	}
+	Some(out)
+}
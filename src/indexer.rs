@@ -0,0 +1,34 @@
+use std::{
+	io,
+	path::PathBuf,
+	process::{Command, Stdio},
+};
+
+/* Desktop search indexers do eventually find moved files on their own, but
+only on their next crawl.  Both the Tracker miners on Linux and Spotlight on
+macOS can be told about files directly, which is far quicker than waiting. */
+#[cfg(target_os = "macos")]
+const INDEXER: &[&str] = &["mdimport"];
+#[cfg(not(target_os = "macos"))]
+const INDEXER: &[&str] = &["tracker3", "index"];
+
+pub fn notify(paths: &[PathBuf]) -> io::Result<()> {
+	if paths.is_empty() {
+		return Ok(());
+	}
+	let status = Command::new(INDEXER[0])
+		.args(&INDEXER[1..])
+		.args(paths)
+		.stdin(Stdio::null())
+		.stdout(Stdio::null())
+		.status()
+		.map_err(|e| io::Error::new(e.kind(), format!("{}: {e}", INDEXER[0])))?;
+	if status.success() {
+		Ok(())
+	} else {
+		Err(io::Error::other(format!(
+			"{} failed with {status}",
+			INDEXER[0]
+		)))
+	}
+}
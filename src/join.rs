@@ -0,0 +1,120 @@
+use std::{collections::HashMap, fs, io, path::Path};
+
+/* A table read with --join, whose rows are looked up by the value in their key
+column.  Files ending in ‘.tsv’ are split on tabs and nothing else; all others
+are read as CSV as described in RFC 4180, with fields optionally quoted in
+double quotes and quotes inside of them doubled.  The first row names the
+columns. */
+pub struct Table {
+	columns: HashMap<String, usize>,
+	rows: HashMap<String, Vec<String>>,
+}
+
+impl Table {
+	pub fn column(&self, name: &str) -> Option<usize> {
+		self.columns.get(name).copied()
+	}
+
+	pub fn row(&self, key: &str) -> Option<&[String]> {
+		self.rows.get(key).map(Vec::as_slice)
+	}
+}
+
+/* Read the table at ‘path’, keyed by the column named ‘key’, or by the first
+column if no name is given */
+pub fn read(path: &Path, key: Option<&str>) -> io::Result<Table> {
+	let bad = |line: usize, what: &str| {
+		io::Error::new(io::ErrorKind::InvalidData, format!("line {line}: {what}"))
+	};
+
+	let data = fs::read_to_string(path)?;
+	let tsv = path.extension().is_some_and(|e| e == "tsv");
+	let mut records = if tsv {
+		data.lines()
+			.enumerate()
+			.filter(|(_, l)| !l.is_empty())
+			.map(|(i, l)| (i + 1, l.split('\t').map(str::to_string).collect()))
+			.collect::<Vec<(usize, Vec<String>)>>()
+			.into_iter()
+	} else {
+		csv(&data)
+			.map_err(|i| bad(i, "unterminated quote"))?
+			.into_iter()
+	};
+
+	let Some((_, header)) = records.next() else {
+		return Err(io::Error::new(
+			io::ErrorKind::InvalidData,
+			"missing header row",
+		));
+	};
+	let k = match key {
+		Some(key) => header.iter().position(|c| c == key).ok_or_else(|| {
+			io::Error::new(
+				io::ErrorKind::InvalidData,
+				format!("no column named ‘{key}’"),
+			)
+		})?,
+		None => 0,
+	};
+
+	let mut rows = HashMap::new();
+	for (i, r) in records {
+		if r.len() != header.len() {
+			return Err(bad(
+				i,
+				&format!("expected {} fields but found {}", header.len(), r.len()),
+			));
+		}
+		if rows.insert(r[k].clone(), r.clone()).is_some() {
+			return Err(bad(i, &format!("duplicate key ‘{}’", r[k])));
+		}
+	}
+
+	let columns = header
+		.into_iter()
+		.enumerate()
+		.map(|(i, c)| (c, i))
+		.collect();
+	Ok(Table { columns, rows })
+}
+
+/* Split CSV into records along with the lines they start on, or return the
+line of an unterminated quote */
+fn csv(data: &str) -> Result<Vec<(usize, Vec<String>)>, usize> {
+	let mut records = Vec::new();
+	let mut line = 1;
+	let mut it = data.chars().peekable();
+
+	while it.peek().is_some() {
+		let start = line;
+		let mut record = Vec::new();
+		let mut field = String::new();
+		loop {
+			match it.next() {
+				Some('"') if field.is_empty() => loop {
+					match it.next().ok_or(start)? {
+						'"' if it.next_if_eq(&'"').is_some() => field.push('"'),
+						'"' => break,
+						c => {
+							line += (c == '\n') as usize;
+							field.push(c);
+						}
+					}
+				},
+				Some(',') => record.push(std::mem::take(&mut field)),
+				Some('\r') if it.peek() == Some(&'\n') => {}
+				Some('\n') | None => {
+					line += 1;
+					break;
+				}
+				Some(c) => field.push(c),
+			}
+		}
+		record.push(field);
+		if record != [""] {
+			records.push((start, record));
+		}
+	}
+	Ok(records)
+}
@@ -0,0 +1,66 @@
+use std::{
+	collections::HashMap,
+	fs,
+	path::{Path, PathBuf},
+	sync::OnceLock,
+};
+
+/* The directory of --link-dest, whose files are hard linked into place instead
+of copying files identical to them.  The files in it are only looked at once
+something is actually copied, and are matched by size before their contents
+are compared. */
+pub struct LinkDest {
+	pub dir: PathBuf,
+	index: OnceLock<HashMap<u64, Vec<PathBuf>>>,
+}
+
+impl LinkDest {
+	pub fn new(dir: PathBuf) -> LinkDest {
+		LinkDest {
+			dir,
+			index: OnceLock::new(),
+		}
+	}
+
+	/* A file under the directory with the same contents and mode bits as the
+	file ‘file’.  Empty files are never linked, as there is nothing to save. */
+	pub fn find(&self, file: &Path, mode: u32) -> Option<&Path> {
+		let md = fs::symlink_metadata(file).ok()?;
+		if !md.is_file() || md.len() == 0 {
+			return None;
+		}
+		let index = self.index.get_or_init(|| {
+			let mut index = HashMap::new();
+			walk(&self.dir, &mut index);
+			index
+		});
+		index
+			.get(&md.len())?
+			.iter()
+			.map(PathBuf::as_path)
+			.find(|c| {
+				use std::os::unix::fs::PermissionsExt;
+				fs::symlink_metadata(c)
+					.is_ok_and(|m| m.permissions().mode() & 0o7777 == mode & 0o7777)
+					&& crate::same_contents(c, file).unwrap_or(false)
+			})
+	}
+}
+
+/* Index the regular files under ‘dir’ by their sizes.  Symbolic links are not
+followed, and whatever cannot be read is left out. */
+fn walk(dir: &Path, index: &mut HashMap<u64, Vec<PathBuf>>) {
+	let Ok(es) = fs::read_dir(dir) else {
+		return;
+	};
+	for e in es.flatten() {
+		let Ok(md) = e.metadata() else {
+			continue;
+		};
+		if md.is_dir() {
+			walk(&e.path(), index);
+		} else if md.is_file() {
+			index.entry(md.len()).or_default().push(e.path());
+		}
+	}
+}
@@ -0,0 +1,189 @@
+use std::{fs, io, path::Path};
+
+use regex::Regex;
+
+/* Naming rules for --lint, read from a file of ‘key = value’ lines in the
+syntax of TOML:
+
+	lowercase = true        names must not contain uppercase letters
+	no-spaces = true        names must not contain whitespace
+	max-length = 64         names must be at most this many characters long
+	charset = "a-z0-9._-"   names may only contain these characters, given
+				the way they are inside of brackets in a regex
+	replacement = "-"       what whitespace and characters outside of the
+				charset are replaced by when fixing names
+
+Only the subset of TOML needed for these keys is supported, which leaves out
+tables and arrays. */
+pub struct Rules {
+	lowercase: bool,
+	no_spaces: bool,
+	max_length: Option<usize>,
+	charset: Option<Regex>,
+	replacement: String,
+}
+
+pub fn read(path: &Path) -> io::Result<Rules> {
+	let data = fs::read_to_string(path)?;
+	let mut rules = Rules {
+		lowercase: false,
+		no_spaces: false,
+		max_length: None,
+		charset: None,
+		replacement: "-".to_string(),
+	};
+
+	for (i, line) in data.lines().enumerate() {
+		let bad = |what: &str| {
+			io::Error::new(
+				io::ErrorKind::InvalidData,
+				format!("line {}: {what}", i + 1),
+			)
+		};
+		let line = line.trim();
+		if line.is_empty() || line.starts_with('#') {
+			continue;
+		}
+
+		let (key, value) = line
+			.split_once('=')
+			.ok_or_else(|| bad("expected ‘key = value’"))?;
+		let value = value.trim_start();
+		let (value, tail) = match value.as_bytes().first() {
+			Some(b'"') => basic_string(&value[1..]).ok_or_else(|| bad("unterminated string"))?,
+			Some(b'\'') => value[1..]
+				.split_once('\'')
+				.map(|(v, t)| (Value::String(v.to_string()), t))
+				.ok_or_else(|| bad("unterminated string"))?,
+			_ => {
+				let end = value.find(['#', ' ', '\t']).unwrap_or(value.len());
+				let (v, t) = value.split_at(end);
+				let v = match v {
+					"true" => Value::Bool(true),
+					"false" => Value::Bool(false),
+					v => Value::Integer(
+						v.replace('_', "")
+							.parse()
+							.map_err(|_| bad(&format!("invalid value ‘{v}’")))?,
+					),
+				};
+				(v, t)
+			}
+		};
+		let tail = tail.trim_start();
+		if !tail.is_empty() && !tail.starts_with('#') {
+			return Err(bad("trailing characters after value"));
+		}
+
+		match (key.trim().trim_matches('"'), value) {
+			("lowercase", Value::Bool(b)) => rules.lowercase = b,
+			("no-spaces", Value::Bool(b)) => rules.no_spaces = b,
+			("max-length", Value::Integer(n)) => rules.max_length = Some(n),
+			("charset", Value::String(s)) => {
+				let re = Regex::new(&format!("^[{s}]$"))
+					.map_err(|_| bad(&format!("invalid charset ‘{s}’")))?;
+				rules.charset = Some(re);
+			}
+			("replacement", Value::String(s)) => rules.replacement = s,
+			(key @ ("lowercase" | "no-spaces" | "max-length" | "charset" | "replacement"), _) => {
+				return Err(bad(&format!("wrong type of value for ‘{key}’")))
+			}
+			(key, _) => return Err(bad(&format!("unknown rule ‘{key}’"))),
+		}
+	}
+
+	Ok(rules)
+}
+
+enum Value {
+	Bool(bool),
+	Integer(usize),
+	String(String),
+}
+
+/* Parse the rest of a double-quoted string, returning it along with whatever
+follows the closing quote. */
+fn basic_string(s: &str) -> Option<(Value, &str)> {
+	let mut out = String::new();
+	let mut it = s.char_indices();
+	while let Some((i, c)) = it.next() {
+		match c {
+			'"' => return Some((Value::String(out), &s[i + 1..])),
+			'\\' => out.push(match it.next()?.1 {
+				'b' => '\x08',
+				'f' => '\x0C',
+				'n' => '\n',
+				'r' => '\r',
+				't' => '\t',
+				c @ ('"' | '\\') => c,
+				'u' => {
+					let ds = it.by_ref().take(4).map(|(_, c)| c).collect::<String>();
+					char::from_u32(u32::from_str_radix(&ds, 16).ok()?)?
+				}
+				_ => return None,
+			}),
+			c => out.push(c),
+		}
+	}
+	None
+}
+
+/* The rules that ‘name’ breaks, as descriptions fit for a diagnostic */
+pub fn check(rules: &Rules, name: &str) -> Vec<String> {
+	let mut broken = Vec::new();
+	if rules.lowercase && name.chars().any(char::is_uppercase) {
+		broken.push("contains uppercase letters".to_string());
+	}
+	if rules.no_spaces && name.chars().any(char::is_whitespace) {
+		broken.push("contains whitespace".to_string());
+	}
+	if let Some(n) = rules.max_length.filter(|&n| name.chars().count() > n) {
+		broken.push(format!("is longer than {n} characters"));
+	}
+	if let Some(re) = &rules.charset {
+		if let Some(c) = name.chars().find(|c| !allowed(re, *c)) {
+			broken.push(format!("contains the disallowed character ‘{c}’"));
+		}
+	}
+	broken
+}
+
+/* Rewrite ‘name’ to follow the rules as best we can.  Names that are too long
+are cut short before their extension, so that the type of the file is still
+recognizable. */
+pub fn fix(rules: &Rules, name: &str) -> String {
+	let mut out = String::with_capacity(name.len());
+	for c in name.chars() {
+		let c = if rules.lowercase {
+			c.to_lowercase().collect()
+		} else {
+			c.to_string()
+		};
+		for c in c.chars() {
+			let space = rules.no_spaces && c.is_whitespace();
+			match &rules.charset {
+				_ if space => out.push_str(&rules.replacement),
+				Some(re) if !allowed(re, c) => out.push_str(&rules.replacement),
+				_ => out.push(c),
+			}
+		}
+	}
+
+	let Some(n) = rules.max_length.filter(|&n| out.chars().count() > n) else {
+		return out;
+	};
+	let ext = match out.rfind('.') {
+		Some(0) | None => "",
+		Some(i) => &out[i..],
+	};
+	let ext = if ext.chars().count() < n { ext } else { "" };
+	let stem = out[..out.len() - ext.len()]
+		.chars()
+		.take(n - ext.chars().count())
+		.collect::<String>();
+	stem + ext
+}
+
+fn allowed(re: &Regex, c: char) -> bool {
+	re.is_match(c.encode_utf8(&mut [0; 4]))
+}
@@ -1,54 +1,296 @@
+mod check;
+#[cfg(feature = "clipboard")]
+mod clipboard;
+mod conflict;
+mod directives;
+mod encoding;
+mod flatten;
+mod history;
+mod import;
+#[cfg(feature = "indexer")]
+mod indexer;
+mod join;
+mod linkdest;
+mod lint;
+mod map;
+mod mounts;
+mod nest;
+#[cfg(feature = "notify")]
+mod notify;
+mod options;
+mod owners;
+mod preflight;
+mod report;
+mod script;
+mod snapshot;
+mod staging;
+mod subst;
+mod symlinks;
+mod timings;
+mod trash;
+mod tree;
+mod uri;
+#[cfg(target_os = "linux")]
+mod watch;
+
 use std::{
 	cmp::Reverse,
-	collections::{hash_map::DefaultHasher, HashSet, VecDeque},
+	collections::{BTreeMap, HashMap, HashSet},
 	env,
 	ffi::OsString,
-	fs,
-	hash::{Hash, Hasher},
-	io::{self, BufWriter, Read, Write},
+	fmt, fs,
+	io::{self, BufReader, BufWriter, IsTerminal, Read, Write},
 	iter,
+	os::unix::{ffi::OsStrExt, fs::MetadataExt, process::ExitStatusExt},
 	path::{Component, Display, Path, PathBuf},
-	process::{self, Command, Stdio},
-	time::{SystemTime, UNIX_EPOCH},
+	process::{self, Command, ExitStatus, Stdio},
+	str,
+	sync::{
+		atomic::{AtomicBool, AtomicUsize, Ordering},
+		Mutex,
+	},
+	thread,
+	time::{Duration, Instant, SystemTime, UNIX_EPOCH},
 };
 
 use itertools::Itertools;
 
 use {
-	cerm::{err, require, warn},
+	cerm::{err, require},
+	directives::Directives,
+	mmv_core::{
+		plan::{self, Issue, Plan},
+		vfs::{self, Filesystem},
+	},
+	report::{fatal, warning},
+	script::{Arg, Script, Shell},
+	subst::Substitution,
 	tempfile::tempdir,
 };
 
 const MMV_DEFAULT_NAME: &str = "mmv";
 const MCP_DEFAULT_NAME: &str = "mcp";
+const MLN_DEFAULT_NAME: &str = "mln";
+const MRM_DEFAULT_NAME: &str = "mrm";
+const MAP_DEFAULT_NAME: &str = "mmv-map";
+
+/* Exit statuses telling apart the ways in which the command can fail to give us
+anything to work with, besides the general status of 1 */
+const EXIT_CHILD_FAILED: i32 = 2;
+const EXIT_ABORTED: i32 = 3;
+const EXIT_NO_OUTPUT: i32 = 4;
+const EXIT_PENDING: i32 = 100;
+
+/* How often a copy that runs out of space is retried with --assume-yes when
+--retries does not say, which at the default delay is a minute */
+const FULL_RETRIES: u32 = 60;
+
+#[derive(Clone, Copy, PartialEq)]
+enum Policy {
+	Fail,
+	Skip,
+	Warn,
+}
+
+/* The order in which files are moved into the staging directory */
+#[derive(Clone, Copy, PartialEq)]
+enum Order {
+	AsGiven,
+	DepthFirst,
+	LargeFirst,
+	SmallFirst,
+}
 
 struct Flags {
+	pub allow_mismatch: bool,
+	pub allow_weird: bool,
+	pub assume: Option<bool>,
 	pub backup: bool,
 	pub basename: bool,
+	pub changed: Policy,
+	pub check: bool,
+	pub check_contents: bool,
+	pub check_open: bool,
+	pub chdir: Option<PathBuf>,
+	pub ci_duplicates: bool,
+	pub conservative: bool,
+	pub copy_threshold: Option<u64>,
+	pub dir_mode: Option<u32>,
 	pub dryrun: bool,
+	pub emit: Option<Shell>,
 	pub encode: bool,
+	pub encoding: encoding::Encoding,
+	pub expect_digest: Option<String>,
+	pub fix_symlinks: bool,
+	pub flatten: Option<PathBuf>,
+	pub force_chain: bool,
+	pub from: Vec<PathBuf>,
+	#[cfg(feature = "clipboard")]
+	pub from_clipboard: bool,
+	pub full_paths: bool,
+	pub group: Option<u32>,
+	pub group_by_dir: bool,
+	pub history: bool,
+	pub import: Option<(import::Format, PathBuf)>,
 	pub individual: bool,
+	pub interactive: bool,
+	pub into_dirs: bool,
+	pub jobs: usize,
+	pub join: Option<PathBuf>,
+	pub key: Option<String>,
+	pub limit: Option<usize>,
+	pub link_dest: Option<linkdest::LinkDest>,
+	pub lint: Option<PathBuf>,
+	pub map_prefix: Vec<(PathBuf, PathBuf)>,
+	pub map_sources: bool,
 	pub mcp: bool,
+	pub merge: bool,
+	pub merge_identical: bool,
+	pub metadata: bool,
+	pub missing: Policy,
+	pub mln: bool,
+	pub mode: Option<u32>,
+	pub mrm: bool,
+	pub nest_by: Option<nest::Template>,
+	#[cfg(feature = "notify")]
+	pub notify: bool,
+	#[cfg(feature = "indexer")]
+	pub notify_indexer: bool,
 	pub nul: bool,
+	pub numbered: bool,
+	pub order: Order,
+	pub owner: Option<u32>,
+	pub partial_apply: bool,
+	pub porcelain: bool,
+	pub prune: bool,
+	pub quarantine: bool,
+	pub relative_to: Option<(PathBuf, PathBuf)>,
+	pub restore: Option<PathBuf>,
+	pub restrict: Option<PathBuf>,
+	pub resume: Option<PathBuf>,
+	pub retries: u32,
+	pub retry_delay: Option<Duration>,
+	pub review_stats: bool,
+	pub sample: Option<usize>,
+	pub session: Option<String>,
+	pub snapshot: bool,
+	pub subst: Vec<Substitution>,
+	pub symbolic: bool,
+	pub timings: bool,
+	#[cfg(feature = "clipboard")]
+	pub to_clipboard: bool,
+	pub trash: bool,
+	pub tree: bool,
+	pub undo: Option<String>,
+	pub uri: bool,
 	pub verbose: bool,
+	pub watch: Option<PathBuf>,
 }
 
 impl Default for Flags {
 	fn default() -> Self {
 		Flags {
+			allow_mismatch: false,
+			allow_weird: false,
+			assume: None,
 			backup: true,
 			basename: false,
+			changed: Policy::Fail,
+			check: false,
+			check_contents: false,
+			check_open: false,
+			chdir: None,
+			ci_duplicates: false,
+			conservative: false,
+			copy_threshold: None,
+			dir_mode: None,
 			dryrun: false,
+			emit: None,
 			encode: false,
+			encoding: encoding::Encoding::V1,
+			expect_digest: None,
+			fix_symlinks: false,
+			flatten: None,
+			force_chain: false,
+			from: Vec::new(),
+			#[cfg(feature = "clipboard")]
+			from_clipboard: false,
+			full_paths: false,
+			group: None,
+			group_by_dir: false,
+			history: false,
+			import: None,
 			individual: false,
+			interactive: false,
+			into_dirs: false,
+			jobs: 1,
+			join: None,
+			key: None,
+			limit: None,
+			link_dest: None,
+			lint: None,
+			map_prefix: Vec::new(),
+			map_sources: false,
 			mcp: false,
+			merge: false,
+			merge_identical: false,
+			metadata: false,
+			missing: Policy::Fail,
+			mln: false,
+			mode: None,
+			mrm: false,
+			nest_by: None,
+			#[cfg(feature = "notify")]
+			notify: false,
+			#[cfg(feature = "indexer")]
+			notify_indexer: false,
 			nul: false,
+			numbered: false,
+			order: Order::DepthFirst,
+			owner: None,
+			partial_apply: false,
+			porcelain: false,
+			prune: false,
+			quarantine: true,
+			relative_to: None,
+			restore: None,
+			restrict: None,
+			resume: None,
+			retries: 0,
+			retry_delay: None,
+			review_stats: false,
+			sample: None,
+			session: None,
+			snapshot: false,
+			subst: Vec::new(),
+			symbolic: false,
+			timings: false,
+			#[cfg(feature = "clipboard")]
+			to_clipboard: false,
+			trash: true,
+			tree: false,
+			undo: None,
+			uri: false,
 			verbose: false,
+			watch: None,
 		}
 	}
 }
 
 impl Flags {
+	/* The utility we were run as, as one of those of the options module */
+	fn util(&self) -> u8 {
+		if self.mcp {
+			options::MCP
+		} else if self.mln {
+			options::MLN
+		} else if self.mrm {
+			options::MRM
+		} else {
+			options::MMV
+		}
+	}
+
 	fn parse() -> Result<(Flags, Vec<OsString>), lexopt::Error> {
 		use lexopt::prelude::*;
 
@@ -60,20 +302,222 @@ impl Flags {
 		let p = Path::new(&argv0).file_name().unwrap();
 
 		let mcp_name = option_env!("MCP_NAME").unwrap_or(MCP_DEFAULT_NAME);
+		let mln_name = option_env!("MLN_NAME").unwrap_or(MLN_DEFAULT_NAME);
+		let mrm_name = option_env!("MRM_NAME").unwrap_or(MRM_DEFAULT_NAME);
 		if p == mcp_name {
 			flags.mcp = true;
 			flags.backup = false;
+		} else if p == mln_name {
+			flags.mln = true;
+			flags.backup = false;
+		} else if p == mrm_name {
+			flags.mrm = true;
+			flags.backup = false;
 		}
 
 		while let Some(arg) = parser.next()? {
+			/* Which options each utility has is up to the options table */
+			if !options::applies(&arg, flags.util()) {
+				return Err(arg.unexpected());
+			}
 			match arg {
+				Long("allow-length-mismatch") => flags.allow_mismatch = true,
+				Long("allow-weird-names") => flags.allow_weird = true,
+				Long("assume-no") => flags.assume = Some(false),
+				Long("assume-yes") => flags.assume = Some(true),
 				Short('0') | Long("nul") => flags.nul = true,
 				Short('b') | Long("basename") => flags.basename = true,
+				Long("changed") => flags.changed = parser.value()?.parse_with(parse_policy)?,
+				Short('C') | Long("chdir") => {
+					flags.chdir = Some(PathBuf::from(parser.value()?));
+				}
+				Long("check") => flags.check = true,
+				Long("check-contents") => {
+					flags.check = true;
+					flags.check_contents = true;
+				}
+				Long("check-open") => flags.check_open = true,
+				Long("ci-duplicates") => flags.ci_duplicates = true,
+				Long("copy-threshold") => {
+					flags.copy_threshold = Some(parser.value()?.parse_with(parse_size)?);
+				}
+				Long("conservative") => flags.conservative = true,
+				Long("dir-mode") => {
+					flags.dir_mode = Some(parser.value()?.parse_with(parse_mode)?);
+				}
 				Short('d') | Long("dry-run") => flags.dryrun = true,
+				Long("emit-script") => {
+					flags.emit = Some(parser.value()?.parse_with(|s| match s {
+						"sh" => Ok(Shell::Sh),
+						"pwsh" => Ok(Shell::Pwsh),
+						_ => Err("expected one of ‘sh’ or ‘pwsh’"),
+					})?);
+					flags.dryrun = true;
+					flags.backup = false;
+				}
 				Short('e') | Long("encode") => flags.encode = true,
+				Long("encoding") => {
+					flags.encoding = parser.value()?.parse_with(encoding::parse)?;
+					flags.encode = true;
+				}
+				Long("format") => {
+					flags.encoding = parser.value()?.parse_with(encoding::parse_format)?;
+					flags.encode = true;
+				}
+				Long("errors") => {
+					report::set_json(parser.value()?.parse_with(|s| match s {
+						"text" => Ok(false),
+						"json" => Ok(true),
+						_ => Err("expected one of ‘text’ or ‘json’"),
+					})?);
+				}
+				Long("expect-digest") => {
+					flags.expect_digest = Some(parser.value()?.parse_with(|s| {
+						match s.len() == 64 && s.bytes().all(|b| b.is_ascii_hexdigit()) {
+							true => Ok(s.to_ascii_lowercase()),
+							false => Err("expected a SHA-256 digest in hexadecimal"),
+						}
+					})?);
+				}
+				Long("fix-symlinks") => flags.fix_symlinks = true,
+				Long("flatten") => {
+					flags.flatten = Some(PathBuf::from(parser.value()?));
+				}
+				Long("force-chain") => flags.force_chain = true,
+				#[cfg(feature = "clipboard")]
+				Long("from-clipboard") => flags.from_clipboard = true,
+				Long("from-file") => flags.from.push(PathBuf::from(parser.value()?)),
+				Long("input") => {
+					let fd: u32 = parser.value()?.parse()?;
+					flags.from.push(PathBuf::from(format!("/dev/fd/{fd}")));
+				}
+				Long("full-paths") => flags.full_paths = true,
+				Long("group") => {
+					flags.group = Some(parser.value()?.parse_with(owners::group)?);
+				}
+				Long("group-by-dir") => flags.group_by_dir = true,
+				Long("history") => flags.history = true,
+				Long("import") => {
+					let format = parser.value()?.parse_with(import::parse_format)?;
+					let path = PathBuf::from(parser.value()?);
+					flags.import = Some((format, path));
+				}
 				Short('i') | Long("individual") => flags.individual = true,
-				Short('n') | Long("no-backup") if !flags.mcp => flags.backup = false,
-				Short('v') | Long("verbose") => flags.verbose = true,
+				Long("interactive") => flags.interactive = true,
+				Long("into-dirs") => flags.into_dirs = true,
+				Short('h') | Long("help") => help(&flags),
+				Short('j') | Long("jobs") => {
+					flags.jobs = parser.value()?.parse_with(|s| match s.parse() {
+						Ok(0) | Err(_) => Err("expected a positive number of jobs"),
+						Ok(n) => Ok(n),
+					})?;
+				}
+				Long("join") => flags.join = Some(PathBuf::from(parser.value()?)),
+				Long("key") => flags.key = Some(parser.value()?.parse()?),
+				Long("limit") => flags.limit = Some(parser.value()?.parse()?),
+				Long("link-dest") => {
+					flags.link_dest = Some(linkdest::LinkDest::new(PathBuf::from(parser.value()?)))
+				}
+				Long("lint") => flags.lint = Some(PathBuf::from(parser.value()?)),
+				Long("map-prefix") => {
+					flags.map_prefix.push(parser.value()?.parse_with(|s| {
+						s.split_once('=')
+							.map(|(from, to)| (PathBuf::from(from), PathBuf::from(to)))
+							.ok_or("expected ‘from=to’")
+					})?);
+				}
+				Long("map-sources") => flags.map_sources = true,
+				Long("merge") => flags.merge = true,
+				Long("merge-identical") => flags.merge_identical = true,
+				Long("metadata") => flags.metadata = true,
+				Long("missing") => flags.missing = parser.value()?.parse_with(parse_policy)?,
+				Long("mode") => flags.mode = Some(parser.value()?.parse_with(parse_mode)?),
+				Long("nest-by") => {
+					flags.nest_by = Some(parser.value()?.parse_with(nest::Template::parse)?)
+				}
+				Short('n') | Long("no-trash") if flags.mrm => flags.trash = false,
+				#[cfg(feature = "notify")]
+				Long("notify") => flags.notify = true,
+				#[cfg(feature = "indexer")]
+				Long("notify-indexer") => flags.notify_indexer = true,
+				Long("numbered") => flags.numbered = true,
+				Long("order") => {
+					flags.order = parser.value()?.parse_with(parse_order)?;
+				}
+				Long("owner") => {
+					flags.owner = Some(parser.value()?.parse_with(owners::user)?);
+				}
+				Long("partial-apply") => flags.partial_apply = true,
+				Long("porcelain") => {
+					flags.porcelain = true;
+					flags.verbose = true;
+				}
+				Long("prune-empty") => flags.prune = true,
+				Short('n') | Long("no-backup") => flags.backup = false,
+				Long("quarantine") => {
+					flags.quarantine = parser.value()?.parse_with(|s| match s {
+						"keep" => Ok(true),
+						"strip" => Ok(false),
+						_ => Err("expected one of ‘keep’ or ‘strip’"),
+					})?;
+				}
+				Long("relative-to") => {
+					let src_root = PathBuf::from(parser.value()?);
+					let dst_root = PathBuf::from(parser.value()?);
+					flags.relative_to = Some((src_root, dst_root));
+				}
+				Long("restore") => {
+					flags.restore = Some(PathBuf::from(parser.value()?));
+				}
+				Long("restrict") => {
+					flags.restrict = Some(PathBuf::from(parser.value()?));
+				}
+				Long("resume") => {
+					flags.resume = Some(PathBuf::from(parser.value()?));
+				}
+				Long("retries") => flags.retries = parser.value()?.parse()?,
+				Long("retry-delay") => {
+					let ms = parser.value()?.parse()?;
+					flags.retry_delay = Some(Duration::from_millis(ms));
+				}
+				Long("review-stats") => flags.review_stats = true,
+				Long("sample") => flags.sample = Some(parser.value()?.parse()?),
+				Long("session") => {
+					flags.session = Some(parser.value()?.parse_with(|s: &str| {
+						match s.bytes().all(|b| b.is_ascii_digit()) || s.contains('/') {
+							true => Err("expected a name that is not a number and has no slashes"),
+							false => Ok(s.to_string()),
+						}
+					})?)
+				}
+				Long("snapshot") => flags.snapshot = true,
+				Short('s') | Long("symbolic") if flags.mln => flags.symbolic = true,
+				Short('s') | Long("substitute") => {
+					flags
+						.subst
+						.push(parser.value()?.parse_with(Substitution::parse)?);
+				}
+				Long("timings") => flags.timings = true,
+				#[cfg(feature = "clipboard")]
+				Long("to-clipboard") => flags.to_clipboard = true,
+				Long("tree") => {
+					flags.tree = true;
+					flags.dryrun = true;
+				}
+				Long("undo") => flags.undo = Some(parser.value()?.parse()?),
+				Long("uri") => flags.uri = true,
+				Short('v') | Long("verbose") => {
+					flags.timings |= flags.verbose;
+					flags.verbose = true;
+				}
+				Long("version") => version(),
+				Long("watch") => {
+					flags.watch = Some(PathBuf::from(parser.value()?));
+				}
+				/* Our own flags must all come before the command, as everything
+				from the command onwards — flags, ‘--’, and all — is passed to
+				it untouched.  A ‘--’ before the command ends our flags, which
+				allows for commands whose names begin with a dash. */
 				Value(v) => {
 					rest.push(v);
 					rest.extend(iter::from_fn(|| parser.value().ok()));
@@ -88,33 +532,110 @@ impl Flags {
 
 fn usage(bad_flags: Option<lexopt::Error>) -> ! {
 	if let Some(e) = bad_flags {
-		warn!("{e}");
+		warning!("usage", [], "{e}");
+	}
+	let _ = synopsis(&mut io::stderr());
+	process::exit(1);
+}
+
+/* Print the synopsis and every option of the utility we were run as for
+--help */
+fn help(flags: &Flags) -> ! {
+	help_for(flags.util())
+}
+
+/* The same for the utility ‘util’, one of those of the options module, which
+is how mmv-map gets its own */
+fn help_for(util: u8) -> ! {
+	let mut out = io::stdout().lock();
+	let res = synopsis(&mut out)
+		.and_then(|_| writeln!(out, "\nOptions:"))
+		.and_then(|_| options::list(&mut out, util))
+		.and_then(|_| out.flush());
+	match res {
+		Err(e) if e.kind() != io::ErrorKind::BrokenPipe => fatal!("io", [], "{e}"),
+		_ => process::exit(0),
 	}
+}
+
+/* Print the version of the utilities and the optional parts of them that are
+built in for --version */
+fn version() -> ! {
+	let argv0 = env::args().next().unwrap();
+	let p = Path::new(&argv0).file_name().unwrap().to_string_lossy();
+	let fs = options::features();
+	let fs = if fs.is_empty() {
+		"none".to_string()
+	} else {
+		fs.join(" ")
+	};
+	println!("{p} {}\nfeatures: {fs}", env!("CARGO_PKG_VERSION"));
+	process::exit(0);
+}
+
+fn synopsis(out: &mut dyn Write) -> io::Result<()> {
 	let argv0 = env::args().next().unwrap();
 	let p = Path::new(&argv0).file_name().unwrap();
+	let p = p.to_str().unwrap();
 	let mcp_name = option_env!("MCP_NAME").unwrap_or(MCP_DEFAULT_NAME);
-	if p == mcp_name {
-		eprintln!(
-			"Usage: {} [-0bdeiv] command [argument ...]",
-			p.to_str().unwrap()
-		);
+	let mln_name = option_env!("MLN_NAME").unwrap_or(MLN_DEFAULT_NAME);
+	let mrm_name = option_env!("MRM_NAME").unwrap_or(MRM_DEFAULT_NAME);
+	let map_name = option_env!("MAP_NAME").unwrap_or(MAP_DEFAULT_NAME);
+	if p == mrm_name {
+		return writeln!(out, "Usage: {p} [-0bdeinv] command [argument ...]");
+	}
+	if p == map_name {
+		return writeln!(out, "Usage: {p} [-0] expression ...");
+	}
+	let (cmd_flags, subst_flags, subst_opt) = if p == mcp_name {
+		("-0bdeiv", "-0bdv", "-s")
+	} else if p == mln_name {
+		("-0bdeisv", "-0bdsv", "--substitute")
 	} else {
-		eprintln!(
-			"Usage: {} [-0bdeinv] command [argument ...]",
-			p.to_str().unwrap()
-		);
+		("-0bdeinv", "-0bdnv", "-s")
+	};
+	writeln!(
+		out,
+		"Usage: {p} [{cmd_flags}] [-C dir] command [argument ...]\n       {p} [{subst_flags}] [-C dir] {subst_opt} expression ..."
+	)?;
+	if p != mln_name {
+		writeln!(out, "       {p} [-dv] --resume | --restore dir")?;
 	}
-	process::exit(1);
+	if p != mcp_name && p != mln_name {
+		writeln!(out, "       {p} [--session name] --history")?;
+		writeln!(out, "       {p} [-dv] [--force-chain] --undo id | name")?;
+	}
+	writeln!(out, "       {p} [-dv] --import format file")?;
+	writeln!(out, "       {p} [-0dv] --flatten dir")?;
+	writeln!(out, "       {p} [-0dv] --nest-by template")?;
+	writeln!(out, "       {p} [-0] --lint rules")?;
+	Ok(())
 }
 
 fn main() {
-	require!(work())
+	let argv0 = env::args_os().next().unwrap_or_default();
+	let map_name = option_env!("MAP_NAME").unwrap_or(MAP_DEFAULT_NAME);
+	if Path::new(&argv0).file_name() == Some(map_name.as_ref()) {
+		if let Err(e) = map::run() {
+			fatal!("io", [], "{e}");
+		}
+		return;
+	}
+	if let Err(e) = work() {
+		fatal!("io", [], "{e}");
+	}
+	#[cfg(feature = "notify")]
+	notify::succeeded();
 }
 
 fn get_default_config_path() -> PathBuf {
 	[
 		&env::var("HOME").unwrap_or_else(|_| {
-			err!("One of the XDG_CACHE_HOME or HOME variables must be set");
+			fatal!(
+				"environment",
+				[],
+				"One of the XDG_CACHE_HOME or HOME variables must be set"
+			);
 		}),
 		".cache",
 	]
@@ -127,376 +648,2489 @@ fn work() -> Result<(), io::Error> {
 		Ok(a) => a,
 		Err(e) => usage(Some(e)),
 	};
-	let (cmd, args) = rest.split_first().unwrap_or_else(|| usage(None));
-
-	/* Collect sources from standard input */
-	let srcs = io::stdin()
-		.bytes()
-		.map(|x| require!(x))
-		.group_by(|b| is_terminal(flags.nul, b));
-	let srcs = srcs
-		.into_iter()
-		.filter(|(x, _)| !x)
-		.map(|(_, x)| String::from_utf8(x.collect_vec()))
-		.collect::<Result<Vec<_>, _>>();
-	let srcs = require!(srcs);
-
-	let mut dsts = Vec::with_capacity(srcs.len());
-	if flags.individual {
-		run_indiv(&srcs, &mut dsts, &flags, cmd, args)?;
-	} else {
-		run_multi(&srcs, &mut dsts, &flags, cmd, args)?;
+	#[cfg(feature = "notify")]
+	if flags.notify && !flags.dryrun && flags.emit.is_none() {
+		notify::enable();
 	}
-
-	if dsts.len() != srcs.len() {
-		err!("Files have been added or removed during editing");
+	#[cfg(feature = "clipboard")]
+	if (flags.from_clipboard && (!flags.from.is_empty() || flags.watch.is_some()))
+		|| (flags.to_clipboard && !flags.dryrun)
+	{
+		usage(None);
 	}
-
-	let mut uniq_srcs: HashSet<PathBuf> = HashSet::with_capacity(srcs.len());
-	let mut uniq_dsts: HashSet<PathBuf> = HashSet::with_capacity(dsts.len());
-
-	let dir = tempdir()?;
-	if flags.verbose {
-		eprintln!("created directory ‘{}’", dir.path().display());
+	if let Some(dir) = flags.resume.as_ref().or(flags.restore.as_ref()) {
+		if !rest.is_empty() || !flags.subst.is_empty() {
+			usage(None);
+		}
+		return recover(&flags, dir);
 	}
-
-	let ps = srcs
-		.iter()
-		.zip(dsts)
-		.map(|(s, d)| -> Result<(PathBuf, PathBuf, PathBuf), io::Error> {
-			let s = fs::canonicalize(s)?;
-			let d = env::current_dir()?.join(Path::new(&d));
-			let d = normalize_path(&d);
-
-			if !uniq_srcs.insert(s.clone()) {
-				err!(
-					"Input file “{}” specified more than once",
-					s.to_string_lossy()
-				);
-			} else if !uniq_dsts.insert(d.clone()) {
-				err!(
-					"Output file “{}” specified more than once",
-					d.to_string_lossy()
-				);
-			} else {
-				let mut hasher = DefaultHasher::new();
-				s.hash(&mut hasher);
-				let file = hasher.finish().to_string();
-				let t = dir.path().join(&file);
-				Ok((s, t, d))
-			}
-		})
-		.map(|x| require!(x))
-		.sorted_by_key(|s| Reverse(s.0.components().count()))
-		.collect_vec();
-
-	let mut cache_dir = PathBuf::default();
-	if flags.backup {
-		let ts = require!(SystemTime::now().duration_since(UNIX_EPOCH))
-			.as_nanos()
-			.to_string();
-		let cache_base = match env::var("XDG_CACHE_HOME") {
-			Ok(s) => {
-				if s.is_empty() {
-					get_default_config_path()
-				} else {
-					PathBuf::from(s)
-				}
-			}
-			_ => get_default_config_path(),
-		};
-		let mmv_name = option_env!("MMV_NAME").unwrap_or(MMV_DEFAULT_NAME);
-		cache_dir = [
-			cache_base.as_path(),
-			Path::new(mmv_name),
-			Path::new(ts.as_str()),
-		]
-		.iter()
-		.collect::<PathBuf>();
-		fs::create_dir_all(&cache_dir)?;
-
-		if flags.verbose {
-			eprintln!("created directory ‘{}’", cache_dir.display());
+	if flags.history {
+		if !rest.is_empty() || !flags.subst.is_empty() {
+			usage(None);
 		}
-
-		let cwd = require!(env::current_dir());
-		require!(env::set_current_dir(&cache_dir));
-		backup_srcs(&flags, &cache_dir, ps.iter().map(|(s, _, _)| s))?;
-		require!(env::set_current_dir(cwd));
+		return list_history(&flags);
 	}
-
-	if flags.dryrun {
-		for (s, _, d) in ps {
-			eprintln!(
-				"{} ‘{}’ -> ‘{}’",
-				if flags.mcp { "copied" } else { "renamed" },
-				disp(&s),
-				disp(&d)
-			);
+	if let Some(id) = &flags.undo {
+		if !rest.is_empty()
+			|| !flags.subst.is_empty()
+			|| flags.watch.is_some()
+			|| flags.session.is_some()
+		{
+			usage(None);
 		}
-	} else {
-		for (s, t, _) in ps.iter() {
-			move_path(&flags, &s, &t);
+		return undo(&flags, id);
+	} else if flags.force_chain {
+		usage(None);
+	}
+	if let Some((format, path)) = &flags.import {
+		if !rest.is_empty() || !flags.subst.is_empty() || flags.watch.is_some() {
+			usage(None);
 		}
-		for (_, t, d) in ps.iter().rev() {
-			move_path(&flags, &t, &d);
+		let (srcs, dsts) = import::read(*format, path)
+			.unwrap_or_else(|e| fatal!("import", ["plan" => path], "{}: {e}", path.display()))
+			.into_iter()
+			.unzip();
+		return run(&flags, &rest, srcs, Some(dsts)).map(|_| ());
+	}
+	if let Some(dir) = &flags.flatten {
+		if !rest.is_empty() || !flags.subst.is_empty() || flags.watch.is_some() {
+			usage(None);
 		}
+		let (srcs, dsts) = flatten::plan(dir, &collect_srcs(&flags))
+			.unwrap_or_else(|e| fatal!("io", ["directory" => dir], "{}: {e}", dir.display()));
+		return run(&flags, &rest, srcs, Some(dsts)).map(|_| ());
 	}
-
-	if flags.backup {
-		fs::remove_dir_all(&cache_dir)?;
-		if flags.verbose {
-			eprintln!("removing directory ‘{}’", disp(&cache_dir));
+	if let Some(t) = &flags.nest_by {
+		if !rest.is_empty() || !flags.subst.is_empty() || flags.watch.is_some() {
+			usage(None);
 		}
+		/* Files the template has no directory for stay where they are */
+		let (srcs, dsts) = collect_srcs(&flags)
+			.into_iter()
+			.filter_map(|s| match t.apply(Path::new(&s)) {
+				Ok(d) => Some((s, d?.into_os_string().into_string().ok()?)),
+				Err(e) => fatal!("io", ["source" => &s], "{s}: {e}"),
+			})
+			.unzip();
+		return run(&flags, &rest, srcs, Some(dsts)).map(|_| ());
 	}
-
-	Ok(())
-}
-
-fn backup_srcs<'a, I>(flags: &Flags, cwd: &PathBuf, xs: I) -> Result<(), io::Error>
-where
-	I: Iterator<Item = &'a PathBuf>,
-{
-	for x in xs {
-		let data = require!(fs::metadata(x));
-		if data.is_dir() {
-			let rel_x = require!(x.strip_prefix("/"));
-			fs::create_dir_all(rel_x)?;
-			if flags.verbose {
-				eprintln!("created directory ‘{}/{}’", disp(cwd), rel_x.display());
-			}
-		} else {
-			if let Some(p) = x.parent() {
-				let rel_x = require!(p.strip_prefix("/"));
-				fs::create_dir_all(rel_x)?;
-				if flags.verbose {
-					eprintln!("created directory ‘{}/{}’", disp(cwd), rel_x.display());
-				}
-			}
-			let rel_x = require!(x.strip_prefix("/"));
-			fs::copy(x, rel_x)?;
-			if flags.verbose {
-				eprintln!(
-					"copied ‘{}’ -> ‘{}/{}’",
-					disp(x),
-					disp(cwd),
-					rel_x.display()
-				);
-			}
+	if let Some(path) = &flags.lint {
+		if !rest.is_empty() || !flags.subst.is_empty() || flags.watch.is_some() {
+			usage(None);
 		}
+		return run_lint(&flags, path, collect_srcs(&flags));
 	}
-
-	Ok(())
+	if flags.subst.is_empty() == rest.is_empty()
+		|| (flags.join.is_some() && flags.subst.is_empty())
+		|| (flags.key.is_some() && flags.join.is_none())
+	{
+		usage(None);
+	}
+	if let Some(dir) = &flags.watch {
+		if !flags.from.is_empty() {
+			usage(None);
+		}
+		return watch(&flags, &rest, dir);
+	}
+	run(&flags, &rest, collect_srcs(&flags), None).map(|_| ())
 }
 
-fn run_indiv(
-	srcs: &Vec<String>,
-	dsts: &mut Vec<String>,
-	flags: &Flags,
-	cmd: &OsString,
-	args: &[OsString],
-) -> Result<(), io::Error> {
-	for src in srcs {
-		let mut child = Command::new(cmd)
-			.args(args)
-			.stdin(Stdio::piped())
-			.stdout(Stdio::piped())
-			.spawn()
-			.unwrap_or_else(|e| {
-				err!("Failed to spawn utility: “{}”: {e}", cmd.to_str().unwrap());
-			});
-
-		let mut components = vec![];
-		{
-			let mut ci = child.stdin.take().unwrap_or_else(|| {
-				err!("Could not open the child process’ stdin");
-			});
-			let s;
-			if flags.basename {
-				components = Path::new(src).components().collect_vec();
-				s = components.pop().unwrap().as_os_str().to_str().unwrap();
+/* Collect sources from standard input, or from all the given files one after
+the other */
+fn collect_srcs(flags: &Flags) -> Vec<String> {
+	#[cfg(feature = "clipboard")]
+	if flags.from_clipboard {
+		let data = clipboard::paste().unwrap_or_else(|e| fatal!("clipboard", [], "{e}"));
+		return read_srcs(flags, &data[..]);
+	}
+	if flags.from.is_empty() {
+		read_srcs(flags, io::stdin().lock())
+	} else {
+		let mut srcs = Vec::new();
+		for p in flags.from.iter() {
+			let f = if p == Path::new("-") {
+				Box::new(io::stdin().lock()) as Box<dyn Read>
 			} else {
-				s = src;
-			}
-			write!(
-				ci,
-				"{}",
-				if flags.encode {
-					encode_string(s)
-				} else {
-					s.to_string()
-				}
-			)?;
+				Box::new(fs::File::open(p).unwrap_or_else(|e| {
+					fatal!("io", ["file" => p], "{}: {e}", p.to_string_lossy());
+				}))
+			};
+			srcs.extend(read_srcs(flags, BufReader::new(f)));
 		}
+		srcs
+	}
+}
 
-		let mut co = child.stdout.take().unwrap_or_else(|| {
-			err!("Count not open the child process’ stdout.");
-		});
-		let mut s = String::with_capacity(src.len());
-		co.read_to_string(&mut s)?;
-		let s = if flags.encode {
-			decode_string(s.as_str())
-		} else {
-			s
-		};
+/* Check the names of the input files against the rules of --lint without
+moving anything, and write a plan renaming the files that break them to the
+standard output, in the format read by ‘--import tsv’. */
+fn run_lint(flags: &Flags, path: &Path, srcs: Vec<String>) -> Result<(), io::Error> {
+	let rules = lint::read(path)
+		.unwrap_or_else(|e| fatal!("lint", ["rules" => path], "{}: {e}", path.display()));
 
-		if flags.basename {
-			let path = components.iter().collect::<PathBuf>().join(s);
-			dsts.push(path.to_str().unwrap().to_string());
-		} else {
-			dsts.push(s);
+	let mut out = BufWriter::new(io::stdout().lock());
+	let mut n = 0;
+	for s in srcs.iter().filter(|s| exists(flags, s)) {
+		let p = Path::new(s);
+		let Some(name) = p.file_name().and_then(|n| n.to_str()) else {
+			continue;
+		};
+		let broken = lint::check(&rules, name);
+		for b in broken.iter() {
+			warning!("lint", ["source" => p], "“{s}” {b}");
+		}
+		if broken.is_empty() {
+			continue;
 		}
+		n += 1;
 
-		/* If the process failed, it is expected to print an error message; as such,
-		   we exit directly. */
-		if !child.wait()?.success() {
-			process::exit(1);
+		let fixed = lint::fix(&rules, name);
+		if fixed != name {
+			let d = p.with_file_name(fixed);
+			writeln!(
+				out,
+				"{}\t{}",
+				import::escape(s),
+				import::escape(&d.to_string_lossy())
+			)?;
 		}
 	}
+	out.flush()?;
 
+	if n > 0 {
+		fatal!("lint", [], "{n} file(s) break the naming rules");
+	}
 	Ok(())
 }
 
-fn run_multi(
-	srcs: &Vec<String>,
-	dsts: &mut Vec<String>,
-	flags: &Flags,
-	cmd: &OsString,
-	args: &[OsString],
-) -> Result<(), io::Error> {
-	let mut child = Command::new(cmd)
-		.args(args)
-		.stdin(Stdio::piped())
-		.stdout(Stdio::piped())
-		.spawn()
-		.unwrap_or_else(|e| {
-			err!("Failed to spawn utility “{}”: {e}", cmd.to_str().unwrap());
-		});
-
-	/* Pass the source files to the child process. */
-	let mut components_vec = VecDeque::with_capacity(srcs.len());
-
-	{
-		let ci = child.stdin.take().unwrap_or_else(|| {
-			err!("Could not open the child process’ stdin");
-		});
-		let mut ci = BufWriter::new(ci);
-		for src in srcs {
-			let s = if flags.basename {
-				let components = Path::new(src).components().collect_vec();
-				components_vec.push_back(components);
-				components_vec
-					.back_mut()
-					.unwrap()
-					.pop()
-					.unwrap()
-					.as_os_str()
-					.to_str()
-					.unwrap()
-			} else {
-				src
-			};
-
-			write!(
-				ci,
-				"{}",
-				if flags.encode {
-					encode_string(s)
-				} else {
-					s.to_owned()
-				}
-			)?;
-			ci.write_all(if flags.nul && !flags.encode {
-				&[b'\0']
-			} else {
-				&[b'\n']
-			})?;
-		}
+fn read_srcs(flags: &Flags, mut r: impl Read) -> Vec<String> {
+	let mut data = Vec::new();
+	require!(r.read_to_end(&mut data));
+	let srcs = records(&data, terminator(flags.nul))
+		.map(|x| str::from_utf8(x).map(str::to_owned))
+		.collect::<Result<Vec<_>, _>>();
+	let srcs = require!(srcs);
+	if !flags.uri {
+		return srcs;
 	}
+	srcs.into_iter()
+		.filter_map(|s| uri::decode(&s).unwrap_or_else(|e| fatal!("bad-uri", [], "“{s}”: {e}")))
+		.collect()
+}
 
-	/* Read the destination file list from the process. */
-	let co = child.stdout.take().unwrap_or_else(|| {
-		err!("Count not open the child process’ stdout.");
-	});
-	let groups = co
-		.bytes()
-		.map(|x| require!(x))
-		.group_by(|b| is_terminal(flags.nul && !flags.encode, b));
-	groups
-		.into_iter()
-		.filter_map(|(x, y)| match x {
-			true => None,
-			false => Some(y),
+/* Split ‘data’ into the records terminated by ‘sep’.  Runs of terminators
+count as one, so there are never any empty records, and the last record needs
+no terminator. */
+fn records(data: &[u8], sep: u8) -> impl Iterator<Item = &[u8]> {
+	let mut start = 0;
+	memchr::memchr_iter(sep, data)
+		.chain(iter::once(data.len()))
+		.map(move |i| {
+			let r = &data[start..i];
+			start = i + 1;
+			r
 		})
-		.for_each(|x| {
-			let dst = require!(String::from_utf8(x.collect_vec()));
-
-			let s = if flags.basename {
-				let components = require!(components_vec.pop_front(), "WOW");
-				let path = components.iter().collect::<PathBuf>().join(dst);
-				path.to_str().unwrap().to_string()
-			} else {
-				dst
-			};
+		.filter(|r| !r.is_empty())
+}
 
-			dsts.push(if flags.encode { decode_string(&s) } else { s });
-		});
+/* Process every file that appears in the directory ‘dir’ as if it were given
+on the standard input, for as long as we are not killed.  The files we move
+into ‘dir’ ourselves appear in it too, so we remember them to not process
+them over and over again. */
+#[cfg(target_os = "linux")]
+fn watch(flags: &Flags, rest: &[OsString], dir: &Path) -> Result<(), io::Error> {
+	let dir = fs::canonicalize(dir)
+		.map_err(|e| io::Error::new(e.kind(), format!("{}: {e}", dir.to_string_lossy())))?;
+	let mut w = watch::Watcher::new(&dir)
+		.map_err(|e| io::Error::new(e.kind(), format!("{}: {e}", dir.to_string_lossy())))?;
+	let mut ours: HashMap<PathBuf, Option<Id>> = HashMap::new();
 
-	/* If the process failed, it is expected to print an error message; as such,
-	   we exit directly. */
-	if !child.wait()?.success() {
-		process::exit(1);
+	loop {
+		let names = w.wait()?;
+		ours.retain(|p, id| identity(p) == *id);
+		let srcs = names
+			.into_iter()
+			.map(|n| dir.join(n))
+			.unique()
+			.filter(|p| {
+				let id = identity(p);
+				id.is_some() && ours.get(p).is_none_or(|x| *x != id)
+			})
+			.filter_map(|p| p.to_str().map(String::from))
+			.collect_vec();
+		if srcs.is_empty() {
+			continue;
+		}
+		for d in run(flags, rest, srcs, None)? {
+			let id = identity(&d);
+			ours.insert(d, id);
+		}
 	}
-
-	Ok(())
 }
 
-fn encode_string(s: &str) -> String {
-	s.chars()
-		.flat_map(|c| {
-			let cs = match c {
-				'\\' => ['\\', '\\'],
-				'\n' => ['\\', 'n'],
-				_ => [c, '\0'],
-			};
-			cs.into_iter()
-				.enumerate()
-				.filter(|(i, c)| *i != 1 || *c != '\0')
-				.map(|(_, c)| c)
-		})
-		.collect::<String>()
+#[cfg(not(target_os = "linux"))]
+fn watch(_: &Flags, _: &[OsString], _: &Path) -> Result<(), io::Error> {
+	fatal!(
+		"unsupported",
+		[],
+		"Watching directories is only supported on Linux"
+	);
 }
 
-fn decode_string(s: &str) -> String {
-	let mut pv = false;
+/* Map the files ‘srcs’ to their destinations and carry out the moves, copies,
+links, or removals, returning the destinations.  Plans read with --import come
+with their destinations in ‘imported’ instead. */
+fn run(
+	flags: &Flags,
+	rest: &[OsString],
+	srcs: Vec<String>,
+	imported: Option<Vec<String>>,
+) -> Result<Vec<PathBuf>, io::Error> {
+	let srcs = if flags.map_sources {
+		srcs.iter().map(|s| map_prefix(flags, s)).collect()
+	} else {
+		srcs
+	};
+	let n = srcs.len();
+	let import = imported.is_some();
+	let (srcs, imported): (Vec<_>, Vec<_>) = srcs
+		.into_iter()
+		.zip(imported.map_or_else(|| vec![None; n], |ds| ds.into_iter().map(Some).collect()))
+		.filter(|(s, _)| exists(flags, s))
+		.unzip();
+	let skipped = n - srcs.len();
 
-	s.chars()
-		.map(|c| {
-			Ok(match (pv, c) {
-				(true, '\\') => {
-					pv = false;
-					Some('\\')
-				}
-				(true, 'n') => {
-					pv = false;
-					Some('\n')
+	/* Remember which files we were given, so that we can tell if they get
+	replaced while the command runs (as the user edits the list, say). */
+	let ids = srcs.iter().map(|s| identity(Path::new(s))).collect_vec();
+
+	/* With --relative-to the command only gets to see (and edit) the part of
+	each path below the source root. */
+	let inputs = match &flags.relative_to {
+		Some((src_root, _)) => strip_root(&srcs, src_root),
+		None => srcs.clone(),
+	};
+
+	if flags.numbered && (flags.individual || rest.is_empty()) {
+		fatal!(
+			"usage",
+			[],
+			"The --numbered option requires a command that is run once for all files"
+		);
+	}
+	if flags.jobs > 1 && (!flags.individual || rest.is_empty()) {
+		fatal!(
+			"usage",
+			[],
+			"The --jobs option requires the --individual option"
+		);
+	}
+	if flags.partial_apply && (!flags.individual || rest.is_empty()) {
+		fatal!(
+			"usage",
+			[],
+			"The --partial-apply option requires the --individual option"
+		);
+	}
+	if flags.metadata && flags.emit.is_some() {
+		fatal!(
+			"usage",
+			[],
+			"The --metadata option cannot be used with --emit-script"
+		);
+	}
+	if flags.nest_by.is_some() && flags.emit.is_some() {
+		fatal!(
+			"usage",
+			[],
+			"The --nest-by option cannot be used with --emit-script"
+		);
+	}
+	if flags.link_dest.is_some() && flags.emit.is_some() {
+		fatal!(
+			"usage",
+			[],
+			"The --link-dest option cannot be used with --emit-script"
+		);
+	}
+	if let Some(l) = flags.link_dest.as_ref().filter(|l| !l.dir.is_dir()) {
+		fatal!(
+			"io",
+			["link-dest" => &l.dir],
+			"{}: not a directory",
+			l.dir.to_string_lossy()
+		);
+	}
+	if flags.limit.is_some() && flags.sample.is_some() {
+		fatal!(
+			"usage",
+			[],
+			"The --limit and --sample options are mutually exclusive"
+		);
+	}
+	if flags.allow_mismatch && !flags.numbered {
+		fatal!(
+			"usage",
+			[],
+			"The --allow-length-mismatch option requires the --numbered option"
+		);
+	}
+
+	let mut dsts = Vec::with_capacity(srcs.len());
+	let dropped = match rest.split_first() {
+		_ if import => {
+			dsts.extend(imported.into_iter().flatten());
+			Vec::new()
+		}
+		None => {
+			run_subst(&inputs, &mut dsts, flags);
+			Vec::new()
+		}
+		Some((cmd, args)) if flags.individual => {
+			let failed = run_indiv(&inputs, &mut dsts, flags, cmd, args)?;
+			let ok = inputs.len() - failed.len();
+			if !failed.is_empty()
+				&& !flags.dryrun
+				&& (ok == 0
+					|| !ask(
+						flags,
+						false,
+						format_args!(
+							"Apply the {ok} file(s) that were mapped successfully? [y/N] "
+						),
+					)) {
+				fatal!(
+					exit EXIT_CHILD_FAILED,
+					"child-failed",
+					[],
+					"The command “{}” failed for {} file(s); no files were touched",
+					cmd.to_string_lossy(),
+					failed.len()
+				);
+			}
+			failed
+		}
+		Some((cmd, args)) => run_multi(&inputs, &mut dsts, flags, cmd, args)?,
+	};
+
+	/* Files that the command left out are left alone */
+	let (srcs, ids) = if dropped.is_empty() {
+		(srcs, ids)
+	} else {
+		for &i in dropped.iter() {
+			warning!(
+				"dropped",
+				["source" => &srcs[i]],
+				"No output file was given for “{}”; leaving it untouched",
+				srcs[i]
+			);
+		}
+		srcs.into_iter()
+			.zip(ids)
+			.enumerate()
+			.filter(|(i, _)| dropped.binary_search(i).is_err())
+			.map(|(_, x)| x)
+			.unzip()
+	};
+
+	/* The directives following the output files are split off before anything
+	else gets to look at them */
+	let mut directives = HashMap::new();
+	if flags.metadata {
+		for (s, d) in srcs.iter().zip(dsts.iter_mut()) {
+			let (dst, ds) = directives::split(d).unwrap_or_else(|e| {
+				fatal!("directive", ["source" => s], "“{d}”: {e}");
+			});
+			let dst = dst.to_string();
+			if !ds.is_empty() {
+				directives.insert(s.clone(), ds);
+			}
+			*d = dst;
+		}
+	}
+
+	if let Some((_, dst_root)) = &flags.relative_to {
+		let dst_root = normalize_path(&env::current_dir()?.join(dst_root));
+		dsts = dsts
+			.iter()
+			.map(|d| dst_root.join(d).to_str().unwrap().to_string())
+			.collect();
+	}
+	if !flags.map_prefix.is_empty() {
+		dsts = dsts.iter().map(|d| map_prefix(flags, d)).collect();
+	}
+
+	if flags.mrm {
+		let pending = remove_unkept(flags, &vfs::Std, &srcs, &dsts)?;
+		report_skipped(flags, skipped);
+		dry_run_done(flags, pending);
+		return Ok(Vec::new());
+	}
+
+	if dsts.len() != srcs.len() {
+		fatal!(
+			"length-mismatch",
+			[],
+			"Files have been added or removed during editing"
+		);
+	}
+	if !flags.numbered && !import && !rest.is_empty() {
+		report_reordered(&srcs, &dsts);
+	}
+	if !flags.allow_weird {
+		report_weird(&srcs, &dsts);
+	}
+	let (srcs, dsts, ids) = drop_changed(flags, srcs, dsts, ids);
+	let (srcs, dsts, ids) = select(flags, srcs, dsts, ids);
+
+	let dir = tempdir()?;
+	if flags.verbose {
+		eprintln!("created directory ‘{}’", dir.path().display());
+	}
+
+	let canon = srcs
+		.iter()
+		.map(fs::canonicalize)
+		.collect::<Result<Vec<_>, _>>()?;
+	let directives: HashMap<PathBuf, Directives> = srcs
+		.iter()
+		.zip(canon.iter())
+		.filter_map(|(s, c)| Some((c.clone(), directives.remove(s)?)))
+		.collect();
+	let srcs = canon;
+	let src_set: HashSet<&PathBuf> = srcs.iter().collect();
+	let ids: HashMap<&PathBuf, Option<Id>> = srcs.iter().zip(ids).collect();
+	let base = match &flags.chdir {
+		Some(dir) => fs::canonicalize(dir).unwrap_or_else(|e| {
+			fatal!("io", ["directory" => dir], "{}: {e}", dir.to_string_lossy());
+		}),
+		None => env::current_dir()?,
+	};
+
+	/* With --merge-identical, sources whose contents are identical to those
+	of the first source moved to the same destination are trashed instead. */
+	let mut firsts: HashMap<PathBuf, &PathBuf> = HashMap::with_capacity(srcs.len());
+	let mut dups = Vec::new();
+
+	let mut pairs = Vec::with_capacity(srcs.len());
+	for (s, d) in srcs.iter().zip(dsts) {
+		let d = normalize_path(&base.join(Path::new(&d)));
+
+		/* Like mv(1), treat an existing directory as the directory to move into.
+		Directories that are themselves being moved are left alone, or swapping
+		two directories would be impossible. */
+		let d = match s.file_name() {
+			Some(name) if flags.into_dirs && d.is_dir() && !src_set.contains(&d) => d.join(name),
+			_ => d,
+		};
+
+		let dup =
+			|k: &&&PathBuf| flags.merge_identical && **k != s && dups.iter().all(|(x, _)| x != s);
+		if let Some(k) = firsts.get(&d).filter(dup) {
+			let same = same_contents(k, s).unwrap_or_else(|e| {
+				fatal!("io", ["source" => s], "{}: {e}", s.to_string_lossy());
+			});
+			if same {
+				dups.push((s.clone(), d));
+				continue;
+			}
+		}
+		firsts.entry(d.clone()).or_insert(s);
+
+		/* With --interactive, existing files are only replaced once the user
+		has seen how they differ from the files replacing them */
+		if flags.interactive
+			&& !flags.dryrun
+			&& flags.emit.is_none()
+			&& !src_set.contains(&d)
+			&& fs::symlink_metadata(&d).is_ok()
+			&& !ask(
+				flags,
+				false,
+				format_args!(
+					"Output file ‘{}’ already exists:\n{}Overwrite it with ‘{}’? [y/N] ",
+					disp(&d),
+					conflict::compare(s, &d),
+					disp(s)
+				),
+			) {
+			warning!(
+				"kept-output",
+				["source" => s, "destination" => &d],
+				"Kept the existing output file “{}”",
+				d.to_string_lossy()
+			);
+			continue;
+		}
+		pairs.push((s.clone(), d));
+	}
+	let mut plan = Plan::from_pairs(dir.path(), pairs).unwrap_or_else(|e| match &e {
+		plan::Error::DuplicateSource(s) => fatal!("duplicate-input", ["source" => s], "{e}"),
+		plan::Error::DuplicateDestination(d) => {
+			fatal!("duplicate-output", ["destination" => d], "{e}")
+		}
+		_ => fatal!("io", [], "{e}"),
+	});
+	let issues = plan.validate(&vfs::Std);
+
+	/* Moving whole directories instead of their entries one by one saves a lot
+	of work on large trees, but leaves nothing for the options that act on each
+	file moved to act on.  The working directory is never moved from under us. */
+	let whole = !flags.mcp
+		&& !flags.mln
+		&& !flags.check
+		&& !flags.merge
+		&& !flags.fix_symlinks
+		&& !flags.metadata
+		&& flags.quarantine
+		&& flags.emit.is_none()
+		&& flags.owner.is_none()
+		&& flags.group.is_none()
+		&& flags.mode.is_none()
+		&& flags.dir_mode.is_none();
+	let cwd = env::current_dir()?;
+	let collapsed: HashMap<PathBuf, Collapsed> = if whole {
+		plan.collapse_dirs(&vfs::Std, |d| cwd.starts_with(d))
+			.into_iter()
+			.map(|(p, srcs)| {
+				let c = Collapsed {
+					dst_mode: vfs::Std.metadata(&p.dst)?.mode,
+					srcs,
+				};
+				Ok((p.src, c))
+			})
+			.collect::<io::Result<_>>()?
+	} else {
+		HashMap::new()
+	};
+
+	/* Taken after collapsing, so that the digest is that of the moves which
+	are actually made */
+	let digest = plan.digest().iter().map(|b| format!("{b:02x}")).join("");
+	if flags.verbose {
+		eprintln!("plan digest ‘{digest}’");
+	}
+	if let Some(d) = flags.expect_digest.as_ref().filter(|d| **d != digest) {
+		fatal!(
+			"digest-mismatch",
+			[],
+			"The plan has the digest “{digest}” instead of the expected “{d}”"
+		);
+	}
+	let size = |p: &plan::Pair| check::digest(&p.src, false, false).map_or(0, |d| d.size);
+	let ps = match flags.order {
+		Order::AsGiven => plan.into_pairs_by(|_| 0),
+		Order::DepthFirst => plan.into_pairs(),
+		Order::LargeFirst => plan.into_pairs_by(|p| Reverse(size(p))),
+		Order::SmallFirst => plan.into_pairs_by(size),
+	}
+	.into_iter()
+	.map(|p| (p.src, p.stage, p.dst))
+	.collect_vec();
+
+	let dst_set: HashSet<&PathBuf> = ps.iter().map(|(_, _, d)| d).collect();
+	if let Some((s, _)) = dups.iter().find(|(s, _)| dst_set.contains(s)) {
+		fatal!(
+			"duplicate-output",
+			["source" => s],
+			"Refusing to trash the duplicate “{}” which is also an output file",
+			s.to_string_lossy()
+		);
+	}
+
+	/* Existing destinations are replaced like with mv(1), and missing sources
+	were already dealt with according to --missing, so only files that went
+	missing since then are left. */
+	let mut nested = Vec::new();
+	let mut split = Vec::new();
+	let mut collisions = Vec::new();
+	for i in issues.iter() {
+		match i {
+			Issue::MissingSource(s) => fatal!("missing-input", ["source" => s], "{i}"),
+			Issue::NestedDestination { dst, outer } => nested.push((dst, outer, i)),
+			Issue::SplitSource { src, outer } => split.push((src, outer, i)),
+			Issue::CaseCollision(a, b) => collisions.push((a.as_path(), b.as_path())),
+			_ => {}
+		}
+	}
+	for (d, o, i) in nested.iter() {
+		warning!(
+			"nested-output",
+			["destination" => d, "destination" => o],
+			"{i}"
+		);
+	}
+	if !nested.is_empty() {
+		fatal!(
+			"nested-output",
+			[],
+			"Refusing to start with {} output file(s) inside of others that are not directories",
+			nested.len()
+		);
+	}
+
+	/* Moving files out of a directory that is moved itself works, as they are
+	always moved out first, but it is easily done by accident when the files
+	were meant to stay with the directory.  Copies leave their sources be. */
+	if !flags.mcp && !flags.mln && !split.is_empty() {
+		for (s, o, i) in split.iter() {
+			warning!("split-move", ["source" => s, "source" => o], "{i}");
+		}
+		if !flags.dryrun
+			&& flags.emit.is_none()
+			&& !ask(
+				flags,
+				true,
+				format_args!(
+			"Move the {} file(s) out of their directories before moving the directories? [y/N] ",
+			split.len()
+		),
+			) {
+			fatal!(
+				exit EXIT_ABORTED,
+				"split-move",
+				[],
+				"Refusing to split {} file(s) off of directories that are moved as well",
+				split.len()
+			);
+		}
+	}
+
+	let collisions = preflight::case_collisions(flags.ci_duplicates, collisions.into_iter());
+	for (a, b) in collisions.iter() {
+		warning!(
+			"case-collision",
+			["destination" => a, "destination" => b],
+			"Output files “{}” and “{}” only differ in case",
+			a.to_string_lossy(),
+			b.to_string_lossy()
+		);
+	}
+	if !collisions.is_empty() {
+		fatal!(
+			"case-collision",
+			[],
+			"Refusing to start with {} pair(s) of output files that only differ in case",
+			collisions.len()
+		);
+	}
+
+	if let Some(root) = &flags.restrict {
+		let root = fs::canonicalize(root).unwrap_or_else(|e| {
+			fatal!("io", ["directory" => root], "{}: {e}", root.to_string_lossy());
+		});
+		let bad = preflight::escaping(&root, ps.iter().map(|(_, _, d)| d.as_path()));
+		for d in bad.iter() {
+			warning!(
+				"restricted",
+				["destination" => d, "directory" => &root],
+				"Output file “{}” is not under “{}”",
+				d.to_string_lossy(),
+				root.to_string_lossy()
+			);
+		}
+		if !bad.is_empty() {
+			fatal!(
+				"restricted",
+				["directory" => &root],
+				"Refusing to start with {} output file(s) outside of “{}”",
+				bad.len(),
+				root.to_string_lossy()
+			);
+		}
+	}
+
+	/* Links leave their targets alone, so only their destinations matter */
+	report_unmodifiable(preflight::check(
+		flags.mcp,
+		ps.iter()
+			.filter(|_| !flags.mln)
+			.map(|(s, _, _)| s.as_path())
+			.chain(dups.iter().map(|(s, _)| s.as_path())),
+		ps.iter().map(|(_, _, d)| d.as_path()),
+	));
+	if flags.check_open {
+		report_open(ps.iter().map(|(s, _, _)| s.as_path()));
+	}
+	for (d, other) in preflight::bystanders(
+		flags.merge,
+		ps.iter().map(|(s, _, _)| s.as_path()),
+		ps.iter().map(|(_, _, d)| d.as_path()),
+	) {
+		match other {
+			Some(o) => warning!(
+				"bystander",
+				["destination" => d, "file" => &o],
+				"Output file “{}” is the existing file “{}” on its filesystem",
+				d.to_string_lossy(),
+				o.to_string_lossy()
+			),
+			None => warning!(
+				"bystander",
+				["destination" => d],
+				"Output file “{}” already exists",
+				d.to_string_lossy()
+			),
+		}
+	}
+
+	if flags.review_stats {
+		report_stats(ps.iter().map(|(s, _, d)| (s.as_path(), d.as_path())));
+	}
+	if flags.mcp && (flags.dryrun || flags.review_stats) && flags.emit.is_none() {
+		report_usage(&ps);
+	}
+	if let Some(n) = flags.copy_threshold.filter(|_| flags.emit.is_none()) {
+		check_copy_threshold(flags, n, dir.path(), &ps);
+	}
+
+	/* Devices mounted over protocols such as MTP only reliably support
+	streaming whole files, so with any of them involved we fall back to the
+	conservative backend for everything. */
+	let ms = mounts::mounts();
+	let degraded = mounts::degraded(
+		&ms,
+		ps.iter().flat_map(|(s, _, d)| [s.as_path(), d.as_path()]),
+	);
+	let vfs: &dyn Filesystem = if flags.conservative || !degraded.is_empty() {
+		&vfs::Conservative
+	} else {
+		&vfs::Std
+	};
+	let retrying = vfs::Retrying::new(
+		vfs,
+		flags.retries,
+		flags.retry_delay.unwrap_or(Duration::from_millis(100)),
+	);
+	let vfs: &dyn Filesystem = &retrying;
+
+	/* Snapshots take the place of the backup in the cache directory, and are
+	kept around for as long as the user wants to go back to them */
+	let ts = require!(SystemTime::now().duration_since(UNIX_EPOCH))
+		.as_nanos()
+		.to_string();
+	let backup = flags.backup && !flags.snapshot;
+	if flags.snapshot && !flags.dryrun && flags.emit.is_none() {
+		let snaps = snapshot::take(ps.iter().map(|(s, _, _)| s), &ts)
+			.unwrap_or_else(|e| fatal!("snapshot", [], "Could not take a snapshot: {e}"));
+		if flags.verbose {
+			for s in snaps {
+				eprintln!("created snapshot ‘{s}’");
+			}
+		}
+	}
+
+	let mut cache_dir = PathBuf::default();
+	if backup {
+		let cache_base = match env::var("XDG_CACHE_HOME") {
+			Ok(s) => {
+				if s.is_empty() {
+					get_default_config_path()
+				} else {
+					PathBuf::from(s)
+				}
+			}
+			_ => get_default_config_path(),
+		};
+		let mmv_name = option_env!("MMV_NAME").unwrap_or(MMV_DEFAULT_NAME);
+		let name = match &flags.session {
+			Some(s) => format!("{s}-{ts}"),
+			None => ts.clone(),
+		};
+		cache_dir = [
+			cache_base.as_path(),
+			Path::new(mmv_name),
+			Path::new(name.as_str()),
+		]
+		.iter()
+		.collect::<PathBuf>();
+		fs::create_dir_all(&cache_dir)?;
+
+		if flags.verbose {
+			eprintln!("created directory ‘{}’", cache_dir.display());
+		}
+
+		let cwd = require!(env::current_dir());
+		require!(env::set_current_dir(&cache_dir));
+		let srcs = ps.iter().flat_map(|(s, _, _)| match collapsed.get(s) {
+			Some(c) => c.srcs.iter().collect_vec(),
+			None => vec![s],
+		});
+		backup_srcs(flags, vfs, &cache_dir, srcs)?;
+		require!(env::set_current_dir(cwd));
+	}
+
+	let produced = ps.iter().map(|(_, _, d)| d.clone()).collect_vec();
+	let digests = if flags.check && !flags.dryrun && flags.emit.is_none() {
+		let ds = ps
+			.iter()
+			.map(|(s, _, _)| {
+				check::digest(s, flags.check_contents, false).unwrap_or_else(|e| {
+					fatal!("io", ["source" => s], "{}: {e}", s.to_string_lossy());
+				})
+			})
+			.collect_vec();
+		Some(ds)
+	} else {
+		None
+	};
+	let mut moved = vec![true; ps.len()];
+	if let Some(shell) = flags.emit {
+		let mut sc = Script::new(shell, !flags.mln);
+		if flags.mln {
+			for (s, _, d) in ps.iter() {
+				sc.link(flags.symbolic, Arg::Path(s), Arg::Path(d));
+			}
+		} else {
+			fn stage(t: &Path) -> Arg<'_> {
+				Arg::Stage(Path::new(t.file_name().unwrap()))
+			}
+			for (s, t, _) in ps.iter() {
+				if flags.mcp {
+					sc.copy(Arg::Path(s), stage(t));
+				} else {
+					sc.rename(Arg::Path(s), stage(t));
+				}
+			}
+			for (_, t, d) in ps.iter().rev() {
+				sc.rename(stage(t), Arg::Path(d));
+			}
+			for (s, _) in dups.iter() {
+				sc.remove(Arg::Path(s));
+			}
+		}
+		print!("{}", sc.finish());
+	} else if flags.dryrun {
+		if flags.group_by_dir {
+			report_by_dir(flags, ps.iter().map(|(s, _, d)| (s.as_path(), d.as_path())));
+		} else {
+			for (s, _, d) in ps.iter() {
+				print_pair(flags, action(flags), s, d);
+			}
+		}
+		for (s, d) in dups.iter() {
+			print_duplicate(flags, s, d);
+		}
+		if flags.tree {
+			/* Duplicates come first so that the destinations they share are
+			mapped to the files actually moved there */
+			let pairs = dups
+				.iter()
+				.map(|(s, d)| (s.as_path(), d.as_path()))
+				.chain(ps.iter().map(|(s, _, d)| (s.as_path(), d.as_path())))
+				.collect_vec();
+			eprint!("{}", tree::render(&pairs, !flags.mcp && !flags.mln)?);
+		}
+
+		/* The plan goes on the clipboard in the tsv format of --import,
+		so that it can be edited and run from there */
+		#[cfg(feature = "clipboard")]
+		if flags.to_clipboard {
+			let plan = ps
+				.iter()
+				.map(|(s, _, d)| {
+					format!(
+						"{}\t{}\n",
+						import::escape(&s.to_string_lossy()),
+						import::escape(&d.to_string_lossy())
+					)
+				})
+				.collect::<String>();
+			clipboard::copy(plan.as_bytes()).unwrap_or_else(|e| fatal!("clipboard", [], "{e}"));
+		}
+	} else if flags.mln {
+		if flags.nest_by.is_some() {
+			for (s, _, d) in ps.iter() {
+				create_parent(flags, vfs, d).unwrap_or_else(|e| {
+					PairError::new(flags, s, None, d, e).fatal();
+				});
+			}
+		}
+		for (s, _, d) in ps.iter() {
+			link_path(flags, vfs, s, d).unwrap_or_else(|e| {
+				PairError::new(flags, s, None, d, e).fatal();
+			});
+		}
+		if flags.verbose && flags.group_by_dir {
+			report_by_dir(flags, ps.iter().map(|(s, _, d)| (s.as_path(), d.as_path())));
+		}
+	} else {
+		let entries = ps
+			.iter()
+			.map(|(s, t, d)| (s.as_path(), t.as_path(), d.as_path()))
+			.collect_vec();
+		staging::write(dir.path(), flags.mcp, &entries, &dups)?;
+		let fixes = if flags.fix_symlinks {
+			symlinks::plan(&entries.iter().map(|(s, _, d)| (*s, *d)).collect_vec())?
+		} else {
+			Vec::new()
+		};
+		for (i, (s, t, d)) in ps.iter().enumerate() {
+			/* Check once more right before moving, in case something else is
+			modifying the tree while we work. */
+			let changed = match collapsed.get(s) {
+				Some(c) => c.srcs.iter().any(|x| identity(x) != ids[x]),
+				None => identity(s) != ids[s],
+			};
+			if changed {
+				let e = io::Error::other("file was replaced since the command was run");
+				match flags.changed {
+					Policy::Fail => staging_err(
+						dir.path(),
+						PairError::new(flags, s, Some(t), d, (s.clone(), e)),
+					),
+					Policy::Warn => PairError::new(flags, s, Some(t), d, (s.clone(), e)).warning(),
+					Policy::Skip => {}
+				}
+				moved[i] = false;
+				staging::mark_skipped(dir.path(), i).unwrap_or_else(|e| journal_err(dir.path(), e));
+				continue;
+			}
+			move_path(flags, vfs, s, t).unwrap_or_else(|e| {
+				staging_err(dir.path(), PairError::new(flags, s, Some(t), d, e))
+			});
+			staging::mark_done(dir.path(), i).unwrap_or_else(|e| journal_err(dir.path(), e));
+		}
+		staging::mark_staged(dir.path()).unwrap_or_else(|e| journal_err(dir.path(), e));
+		if flags.nest_by.is_some() {
+			for ((s, t, d), _) in ps.iter().zip(moved.iter()).filter(|(_, m)| **m) {
+				create_parent(flags, vfs, d).unwrap_or_else(|e| {
+					staging_err(dir.path(), PairError::new(flags, s, Some(t), d, e))
+				});
+			}
+		}
+		for (i, (s, t, d)) in ps
+			.iter()
+			.enumerate()
+			.zip(moved.iter())
+			.rev()
+			.filter(|(_, m)| **m)
+			.map(|(p, _)| p)
+		{
+			move_path(flags, vfs, t, d).unwrap_or_else(|e| {
+				staging_err(dir.path(), PairError::new(flags, s, Some(t), d, e))
+			});
+			staging::mark_done(dir.path(), i).unwrap_or_else(|e| journal_err(dir.path(), e));
+		}
+		if flags.verbose && flags.group_by_dir {
+			report_by_dir(
+				flags,
+				ps.iter()
+					.zip(moved.iter())
+					.filter(|(_, m)| **m)
+					.map(|((s, _, d), _)| (s.as_path(), d.as_path())),
+			);
+		}
+		for ((s, _, d), _) in ps.iter().zip(moved.iter()).filter(|(_, m)| **m) {
+			if let Some(c) = collapsed.get(s) {
+				vfs.set_mode(d, c.dst_mode).unwrap_or_else(|e| {
+					warning!("io", ["destination" => d], "{}: {e}", d.to_string_lossy());
+				});
+			}
+		}
+		if flags.owner.is_some() || flags.group.is_some() {
+			for ((_, _, d), _) in ps.iter().zip(moved.iter()).filter(|(_, m)| **m) {
+				if let Err(e) = owners::chown_all(d, flags.owner, flags.group) {
+					warning!("io", ["destination" => d], "{}: {e}", d.to_string_lossy());
+				} else if flags.verbose {
+					eprintln!("changed ownership of ‘{}’", disp(d));
+				}
+			}
+		}
+		if !flags.quarantine {
+			for ((_, _, d), _) in ps.iter().zip(moved.iter()).filter(|(_, m)| **m) {
+				strip_quarantine(d).unwrap_or_else(|e| {
+					warning!("io", ["destination" => d], "{}: {e}", d.to_string_lossy());
+				});
+			}
+		}
+		for ((s, _, d), _) in ps.iter().zip(moved.iter()).filter(|(_, m)| **m) {
+			let Some(ds) = directives.get(s) else {
+				continue;
+			};
+			match directives::apply(d, ds) {
+				Ok(()) if flags.verbose => eprintln!("changed metadata of ‘{}’", disp(d)),
+				Ok(()) => {}
+				Err(e) => warning!("io", ["destination" => d], "{}: {e}", d.to_string_lossy()),
+			}
+		}
+		trash_duplicates(flags, &dups);
+		for f in fixes.iter() {
+			symlinks::apply(f).unwrap_or_else(|e| {
+				fatal!(
+					"io",
+					["file" => &f.link],
+					"{}: {e}",
+					f.link.to_string_lossy()
+				);
+			});
+			if flags.verbose {
+				print_pair(flags, "retargeted", &f.link, &f.target);
+			}
+		}
+		if flags.prune {
+			prune_empty(flags, vfs, ps.iter().map(|(s, _, _)| s.as_path()))?;
+		}
+	}
+
+	if let Some(digests) = digests {
+		verify(flags, &ps, &digests, &moved);
+	}
+
+	for m in degraded.iter().filter(|_| !flags.dryrun) {
+		warning!(
+			"degraded-mount",
+			["directory" => m],
+			"{}",
+			mounts::degraded_warning(m)
+		);
+	}
+
+	if backup {
+		fs::remove_dir_all(&cache_dir)?;
+		if flags.verbose {
+			eprintln!("removing directory ‘{}’", disp(&cache_dir));
+		}
+	}
+
+	#[cfg(feature = "indexer")]
+	if flags.notify_indexer && !flags.dryrun {
+		if let Err(e) = indexer::notify(&produced) {
+			warning!("indexer", [], "Could not notify the search indexer: {e}");
+		}
+	}
+
+	if retrying.retried() > 0 {
+		warning!(
+			"retried",
+			[],
+			"Retried {} operation(s) after transient errors",
+			retrying.retried()
+		);
+	}
+	if flags.timings {
+		timings::report();
+	}
+	#[cfg(feature = "notify")]
+	notify::summarize(format!("{} file(s) {}", ps.len(), action(flags)));
+	/* Runs are recorded in the history for --undo, except for those that
+	themselves undo a run */
+	if !flags.dryrun && flags.emit.is_none() && !flags.mcp && !flags.mln && flags.undo.is_none() {
+		let pairs = ps
+			.iter()
+			.zip(moved.iter())
+			.filter(|((s, _, d), m)| **m && s != d)
+			.map(|((s, _, d), _)| (s.as_path(), d.as_path()))
+			.collect_vec();
+		if !pairs.is_empty() {
+			let time = require!(SystemTime::now().duration_since(UNIX_EPOCH)).as_secs();
+			let command = env::args_os()
+				.map(|a| a.to_string_lossy().into_owned())
+				.join(" ");
+			let session = flags.session.as_deref();
+			if let Err(e) = history::record(time as i64, &command, session, &pairs) {
+				warning!(
+					"history",
+					[],
+					"Could not record the run in the history: {e}"
+				);
+			}
+		}
+	}
+
+	report_skipped(flags, skipped);
+	dry_run_done(flags, !dups.is_empty() || ps.iter().any(|(s, _, d)| s != d));
+	Ok(produced)
+}
+
+fn list_history(flags: &Flags) -> Result<(), io::Error> {
+	let runs = history::runs()?;
+	for r in runs
+		.iter()
+		.filter(|r| flags.session.is_none() || r.session == flags.session)
+	{
+		let mut time = String::new();
+		nest::strftime(&mut time, "%Y-%m-%d %H:%M:%S", r.time);
+		println!(
+			"{}\t{time}\t{}\t{}\t{} file(s)\t{}",
+			r.id,
+			r.session.as_deref().unwrap_or("-"),
+			disp(&r.cwd),
+			r.pairs.len(),
+			r.command
+		);
+	}
+	Ok(())
+}
+
+/* Move the files of the run ‘id’ in the history, or of all the runs of the
+session named ‘id’, back to where they came from.  Later runs that moved any of
+the same files (or files inside of them) have to be undone first, or undoing
+these would pull the files from under them. */
+fn undo(flags: &Flags, id: &str) -> Result<(), io::Error> {
+	let runs = history::runs()?;
+	let chosen = |r: &history::Run| match id.parse::<u64>() {
+		Ok(n) => r.id == n,
+		Err(_) => r.session.as_deref() == Some(id),
+	};
+	let Some(i) = runs.iter().position(chosen) else {
+		match id.parse::<u64>() {
+			Ok(_) => fatal!("unknown-run", [], "There is no run {id} in the history"),
+			Err(_) => fatal!(
+				"unknown-run",
+				[],
+				"There is no session “{id}” in the history"
+			),
+		}
+	};
+	let mut chain = vec![&runs[i]];
+	let mut later = Vec::new();
+	for r in runs[i + 1..].iter() {
+		if chosen(r) {
+			chain.push(r);
+		} else if chain.iter().any(|c| c.conflicts(r)) {
+			chain.push(r);
+			later.push(r);
+		}
+	}
+	if !later.is_empty() && !flags.force_chain {
+		for r in later.iter() {
+			warning!(
+				"undo-conflict",
+				[],
+				"Run {} moved some of the same files: {}",
+				r.id,
+				r.command
+			);
+		}
+		fatal!(
+			"undo-conflict",
+			[],
+			"Refusing to undo “{id}” before the {} later run(s) that conflict with it; see --force-chain",
+			later.len()
+		);
+	}
+
+	for r in chain.into_iter().rev() {
+		if flags.verbose {
+			eprintln!("undoing run {}", r.id);
+		}
+		let utf8 = |p: &PathBuf| match p.to_str() {
+			Some(s) => s.to_string(),
+			None => fatal!(
+				"encoding",
+				["file" => p],
+				"“{}” is not valid UTF-8",
+				p.to_string_lossy()
+			),
+		};
+		let (srcs, dsts) = r.pairs.iter().map(|(s, d)| (utf8(d), utf8(s))).unzip();
+		run(flags, &[], srcs, Some(dsts))?;
+		if !flags.dryrun {
+			history::forget(r.id)?;
+		}
+	}
+	Ok(())
+}
+
+/* Dry runs exit with their own status when there is anything left to do, so
+that they can be used to check that files are already named as they should
+be.  The emitted scripts are meant to be piped into a shell, so those runs are
+left alone, as are the repeated runs of --watch. */
+fn dry_run_done(flags: &Flags, pending: bool) {
+	if pending && flags.dryrun && flags.emit.is_none() && flags.watch.is_none() {
+		process::exit(EXIT_PENDING);
+	}
+}
+
+type Id = (u64, u64);
+
+fn identity(p: &Path) -> Option<Id> {
+	fs::metadata(p).ok().map(|m| (m.dev(), m.ino()))
+}
+
+/* Remove the flag macOS puts on downloaded files from ‘path’ and everything in
+it, so that moving files out of the downloads directory can double as
+vetting them. */
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+fn strip_quarantine(path: &Path) -> io::Result<()> {
+	use rustix::{fs::lremovexattr, io::Errno};
+
+	/* A missing attribute is ENOATTR on macOS, which Linux calls ENODATA */
+	#[cfg(target_os = "macos")]
+	const MISSING: Errno = Errno::NOATTR;
+	#[cfg(not(target_os = "macos"))]
+	const MISSING: Errno = Errno::NODATA;
+
+	match lremovexattr(path, "com.apple.quarantine") {
+		Err(e) if e != MISSING && e != Errno::NOTSUP => return Err(e.into()),
+		_ => {}
+	}
+	if fs::symlink_metadata(path)?.is_dir() {
+		for e in fs::read_dir(path)? {
+			strip_quarantine(&e?.path())?;
+		}
+	}
+	Ok(())
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos")))]
+fn strip_quarantine(_: &Path) -> io::Result<()> {
+	Ok(())
+}
+
+/* Make sure that every file ended up where it should have, untouched on the way
+there, and that every file moved away is gone. */
+fn verify(
+	flags: &Flags,
+	ps: &[(PathBuf, PathBuf, PathBuf)],
+	before: &[check::Digest],
+	moved: &[bool],
+) {
+	let dsts: HashSet<&Path> = ps.iter().map(|(_, _, d)| d.as_path()).collect();
+	let mut bad = 0;
+	/* Merging into existing directories makes them hold more than was moved
+	into them */
+	let compare = !flags.merge;
+	for ((s, _, d), b) in ps
+		.iter()
+		.zip(before)
+		.zip(moved)
+		.filter(|(_, m)| **m)
+		.map(|(p, _)| p)
+	{
+		let mut problem = |msg: String| {
+			warning!("check-failed", ["source" => s, "destination" => d], "{msg}");
+			bad += 1;
+		};
+		let follow = flags.mln && flags.symbolic;
+		match check::digest(d, flags.check_contents, follow) {
+			Err(e) => problem(format!(
+				"“{}” is missing after the run: {e}",
+				d.to_string_lossy()
+			)),
+			Ok(a) if compare && a.size != b.size => problem(format!(
+				"“{}” holds {} bytes instead of the {} of “{}”",
+				d.to_string_lossy(),
+				a.size,
+				b.size,
+				s.to_string_lossy()
+			)),
+			Ok(a) if compare && a != *b => problem(format!(
+				"The contents of “{}” differ from those of “{}”",
+				d.to_string_lossy(),
+				s.to_string_lossy()
+			)),
+			Ok(_) => {}
+		}
+		if !flags.mcp
+			&& !flags.mln
+			&& !dsts.contains(s.as_path())
+			&& fs::symlink_metadata(s).is_ok()
+		{
+			problem(format!(
+				"“{}” still exists after being moved",
+				s.to_string_lossy()
+			));
+		}
+	}
+
+	if bad > 0 {
+		fatal!(
+			"check-failed",
+			[],
+			"Checking the results of the run found {bad} problem(s)"
+		);
+	}
+	if flags.verbose {
+		eprintln!("checked {} file(s)", moved.iter().filter(|m| **m).count());
+	}
+}
+
+/* Whether ‘a’ and ‘b’ are both regular files with the same bytes in them */
+fn same_contents(a: &Path, b: &Path) -> io::Result<bool> {
+	let (ma, mb) = (fs::symlink_metadata(a)?, fs::symlink_metadata(b)?);
+	if !ma.is_file() || !mb.is_file() || ma.len() != mb.len() {
+		return Ok(false);
+	}
+
+	let mut fa = BufReader::new(fs::File::open(a)?);
+	let mut fb = BufReader::new(fs::File::open(b)?);
+	let (mut ba, mut bb) = ([0; 8192], [0; 8192]);
+	loop {
+		let n = fa.read(&mut ba)?;
+		if n == 0 {
+			return Ok(true);
+		}
+		fb.read_exact(&mut bb[..n])?;
+		if ba[..n] != bb[..n] {
+			return Ok(false);
+		}
+	}
+}
+
+fn trash_duplicates(flags: &Flags, dups: &[(PathBuf, PathBuf)]) {
+	for (s, d) in dups.iter() {
+		trash::trash(s).unwrap_or_else(|e| {
+			fatal!("io", ["source" => s], "{}: {e}", s.to_string_lossy());
+		});
+		if flags.verbose {
+			print_duplicate(flags, s, d);
+		}
+	}
+}
+
+/* Deal with input files that were removed or replaced by different files while
+the command was running, keeping the sources and destinations paired up. */
+fn drop_changed(
+	flags: &Flags,
+	srcs: Vec<String>,
+	dsts: Vec<String>,
+	ids: Vec<Option<Id>>,
+) -> (Vec<String>, Vec<String>, Vec<Option<Id>>) {
+	let mut changed = Vec::new();
+	let mut kept = (Vec::new(), Vec::new(), Vec::new());
+	for ((s, d), id) in srcs.into_iter().zip(dsts).zip(ids) {
+		if identity(Path::new(&s)) == id {
+			kept.0.push(s);
+			kept.1.push(d);
+			kept.2.push(id);
+			continue;
+		}
+		match flags.changed {
+			Policy::Fail => changed.push(s),
+			Policy::Warn => {
+				warning!(
+					"changed-input",
+					["source" => &s],
+					"Input file “{s}” was replaced while the command ran; leaving it untouched"
+				);
+			}
+			Policy::Skip => {}
+		}
+	}
+	if !changed.is_empty() {
+		for s in changed.iter() {
+			warning!(
+				"changed-input",
+				["source" => s],
+				"Input file “{s}” was replaced while the command ran"
+			);
+		}
+		fatal!(
+			"changed-input",
+			[],
+			"Refusing to start with {} changed input file(s); see --changed",
+			changed.len()
+		);
+	}
+	kept
+}
+
+/* With --limit or --sample, only some of the files are renamed and the rest are
+left alone, so that a large plan can be tried out on a handful of files first.
+Files that keep their names do not count towards the number.  A file moved onto
+the input file of another pair takes that pair along, and so on, as renaming it
+alone would replace a file that was meant to be moved out of the way, so chains
+and swaps are always renamed whole. */
+fn select(
+	flags: &Flags,
+	srcs: Vec<String>,
+	dsts: Vec<String>,
+	ids: Vec<Option<Id>>,
+) -> (Vec<String>, Vec<String>, Vec<Option<Id>>) {
+	let Some(n) = flags.limit.or(flags.sample) else {
+		return (srcs, dsts, ids);
+	};
+	let mut picked = (0..srcs.len())
+		.filter(|&i| srcs[i] != dsts[i])
+		.collect_vec();
+	let total = picked.len();
+	if flags.sample.is_some() {
+		/* A partial Fisher–Yates shuffle driven by xorshift64*, which is random
+		enough for picking files to look at */
+		let now = SystemTime::now()
+			.duration_since(UNIX_EPOCH)
+			.unwrap_or_default();
+		let mut x = (now.as_nanos() as u64 ^ (process::id() as u64) << 32) | 1;
+		for i in 0..n.min(total) {
+			x ^= x >> 12;
+			x ^= x << 25;
+			x ^= x >> 27;
+			let r = x.wrapping_mul(0x2545f4914f6cdd1d);
+			picked.swap(i, i + (r % (total - i) as u64) as usize);
+		}
+	}
+	picked.truncate(n);
+
+	/* Paths are resolved the same way as when the plan is made */
+	let cwd = env::current_dir().unwrap_or_default();
+	let base = match &flags.chdir {
+		Some(d) => fs::canonicalize(d).unwrap_or_else(|_| cwd.join(d)),
+		None => cwd.clone(),
+	};
+	let by_src: HashMap<PathBuf, usize> = srcs
+		.iter()
+		.enumerate()
+		.map(|(i, s)| {
+			let p = fs::canonicalize(s).unwrap_or_else(|_| normalize_path(&cwd.join(s)));
+			(p, i)
+		})
+		.collect();
+	let mut chosen = vec![false; srcs.len()];
+	for &i in picked.iter() {
+		chosen[i] = true;
+	}
+	while let Some(i) = picked.pop() {
+		let d = normalize_path(&base.join(&dsts[i]));
+		if let Some(&j) = by_src.get(&d).filter(|&&j| !chosen[j]) {
+			chosen[j] = true;
+			picked.push(j);
+		}
+	}
+	if flags.verbose {
+		let n = (0..srcs.len()).filter(|&i| chosen[i] && srcs[i] != dsts[i]);
+		eprintln!("selected {} of {total} renamed file(s)", n.count());
+	}
+
+	let mut kept = (Vec::new(), Vec::new(), Vec::new());
+	for (i, ((s, d), id)) in srcs.into_iter().zip(dsts).zip(ids).enumerate() {
+		if chosen[i] {
+			kept.0.push(s);
+			kept.1.push(d);
+			kept.2.push(id);
+		}
+	}
+	kept
+}
+
+fn parse_policy(s: &str) -> Result<Policy, &'static str> {
+	match s {
+		"fail" => Ok(Policy::Fail),
+		"skip" => Ok(Policy::Skip),
+		"warn" => Ok(Policy::Warn),
+		_ => Err("expected one of ‘fail’, ‘skip’, or ‘warn’"),
+	}
+}
+
+fn parse_order(s: &str) -> Result<Order, &'static str> {
+	match s {
+		"as-given" => Ok(Order::AsGiven),
+		"depth-first" => Ok(Order::DepthFirst),
+		"large-first" => Ok(Order::LargeFirst),
+		"small-first" => Ok(Order::SmallFirst),
+		_ => Err("expected one of ‘small-first’, ‘large-first’, ‘depth-first’, or ‘as-given’"),
+	}
+}
+
+fn parse_mode(s: &str) -> Result<u32, &'static str> {
+	match u32::from_str_radix(s, 8) {
+		Ok(n) if n <= 0o7777 => Ok(n),
+		_ => Err("expected an octal file mode"),
+	}
+}
+
+/* A number of bytes, optionally followed by a binary unit such as ‘K’ or ‘MiB’ */
+fn parse_size(s: &str) -> Result<u64, &'static str> {
+	let bad = "expected a size such as ‘512M’ or ‘2GiB’";
+	let i = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+	let n: u64 = s[..i].parse().map_err(|_| bad)?;
+	let shift = match s[i..].trim_end_matches("iB").trim_end_matches('B') {
+		"" => 0,
+		"K" | "k" => 10,
+		"M" => 20,
+		"G" => 30,
+		"T" => 40,
+		_ => return Err(bad),
+	};
+	n.checked_mul(1 << shift).ok_or(bad)
+}
+
+fn umask() -> u32 {
+	let m = rustix::process::umask(rustix::fs::Mode::empty());
+	rustix::process::umask(m);
+	m.bits()
+}
+
+/* Create the directory ‘dir’ and any missing parents, giving the ones we create
+the mode from --dir-mode if there is one. */
+fn create_dirs(flags: &Flags, vfs: &dyn Filesystem, dir: &Path) -> io::Result<()> {
+	let Some(mode) = flags.dir_mode else {
+		return vfs.create_dir_all(dir);
+	};
+	let missing = dir
+		.ancestors()
+		.take_while(|a| !a.as_os_str().is_empty() && vfs.symlink_metadata(a).is_err())
+		.collect_vec();
+	for a in missing.into_iter().rev() {
+		vfs.create_dir(a)?;
+		vfs.set_mode(a, mode)?;
+	}
+	Ok(())
+}
+
+/* Create the missing directories --nest-by moves ‘dst’ into */
+fn create_parent(
+	flags: &Flags,
+	vfs: &dyn Filesystem,
+	dst: &Path,
+) -> Result<(), (PathBuf, io::Error)> {
+	match dst.parent() {
+		Some(p) => create_dirs(flags, vfs, p).map_err(|e| (p.to_owned(), e)),
+		None => Ok(()),
+	}
+}
+
+fn report_skipped(flags: &Flags, n: usize) {
+	if flags.missing == Policy::Skip && n > 0 {
+		warning!("skipped-missing", [], "Skipped {n} missing input file(s)");
+	}
+}
+
+/* Moves only stay renames within a single filesystem, and moving anything big
+anywhere else turns into a copy that can easily take hours, which is not what
+anyone expects of renaming a few files.  Every file goes by way of the staging
+directory, so it matters which filesystem that is on as well. */
+fn check_copy_threshold(flags: &Flags, n: u64, stage: &Path, ps: &[(PathBuf, PathBuf, PathBuf)]) {
+	let dev = |p: &Path| {
+		p.ancestors()
+			.find_map(|a| fs::symlink_metadata(a).ok())
+			.map(|m| m.dev())
+	};
+	let stage_dev = dev(stage);
+	let big = ps
+		.iter()
+		.filter(|(s, _, d)| {
+			let devs = [dev(s), stage_dev, dev(d)];
+			devs.windows(2).any(|w| w[0] != w[1])
+		})
+		.filter_map(|(s, _, d)| {
+			let size = check::digest(s, false, false).ok()?.size;
+			(size > n).then_some((s, d, size))
+		})
+		.collect_vec();
+	if big.is_empty() {
+		return;
+	}
+
+	for (s, d, size) in big.iter() {
+		warning!(
+			"copy-threshold",
+			["source" => s, "destination" => d],
+			"Moving “{}” ({size} bytes) to “{}” copies it to another filesystem",
+			s.to_string_lossy(),
+			d.to_string_lossy()
+		);
+	}
+	let total: u64 = big.iter().map(|(_, _, n)| n).sum();
+	if flags.dryrun
+		|| ask(
+			flags,
+			false,
+			format_args!(
+				"Copy {} file(s) totalling {total} bytes across filesystems? [y/N] ",
+				big.len()
+			),
+		) {
+		return;
+	}
+	fatal!(
+		"copy-threshold",
+		[],
+		"Refusing to copy {} file(s) above the --copy-threshold across filesystems",
+		big.len()
+	);
+}
+
+/* Copies take up space, which users copying large libraries want to know they
+have before starting.  Destinations that already exist are replaced, so their
+size is given back. */
+fn report_usage(ps: &[(PathBuf, PathBuf, PathBuf)]) {
+	let size = |p: &Path| check::digest(p, false, false).map_or(0, |d| d.size);
+	let ms = mounts::mounts();
+	let mut total = 0;
+	let mut fss: Vec<(u64, &Path, i128)> = Vec::new();
+	for (s, _, d) in ps.iter().filter(|(s, _, d)| s != d) {
+		let n = size(s);
+		total += n;
+		let Some((a, md)) = d
+			.ancestors()
+			.find_map(|a| Some((a, fs::symlink_metadata(a).ok()?)))
+		else {
+			continue;
+		};
+		let delta = n as i128 - if a == d { size(d) as i128 } else { 0 };
+		match fss.iter_mut().find(|(dev, _, _)| *dev == md.dev()) {
+			Some((_, _, x)) => *x += delta,
+			None => {
+				let root = mounts::mount_of(&ms, a).map_or(a, |m| m.point.as_path());
+				fss.push((md.dev(), root, delta));
+			}
+		}
+	}
+
+	eprintln!("{total} byte(s) to copy");
+	for (_, root, delta) in fss {
+		let Ok(st) = rustix::fs::statvfs(root) else {
+			continue;
+		};
+		let free = st.f_bavail as i128 * st.f_frsize as i128;
+		let after = free - delta;
+		eprintln!(
+			"‘{}’: {free} byte(s) free, {after} after copying",
+			disp(root)
+		);
+		if after < 0 {
+			warning!(
+				"no-space",
+				["directory" => root],
+				"The copies do not fit on “{}” by {} byte(s)",
+				root.to_string_lossy(),
+				-after
+			);
+		}
+	}
+}
+
+/* Summarize the kinds of changes the command made, as an expression that did
+more than intended shows up as an unexpectedly large number. */
+fn report_stats<'a, I>(ps: I)
+where
+	I: Iterator<Item = (&'a Path, &'a Path)>,
+{
+	let (mut exts, mut cases, mut moves, mut same) = (0, 0, 0, 0);
+	for (s, d) in ps {
+		if s == d {
+			same += 1;
+			continue;
+		}
+		if s.parent() != d.parent() {
+			moves += 1;
+		}
+		if s.extension() != d.extension() {
+			exts += 1;
+		}
+		let (sn, dn) = (s.file_name(), d.file_name());
+		if sn != dn
+			&& sn.map(|x| x.to_string_lossy().to_lowercase())
+				== dn.map(|x| x.to_string_lossy().to_lowercase())
+		{
+			cases += 1;
+		}
+	}
+	eprintln!(
+		"{exts} extension(s) changed\n{cases} case-only change(s)\n{moves} move(s) across directories\n{same} unchanged"
+	);
+}
+
+/* Control characters in filenames are almost always the result of a buggy
+command instead of something the user asked for.  Only the parts of an output
+path that do not also occur in the input path count, so that files already
+living below such names can still be renamed. */
+fn report_weird(srcs: &[String], dsts: &[String]) {
+	let weird = srcs
+		.iter()
+		.zip(dsts)
+		.filter(|(s, d)| {
+			let ss = Path::new(s).components().collect::<HashSet<_>>();
+			Path::new(d)
+				.components()
+				.filter(|c| !ss.contains(c))
+				.any(|c| {
+					c.as_os_str()
+						.to_string_lossy()
+						.chars()
+						.any(char::is_control)
+				})
+		})
+		.map(|(_, d)| d)
+		.collect_vec();
+	if weird.is_empty() {
+		return;
+	}
+	for d in weird.iter() {
+		warning!(
+			"weird-name",
+			["destination" => d],
+			"Output file {d:?} contains control characters"
+		);
+	}
+	fatal!(
+		"weird-name",
+		[],
+		"Refusing to start with {} strangely named output file(s); see --allow-weird-names",
+		weird.len()
+	);
+}
+
+/* Commands such as sort(1) that reorder their input pair sources with the wrong
+destinations, which goes unnoticed as long as the number of lines is the same.
+Such a command leaves the names it did not change in the wrong places, so a
+destination naming a different source gives it away, unless every destination
+does: that is a deliberate swap or cycle, like with tac(1). */
+fn report_reordered(srcs: &[String], dsts: &[String]) {
+	let pos: HashMap<&str, usize> = srcs
+		.iter()
+		.enumerate()
+		.map(|(i, s)| (s.as_str(), i))
+		.collect();
+	if dsts.iter().all(|d| pos.contains_key(d.as_str())) {
+		return;
+	}
+	let moved = dsts
+		.iter()
+		.enumerate()
+		.filter(|&(i, d)| pos.get(d.as_str()).is_some_and(|&j| j != i))
+		.collect_vec();
+	if moved.is_empty() {
+		return;
+	}
+	for &(i, d) in moved.iter() {
+		warning!(
+			"reordered",
+			["source" => &srcs[i], "destination" => d],
+			"Output file “{d}” for “{}” names another input file",
+			srcs[i]
+		);
+	}
+	fatal!(
+		"reordered",
+		[],
+		"The command seems to have reordered its output; see --numbered"
+	);
+}
+
+fn report_unmodifiable(errs: Vec<(PathBuf, io::Error)>) {
+	if errs.is_empty() {
+		return;
+	}
+	for (p, e) in errs.iter() {
+		warning!("unmodifiable", ["file" => p], "{}: {e}", p.to_string_lossy());
+	}
+	fatal!(
+		"unmodifiable",
+		[],
+		"Refusing to start with {} file(s) that cannot be modified",
+		errs.len()
+	);
+}
+
+/* A directory moved as a whole over an empty directory in place of the files
+in it, which were the sources ‘srcs’.  Moving the files would have left the
+mode of the directory they went to alone, so it is kept to be put back. */
+struct Collapsed {
+	srcs: Vec<PathBuf>,
+	dst_mode: u32,
+}
+
+fn report_open<'a>(srcs: impl Iterator<Item = &'a Path>) {
+	let open = preflight::open_for_writing(srcs).unwrap_or_else(|e| {
+		fatal!("io", ["directory" => "/proc"], "/proc: {e}");
+	});
+	if open.is_empty() {
+		return;
+	}
+	for (s, pid) in open.iter() {
+		warning!(
+			"open-for-writing",
+			["source" => s],
+			"Input file “{}” is open for writing by process {pid}",
+			s.to_string_lossy()
+		);
+	}
+	fatal!(
+		"open-for-writing",
+		[],
+		"Refusing to start with {} input file(s) that are open for writing",
+		open.len()
+	);
+}
+
+fn remove_unkept(
+	flags: &Flags,
+	vfs: &dyn Filesystem,
+	srcs: &[String],
+	keep: &[String],
+) -> Result<bool, io::Error> {
+	/* Symbolic links are removed themselves instead of their targets, so we
+	cannot canonicalize the paths here. */
+	let cwd = env::current_dir()?;
+	let srcs: HashSet<PathBuf> = srcs.iter().map(|s| normalize_path(&cwd.join(s))).collect();
+
+	let mut kept = HashSet::with_capacity(keep.len());
+	for k in keep.iter().filter(|k| !k.is_empty()) {
+		let k = normalize_path(&cwd.join(k));
+		if !srcs.contains(&k) {
+			fatal!(
+				"not-input",
+				["destination" => &k],
+				"Output file “{}” was not provided as input",
+				k.to_string_lossy()
+			);
+		}
+		kept.insert(k);
+	}
+
+	let ps = srcs
+		.iter()
+		.filter(|s| !kept.contains(*s))
+		.sorted_by_key(|s| Reverse(s.components().count()))
+		.collect_vec();
+
+	for s in ps.iter() {
+		if let Err(e) = vfs.symlink_metadata(s) {
+			fatal!("missing-input", ["source" => s], "{}: {e}", s.to_string_lossy());
+		}
+		if let Some(k) = kept.iter().find(|k| k.starts_with(s)) {
+			fatal!(
+				"contains-kept",
+				["source" => s, "destination" => k],
+				"Refusing to remove “{}” which contains the kept file “{}”",
+				s.to_string_lossy(),
+				k.to_string_lossy()
+			);
+		}
+	}
+
+	report_unmodifiable(preflight::check(
+		false,
+		ps.iter().map(|s| s.as_path()),
+		iter::empty(),
+	));
+	if flags.check_open {
+		report_open(ps.iter().map(|s| s.as_path()));
+	}
+
+	let pending = !ps.is_empty();
+	if let Some(shell) = flags.emit {
+		let mut sc = Script::new(shell, false);
+		for s in ps {
+			sc.remove(Arg::Path(s));
+		}
+		print!("{}", sc.finish());
+		return Ok(pending);
+	}
+
+	for s in ps {
+		if !flags.dryrun {
+			let res = if flags.trash {
+				trash::trash(s).map(|_| ())
+			} else {
+				vfs.symlink_metadata(s).and_then(|data| {
+					if data.is_dir {
+						vfs.remove_dir_all(s)
+					} else {
+						vfs.remove_file(s)
+					}
+				})
+			};
+			res.unwrap_or_else(|e| {
+				fatal!("io", ["source" => s], "{}: {e}", s.to_string_lossy());
+			});
+		}
+
+		if flags.verbose || flags.dryrun {
+			eprintln!(
+				"{} ‘{}’",
+				if flags.trash { "trashed" } else { "removed" },
+				disp(s)
+			);
+		}
+	}
+
+	Ok(pending)
+}
+
+/* Remove the directories which the given moved paths used to live in if they
+are now empty, working our way up the tree for as long as that is the case.
+We never remove the current working directory or any of its parents. */
+fn prune_empty<'a, I>(flags: &Flags, vfs: &dyn Filesystem, xs: I) -> Result<(), io::Error>
+where
+	I: Iterator<Item = &'a Path>,
+{
+	let cwd = env::current_dir()?;
+	let mut seen = HashSet::new();
+	let dirs = xs
+		.filter_map(Path::parent)
+		.unique()
+		.sorted_by_key(|p| Reverse(p.components().count()))
+		.collect_vec();
+
+	for dir in dirs {
+		for d in dir.ancestors() {
+			if cwd.starts_with(d) || !seen.insert(d.to_owned()) {
+				break;
+			}
+			match vfs.remove_dir(d) {
+				Ok(()) => {
+					if flags.verbose {
+						eprintln!("removed directory ‘{}’", disp(d));
+					}
+				}
+				Err(e) if e.kind() == io::ErrorKind::DirectoryNotEmpty => break,
+				Err(e) => {
+					warning!("io", ["directory" => d], "{}: {e}", d.to_string_lossy());
+					break;
+				}
+			}
+		}
+	}
+
+	Ok(())
+}
+
+fn backup_srcs<'a, I>(
+	flags: &Flags,
+	vfs: &dyn Filesystem,
+	cwd: &Path,
+	xs: I,
+) -> Result<(), io::Error>
+where
+	I: Iterator<Item = &'a PathBuf>,
+{
+	for x in xs {
+		let data = require!(vfs.metadata(x));
+		if data.is_dir {
+			let rel_x = require!(x.strip_prefix("/"));
+			create_dirs(flags, vfs, rel_x)?;
+			if flags.verbose {
+				eprintln!("created directory ‘{}/{}’", disp(cwd), rel_x.display());
+			}
+		} else {
+			if let Some(p) = x.parent() {
+				let rel_x = require!(p.strip_prefix("/"));
+				create_dirs(flags, vfs, rel_x)?;
+				if flags.verbose {
+					eprintln!("created directory ‘{}/{}’", disp(cwd), rel_x.display());
+				}
+			}
+			let rel_x = require!(x.strip_prefix("/"));
+			vfs.copy(x, rel_x)?;
+			if flags.verbose {
+				eprintln!(
+					"copied ‘{}’ -> ‘{}/{}’",
+					disp(x),
+					disp(cwd),
+					rel_x.display()
+				);
+			}
+		}
+	}
+
+	Ok(())
+}
+
+/* Translate a path as seen from inside a container or other mount namespace to
+the same path as seen from here, using the first --map-prefix that matches. */
+fn map_prefix(flags: &Flags, p: &str) -> String {
+	flags
+		.map_prefix
+		.iter()
+		.find_map(|(from, to)| {
+			Path::new(p)
+				.strip_prefix(from)
+				.ok()
+				.map(|rest| match rest.as_os_str().is_empty() {
+					true => to.clone(),
+					false => to.join(rest),
+				})
+		})
+		.map_or_else(|| p.to_string(), |p| p.to_str().unwrap().to_string())
+}
+
+/* Deal with input files that do not exist before the user spends any time
+editing the list. */
+fn exists(flags: &Flags, s: &str) -> bool {
+	match fs::symlink_metadata(s) {
+		Ok(_) => true,
+		Err(e) => {
+			match flags.missing {
+				Policy::Fail => {
+					fatal!("missing-input", ["source" => s], "{s}: {e}");
 				}
-				(true, _) => {
-					pv = false;
-					return Err(());
+				Policy::Warn => {
+					warning!("missing-input", ["source" => s], "{s}: {e}");
 				}
-				(false, '\\') => {
-					pv = true;
-					None
+				Policy::Skip => {}
+			}
+			false
+		}
+	}
+}
+
+fn strip_root(srcs: &[String], root: &Path) -> Vec<String> {
+	let root = fs::canonicalize(root).unwrap_or_else(|e| {
+		fatal!("io", ["directory" => root], "{}: {e}", root.to_string_lossy());
+	});
+	srcs.iter()
+		.map(|s| {
+			let p = require!(fs::canonicalize(s));
+			match p.strip_prefix(&root) {
+				Ok(p) => p.to_str().unwrap().to_string(),
+				Err(_) => {
+					fatal!(
+						"outside-root",
+						["source" => s, "directory" => &root],
+						"Input file “{}” is not under “{}”",
+						s,
+						root.to_string_lossy()
+					);
 				}
-				(false, _) => Some(c),
-			})
+			}
 		})
-		.filter_map(Result::transpose)
-		.collect::<Result<String, ()>>()
-		.unwrap_or_else(|_| {
-			err!("Decoding the file “{}” failed", s);
+		.collect()
+}
+
+fn run_subst(srcs: &Vec<String>, dsts: &mut Vec<String>, flags: &Flags) {
+	let table = flags.join.as_ref().map(|p| {
+		join::read(p, flags.key.as_deref())
+			.unwrap_or_else(|e| fatal!("join", ["table" => p], "{}: {e}", p.display()))
+	});
+
+	for src in srcs {
+		let mut components = vec![];
+		let s = if flags.basename {
+			components = Path::new(src).components().collect_vec();
+			components.pop().unwrap().as_os_str().to_str().unwrap()
+		} else {
+			src
+		};
+
+		let name = Path::new(src)
+			.file_name()
+			.unwrap_or_default()
+			.to_string_lossy();
+		let s = flags
+			.subst
+			.iter()
+			.fold(s.to_string(), |acc, sub| match &table {
+				Some(t) => sub
+					.apply_joined(&acc, &name, t)
+					.unwrap_or_else(|e| fatal!("join", ["source" => src], "{src}: {e}"))
+					.into_owned(),
+				None => sub.apply(&acc).into_owned(),
+			});
+
+		if flags.basename {
+			let path = components.iter().collect::<PathBuf>().join(s);
+			dsts.push(path.to_str().unwrap().to_string());
+		} else {
+			dsts.push(s);
+		}
+	}
+}
+
+/* The environment of the command tells it how we talk to it, so that scripts
+can adapt to our flags instead of needing flags of their own. */
+fn child_env(flags: &Flags, n: usize) -> [(&'static str, String); 4] {
+	let mode = match (flags.mcp, flags.mln, flags.mrm) {
+		(true, _, _) => "copy",
+		(_, true, _) => "link",
+		(_, _, true) => "remove",
+		_ => "move",
+	};
+	let bool = |b: bool| String::from(if b { "1" } else { "0" });
+	[
+		("MMV_COUNT", n.to_string()),
+		(
+			"MMV_ENCODED",
+			String::from(if flags.encode {
+				encoding::name(flags.encoding)
+			} else {
+				"0"
+			}),
+		),
+		("MMV_MODE", mode.to_string()),
+		("MMV_NUL", bool(flags.nul && !flags.encode)),
+	]
+}
+
+fn run_indiv(
+	srcs: &[String],
+	dsts: &mut Vec<String>,
+	flags: &Flags,
+	cmd: &OsString,
+	args: &[OsString],
+) -> Result<Vec<usize>, io::Error> {
+	/* With --partial-apply the files the command fails for are left out, like
+	those --numbered drops, instead of failing the whole run. */
+	let mut failed = Vec::new();
+	let mut fail = |i: usize, why: String| {
+		warning!(
+			"child-failed",
+			["source" => &srcs[i]],
+			"The command “{}” {why} for “{}”",
+			cmd.to_string_lossy(),
+			srcs[i]
+		);
+		failed.push(i);
+	};
+
+	/* Children are run by up to --jobs threads at once, which take the files
+	in order and leave their results in their places, so that the outputs are
+	handled in the order of the files afterwards.  Once the command fails
+	without --partial-apply no more files are taken, as the run is going to be
+	aborted anyway. */
+	let next = AtomicUsize::new(0);
+	let stop = AtomicBool::new(false);
+	let results = srcs.iter().map(|_| Mutex::new(None)).collect_vec();
+	thread::scope(|sc| {
+		for _ in 0..flags.jobs.min(srcs.len()) {
+			sc.spawn(|| {
+				while !stop.load(Ordering::Relaxed) {
+					let i = next.fetch_add(1, Ordering::Relaxed);
+					let Some(src) = srcs.get(i) else {
+						break;
+					};
+					let r = run_child(flags, cmd, args, i, srcs.len(), src);
+					let ok = r
+						.as_ref()
+						.is_ok_and(|(st, s)| st.success() && (flags.mrm || !s.is_empty()));
+					if !ok && !flags.partial_apply {
+						stop.store(true, Ordering::Relaxed);
+					}
+					*results[i].lock().unwrap() = Some(r);
+				}
+			});
+		}
+	});
+
+	for (i, (src, r)) in srcs.iter().zip(results).enumerate() {
+		/* Files are only left out after one that aborts the run */
+		let Some(r) = r.into_inner().unwrap() else {
+			break;
+		};
+		let (status, s) = r?;
+		let mut components = vec![];
+		if flags.basename {
+			components = Path::new(src).components().collect_vec();
+			components.pop();
+		}
+		if !status.success() {
+			if flags.partial_apply {
+				fail(i, exit_reason(status));
+				continue;
+			}
+			child_failed(cmd, status, !s.is_empty());
+		}
+		if s.is_empty() && !flags.mrm {
+			if flags.partial_apply {
+				fail(i, "printed no output".to_string());
+				continue;
+			}
+			no_output(cmd);
+		}
+		let s = match flags.encode.then(|| encoding::decode(flags.encoding, &s)) {
+			Some(Ok(d)) => d,
+			Some(Err(e)) if flags.partial_apply => {
+				fail(i, format!("printed the undecodable “{s}” ({e})"));
+				continue;
+			}
+			Some(Err(e)) => fatal!(
+				"decode-failed",
+				[],
+				"Decoding the output file “{s}” failed: {e}"
+			),
+			None => s,
+		};
+
+		if flags.basename {
+			let path = components.iter().collect::<PathBuf>().join(s);
+			dsts.push(path.to_str().unwrap().to_string());
+		} else {
+			dsts.push(s);
+		}
+	}
+
+	Ok(failed)
+}
+
+/* Run the command of -i for ‘src’, the ‘i’th of ‘n’ files, returning how it
+exited along with its output */
+fn run_child(
+	flags: &Flags,
+	cmd: &OsString,
+	args: &[OsString],
+	i: usize,
+	n: usize,
+	src: &str,
+) -> Result<(ExitStatus, String), io::Error> {
+	let mut child = Command::new(cmd)
+		.args(args)
+		.envs(child_env(flags, n))
+		.env("MMV_INDEX", i.to_string())
+		.env("MMV_TOTAL", n.to_string())
+		.stdin(Stdio::piped())
+		.stdout(Stdio::piped())
+		.spawn()
+		.unwrap_or_else(|e| {
+			fatal!(
+				"spawn-failed",
+				[],
+				"Failed to spawn utility: “{}”: {e}",
+				cmd.to_str().unwrap()
+			);
+		});
+
+	{
+		let mut ci = child.stdin.take().unwrap_or_else(|| {
+			fatal!(
+				"spawn-failed",
+				[],
+				"Could not open the child process’ stdin"
+			);
+		});
+		let s = if flags.basename {
+			Path::new(src)
+				.components()
+				.next_back()
+				.unwrap()
+				.as_os_str()
+				.to_str()
+				.unwrap()
+		} else {
+			src
+		};
+		require!(write!(
+			ci,
+			"{}",
+			if flags.encode {
+				encoding::encode(flags.encoding, s)
+			} else {
+				s.to_string()
+			}
+		));
+	}
+
+	let mut co = child.stdout.take().unwrap_or_else(|| {
+		fatal!(
+			"spawn-failed",
+			[],
+			"Could not open the child process’ stdout"
+		);
+	});
+	let mut s = String::with_capacity(src.len());
+	require!(co.read_to_string(&mut s));
+	Ok((child.wait()?, s))
+}
+
+fn exit_reason(status: ExitStatus) -> String {
+	match (status.code(), status.signal()) {
+		(Some(n), _) => format!("exited with status {n}"),
+		(None, Some(n)) => format!("was killed by signal {n}"),
+		(None, None) => "failed".to_string(),
+	}
+}
+
+/* A command failing before it wrote anything is most often an editor whose user
+gave up on the edit, as with ‘:cq’ in vi(1), which is not an error as such. */
+fn child_failed(cmd: &OsString, status: ExitStatus, output: bool) -> ! {
+	let cmd = cmd.to_string_lossy();
+	let how = exit_reason(status);
+	if output {
+		fatal!(
+			exit EXIT_CHILD_FAILED,
+			"child-failed",
+			[],
+			"The command “{cmd}” {how}; no files were touched"
+		);
+	}
+	fatal!(
+		exit EXIT_ABORTED,
+		"aborted",
+		[],
+		"The command “{cmd}” {how} without any output; no files were touched"
+	);
+}
+
+fn no_output(cmd: &OsString) -> ! {
+	fatal!(
+		exit EXIT_NO_OUTPUT,
+		"no-output",
+		[],
+		"The command “{}” succeeded without any output; no files were touched",
+		cmd.to_string_lossy()
+	);
+}
+
+fn run_multi(
+	srcs: &[String],
+	dsts: &mut Vec<String>,
+	flags: &Flags,
+	cmd: &OsString,
+	args: &[OsString],
+) -> Result<Vec<usize>, io::Error> {
+	let mut child = Command::new(cmd)
+		.args(args)
+		.envs(child_env(flags, srcs.len()))
+		.stdin(Stdio::piped())
+		.stdout(Stdio::piped())
+		.spawn()
+		.unwrap_or_else(|e| {
+			fatal!(
+				"spawn-failed",
+				[],
+				"Failed to spawn utility “{}”: {e}",
+				cmd.to_str().unwrap()
+			);
+		});
+
+	/* Pass the source files to the child process while reading its output,
+	as a child that writes out its output as it goes, like sed(1), would
+	otherwise block on a full pipe before reading all of its input. */
+	let mut components_vec = Vec::with_capacity(srcs.len());
+	let names = srcs
+		.iter()
+		.map(|src| {
+			if !flags.basename {
+				return src.as_str();
+			}
+			let mut components = Path::new(src).components().collect_vec();
+			let name = components.pop().unwrap().as_os_str().to_str().unwrap();
+			components_vec.push(components);
+			name
 		})
+		.collect_vec();
+	let ci = child.stdin.take().unwrap_or_else(|| {
+		fatal!(
+			"spawn-failed",
+			[],
+			"Could not open the child process’ stdin"
+		);
+	});
+	let co = child.stdout.take().unwrap_or_else(|| {
+		fatal!(
+			"spawn-failed",
+			[],
+			"Could not open the child process’ stdout"
+		);
+	});
+	let mut data = Vec::new();
+	thread::scope(|sc| {
+		sc.spawn(|| {
+			let mut ci = BufWriter::new(ci);
+			for (i, s) in names.iter().enumerate() {
+				if flags.numbered {
+					require!(write!(ci, "{i}\t"));
+				}
+				require!(write!(
+					ci,
+					"{}",
+					if flags.encode {
+						encoding::encode(flags.encoding, s)
+					} else {
+						s.to_string()
+					}
+				));
+				require!(ci.write_all(if flags.nul && !flags.encode {
+					b"\0"
+				} else {
+					b"\n"
+				}));
+			}
+		});
+		require!(BufReader::new(co).read_to_end(&mut data));
+	});
+	let mut numbered = vec![None; if flags.numbered { srcs.len() } else { 0 }];
+	let mut undecodable = 0;
+	let mut outputs = 0;
+	records(&data, terminator(flags.nul && !flags.encode))
+		.enumerate()
+		.for_each(|(n, x)| {
+			outputs += 1;
+			let dst = require!(String::from_utf8(x.to_vec()));
+			let (i, dst) = if flags.numbered {
+				parse_numbered(dst, srcs.len())
+			} else {
+				(n, dst)
+			};
+
+			/* Decode before joining, as the directory is not encoded */
+			let dst = match flags.encode {
+				true => encoding::decode(flags.encoding, &dst).unwrap_or_else(|e| {
+					warning!(
+						"decode-failed",
+						[],
+						"Decoding the output file “{dst}” failed: {e}"
+					);
+					undecodable += 1;
+					dst
+				}),
+				false => dst,
+			};
+
+			let s = if flags.basename {
+				let components = require!(components_vec.get(i), "WOW");
+				let path = components.iter().collect::<PathBuf>().join(dst);
+				path.to_str().unwrap().to_string()
+			} else {
+				dst
+			};
+			if !flags.numbered {
+				dsts.push(s);
+			} else if numbered[i].replace(s).is_some() {
+				fatal!(
+					"duplicate-number",
+					["source" => &srcs[i]],
+					"Input file “{}” was numbered more than once",
+					srcs[i]
+				);
+			}
+		});
+
+	let status = child.wait()?;
+	if !status.success() {
+		child_failed(cmd, status, outputs > 0);
+	}
+	/* An empty list of files to keep simply removes everything */
+	if outputs == 0 && !flags.mrm && !srcs.is_empty() {
+		no_output(cmd);
+	}
+	if undecodable > 0 {
+		fatal!(
+			"decode-failed",
+			[],
+			"Refusing to start with {undecodable} output file(s) that could not be decoded"
+		);
+	}
+
+	/* Numbered output may come in any order and, without the same-length
+	invariant, leave some files out.  The removal utility treats left out
+	files as ones to remove, like it does with unnumbered output. */
+	let mut dropped = Vec::new();
+	for (i, d) in numbered.into_iter().enumerate() {
+		match d {
+			Some(d) => dsts.push(d),
+			None if flags.mrm || flags.allow_mismatch => dropped.push(i),
+			None => {
+				fatal!(
+					"length-mismatch",
+					["source" => &srcs[i]],
+					"No output file was given for the input file “{}”",
+					srcs[i]
+				);
+			}
+		}
+	}
+	Ok(if flags.mrm { Vec::new() } else { dropped })
+}
+
+/* Split the number off of an output line of the form ‘N<tab>path’ */
+fn parse_numbered(line: String, n: usize) -> (usize, String) {
+	let parsed = line
+		.split_once('\t')
+		.and_then(|(i, d)| Some((i.parse::<usize>().ok().filter(|&i| i < n)?, d)));
+	match parsed {
+		Some((i, d)) => (i, d.to_owned()),
+		None => {
+			fatal!(
+				"bad-number",
+				[],
+				"Output line “{}” does not begin with the number of an input file",
+				line
+			);
+		}
+	}
 }
 
 /* Blatantly stolen from the Cargo source code.  This is MIT licensed. */
@@ -527,47 +3161,906 @@ fn normalize_path(path: &Path) -> PathBuf {
 	ret
 }
 
-fn move_path(flags: &Flags, from: &PathBuf, to: &PathBuf) {
+fn move_path(
+	flags: &Flags,
+	vfs: &dyn Filesystem,
+	from: &Path,
+	to: &Path,
+) -> Result<(), (PathBuf, io::Error)> {
 	if !flags.dryrun {
-		copy_and_remove_file_or_dir(flags, &from, &to).unwrap_or_else(|(f, e)| {
-			err!("{}: {e}", f.to_string_lossy());
-		});
+		let start = Instant::now();
+		/* Taken before the move, as the source may be gone after it */
+		let dev = flags
+			.timings
+			.then(|| fs::symlink_metadata(from).map(|m| m.dev()));
+		copy_and_remove_file_or_dir(flags, vfs, from, to)?;
+		if let Some(dev) = dev {
+			let crossed = match (dev, to.parent().map(fs::metadata)) {
+				(Ok(a), Some(Ok(b))) => a != b.dev(),
+				_ => false,
+			};
+			let action = if flags.mcp {
+				"copied"
+			} else if crossed {
+				"copied across filesystems"
+			} else {
+				"renamed"
+			};
+			timings::record(start.elapsed(), action, from, to);
+		}
 	}
 
-	if flags.verbose {
-		eprintln!(
-			"{} ‘{}’ -> ‘{}’",
-			if flags.mcp { "copied" } else { "renamed" },
-			disp(&from),
-			disp(&to)
+	if flags.verbose && !flags.group_by_dir {
+		print_pair(flags, action(flags), from, to);
+	}
+	Ok(())
+}
+
+/* An error that occurred while processing the pair of files from ‘src’ to
+‘dst’ (by way of the staging file ‘stage’, if any), where ‘path’ is the file
+that was being operated on when it did. */
+struct PairError {
+	action: &'static str,
+	src: PathBuf,
+	stage: Option<PathBuf>,
+	dst: PathBuf,
+	path: PathBuf,
+	err: io::Error,
+}
+
+impl PairError {
+	fn new(
+		flags: &Flags,
+		src: &Path,
+		stage: Option<&Path>,
+		dst: &Path,
+		(path, err): (PathBuf, io::Error),
+	) -> Self {
+		let action = if flags.mln {
+			"linking"
+		} else if flags.mcp {
+			"copying"
+		} else {
+			"moving"
+		};
+		PairError {
+			action,
+			src: src.to_owned(),
+			stage: stage.map(Path::to_owned),
+			dst: dst.to_owned(),
+			path,
+			err,
+		}
+	}
+}
+
+impl PairError {
+	fn paths(&self) -> Vec<(&str, &Path)> {
+		let mut ps = vec![("source", self.src.as_path())];
+		if let Some(t) = &self.stage {
+			ps.push(("staging", t));
+		}
+		ps.push(("destination", &self.dst));
+		ps.push(("file", &self.path));
+		ps
+	}
+
+	fn fatal(&self) -> ! {
+		report::print_fatal(1, "io", &self.paths(), format_args!("{self}"));
+	}
+
+	fn warning(&self) {
+		report::print_warning("io", &self.paths(), format_args!("{self}"));
+	}
+}
+
+impl fmt::Display for PairError {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		let role = if self.path == self.src {
+			"source"
+		} else if self.stage.as_ref() == Some(&self.path) {
+			"staging file"
+		} else if self.path == self.dst {
+			"destination"
+		} else {
+			"file"
+		};
+		write!(
+			f,
+			"{role} “{}”: {} (while {} “{}” to “{}”",
+			self.path.to_string_lossy(),
+			self.err,
+			self.action,
+			self.src.to_string_lossy(),
+			self.dst.to_string_lossy()
+		)?;
+		if let Some(t) = &self.stage {
+			write!(f, " by way of “{}”", t.to_string_lossy())?;
+		}
+		write!(f, ")")
+	}
+}
+
+/* Failing while files are staged leaves the staging directory behind (we exit
+without running any destructors), so tell the user where to find it. */
+fn staging_err(dir: &Path, e: PairError) -> ! {
+	e.warning();
+	staging_kept(dir);
+}
+
+/* Without a record of what was done, continuing would make the staging
+directory useless for picking up where we left off */
+fn journal_err(dir: &Path, e: io::Error) -> ! {
+	warning!(
+		"io",
+		["directory" => dir],
+		"Could not update the manifest of ‘{}’: {e}",
+		disp(dir)
+	);
+	staging_kept(dir);
+}
+
+fn staging_kept(dir: &Path) -> ! {
+	fatal!(
+		"staged-kept",
+		["directory" => dir],
+		"Staged files were kept in ‘{}’; see the --resume and --restore options",
+		disp(dir)
+	);
+}
+
+/* Pick up the run that the staging directory ‘dir’ belongs to, either moving
+everything on to its destination (--resume) or back to where it came from
+(--restore).  Which files still need to be moved on is read from the progress
+recorded in the manifest, and otherwise determined by which paths exist, which
+is sound as long as the two phases of a run never overlap.  Files are moved
+back by which paths exist alone. */
+fn recover(flags: &Flags, dir: &Path) -> Result<(), io::Error> {
+	let m = staging::read(dir)
+		.map_err(|e| io::Error::new(e.kind(), format!("{}: {e}", dir.to_string_lossy())))?;
+	if m.torn {
+		warning!(
+			"torn-manifest",
+			["directory" => dir],
+			"The last record in the manifest of ‘{}’ is damaged, likely by a crash; ignoring it",
+			disp(dir)
+		);
+	}
+	if m.copy != flags.mcp {
+		let mcp_name = option_env!("MCP_NAME").unwrap_or(MCP_DEFAULT_NAME);
+		let mmv_name = option_env!("MMV_NAME").unwrap_or(MMV_DEFAULT_NAME);
+		fatal!(
+			"wrong-utility",
+			["directory" => dir],
+			"“{}” was staged by {}; recover it with that utility instead",
+			dir.to_string_lossy(),
+			if m.copy { mcp_name } else { mmv_name }
+		);
+	}
+	if m.copy && flags.restore.is_some() {
+		fatal!(
+			"nothing-to-restore",
+			["directory" => dir],
+			"Nothing to restore; copying never modifies its input files"
 		);
 	}
+
+	if !flags.dryrun {
+		let fixed = match (m.legacy, m.torn) {
+			(true, _) => staging::upgrade(dir),
+			(_, true) => staging::drop_torn(dir),
+			_ => Ok(()),
+		};
+		fixed.unwrap_or_else(|e| journal_err(dir, e));
+	}
+
+	let vfs = &vfs::Std;
+	let exists = |p: &Path| vfs.symlink_metadata(p).is_ok();
+	let fail = |e: &staging::Entry, x| -> ! {
+		staging_err(
+			dir,
+			PairError::new(flags, &e.src, Some(&e.stage), &e.dst, x),
+		)
+	};
+
+	let done = |i| {
+		if !flags.dryrun {
+			staging::mark_done(dir, i).unwrap_or_else(|e| journal_err(dir, e));
+		}
+	};
+	let todo = m.entries.iter().enumerate().filter(|(_, e)| !e.skipped);
+
+	if flags.resume.is_some() {
+		if !m.staged {
+			for (i, e) in todo.clone().filter(|(_, e)| !e.done && exists(&e.src)) {
+				/* The staged file is only complete once it is marked as done,
+				and copying never removes the source, so start over */
+				if m.copy && exists(&e.stage) && !flags.dryrun {
+					match vfs.symlink_metadata(&e.stage) {
+						Ok(md) if md.is_dir => vfs.remove_dir_all(&e.stage),
+						_ => vfs.remove_file(&e.stage),
+					}
+					.unwrap_or_else(|x| fail(e, (e.stage.clone(), x)));
+				}
+				move_path(flags, vfs, &e.src, &e.stage).unwrap_or_else(|x| fail(e, x));
+				done(i);
+			}
+			if !flags.dryrun {
+				staging::mark_staged(dir).unwrap_or_else(|e| journal_err(dir, e));
+			}
+		}
+		for (i, e) in todo
+			.rev()
+			.filter(|(_, e)| !(m.staged && e.done) && exists(&e.stage))
+		{
+			move_path(flags, vfs, &e.stage, &e.dst).unwrap_or_else(|x| fail(e, x));
+			done(i);
+		}
+		let dups = m
+			.duplicates
+			.into_iter()
+			.filter(|(s, _)| exists(s))
+			.collect_vec();
+		if flags.dryrun {
+			for (s, d) in dups.iter() {
+				print_duplicate(flags, s, d);
+			}
+		} else {
+			trash_duplicates(flags, &dups);
+		}
+	} else {
+		for (s, _) in m.duplicates.iter().filter(|(s, _)| !exists(s)) {
+			warning!(
+				"not-restored",
+				["source" => s],
+				"Not restoring “{}”, which was already moved to the trash",
+				s.to_string_lossy()
+			);
+		}
+		if m.staged {
+			for (_, e) in todo.clone().filter(|(_, e)| !exists(&e.stage)) {
+				move_path(flags, vfs, &e.dst, &e.stage).unwrap_or_else(|x| fail(e, x));
+			}
+		}
+		for (_, e) in todo.rev().filter(|(_, e)| exists(&e.stage)) {
+			if exists(&e.src) {
+				warning!(
+					"not-restored",
+					["source" => &e.src, "staging" => &e.stage],
+					"Not restoring “{}” over an existing file; it was kept as “{}”",
+					e.src.to_string_lossy(),
+					e.stage.to_string_lossy()
+				);
+				continue;
+			}
+			move_path(flags, vfs, &e.stage, &e.src).unwrap_or_else(|x| fail(e, x));
+		}
+	}
+
+	if !flags.dryrun {
+		fs::remove_dir_all(dir)?;
+		if flags.verbose {
+			eprintln!("removing directory ‘{}’", disp(dir));
+		}
+	}
+	Ok(())
+}
+
+fn link_path(
+	flags: &Flags,
+	vfs: &dyn Filesystem,
+	from: &Path,
+	to: &Path,
+) -> Result<(), (PathBuf, io::Error)> {
+	let start = Instant::now();
+	let res = if flags.symbolic {
+		vfs.symlink(from, to)
+	} else {
+		vfs.hard_link(from, to)
+	};
+	res.map_err(|e| (to.to_owned(), e))?;
+	if flags.timings {
+		timings::record(start.elapsed(), action(flags), from, to);
+	}
+
+	if flags.verbose && !flags.group_by_dir {
+		print_pair(flags, action(flags), from, to);
+	}
+	Ok(())
 }
 
-fn copy_and_remove_file_or_dir<'a>(
+fn copy_and_remove_file_or_dir(
 	flags: &Flags,
-	from: &'a PathBuf,
-	to: &'a PathBuf,
-) -> Result<(), (&'a PathBuf, io::Error)> {
-	let data = fs::metadata(&from).map_err(|e| (from, e))?;
-	if data.is_dir() {
-		fs::create_dir(&to).map_err(|e| (to, e))?;
+	vfs: &dyn Filesystem,
+	from: &Path,
+	to: &Path,
+) -> Result<(), (PathBuf, io::Error)> {
+	/* Inputs are canonicalized, so the only symbolic links seen here are
+	those inside of the directories being copied, which are recreated instead
+	of being followed. */
+	let data = vfs
+		.symlink_metadata(from)
+		.map_err(|e| (from.to_owned(), e))?;
+	if flags.merge && data.is_dir && vfs.metadata(to).is_ok_and(|d| d.is_dir) {
+		return merge_dirs(flags, vfs, from, to);
+	}
+
+	/* Renaming is only possible within a single filesystem; everything else
+	falls back to copying.  Directories that cannot be renamed are recreated
+	and their contents moved one by one, as parts of either tree may be mounted
+	from other devices than the directory itself, which lets each of those be
+	renamed into place on its own. */
+	if !flags.mcp {
+		match vfs.rename(from, to) {
+			Err(e) if e.kind() == io::ErrorKind::CrossesDevices => {}
+			/* A failed rename does not say which side was at fault, but if the
+			source is still there it is most likely the destination. */
+			res => {
+				return res.map_err(|e| match vfs.symlink_metadata(from) {
+					Ok(_) => (to.to_owned(), e),
+					Err(_) => (from.to_owned(), e),
+				})
+			}
+		}
+	}
+
+	/* Moves carry the mode of their sources over like renames do, while copies
+	are subject to the umask like with cp(1). */
+	let mask = if flags.mcp { umask() } else { 0 };
+	if data.is_symlink {
+		vfs.read_link(from)
+			.map_err(|e| (from.to_owned(), e))
+			.and_then(|l| vfs.symlink(&l, to).map_err(|e| (to.to_owned(), e)))?;
 		if !flags.mcp {
-			fs::remove_dir(&from).map_err(|e| (from, e))?
+			vfs.remove_file(from).map_err(|e| (from.to_owned(), e))?
 		}
+	} else if data.is_dir {
+		let mode = flags.dir_mode.unwrap_or(data.mode & !mask);
+		/* Like renaming, this replaces an empty directory in the way */
+		match vfs.create_dir(to) {
+			Err(e)
+				if e.kind() == io::ErrorKind::AlreadyExists
+					&& vfs
+						.symlink_metadata(to)
+						.is_ok_and(|m| m.is_dir && !m.is_symlink)
+					&& vfs.read_dir(to).is_ok_and(|es| es.is_empty()) => {}
+			res => res.map_err(|e| (to.to_owned(), e))?,
+		}
+		merge_dirs(flags, vfs, from, to)?;
+		vfs.set_mode(to, mode).map_err(|e| (to.to_owned(), e))?;
 	} else {
-		fs::copy(&from, &to).map_err(|e| (to, e))?;
+		let mode = flags.mode.unwrap_or(data.mode & !mask);
+		if let Some(c) = flags
+			.link_dest
+			.as_ref()
+			.filter(|_| flags.mcp)
+			.and_then(|l| l.find(from, mode))
+		{
+			match link_over(vfs, c, to) {
+				Err(e) if e.kind() == io::ErrorKind::CrossesDevices => {}
+				res => return res.map_err(|e| (to.to_owned(), e)),
+			}
+		}
+		/* Files are copied under a temporary name next to their destination
+		and renamed into place once complete, so that nothing ever sees a
+		truncated file under its final name, not even after an interrupted
+		run.  Backends that cannot rename write to the destination itself. */
+		let tmp = if vfs.renames() {
+			temp_name(vfs, to)
+		} else {
+			to.to_owned()
+		};
+		let mut attempts = 0;
+		let res = loop {
+			match vfs.copy(from, &tmp) {
+				Err(e) if is_full(&e) && retry_full(flags, from, to, &e, attempts) => attempts += 1,
+				res => break res,
+			}
+		};
+		res.and_then(|_| {
+			if flags.mcp {
+				vfs.set_mode(&tmp, mode)
+			} else {
+				Ok(())
+			}
+		})
+		.and_then(|_| {
+			if tmp != to {
+				vfs.rename(&tmp, to)
+			} else {
+				Ok(())
+			}
+		})
+		.map_err(|e| {
+			if tmp != to {
+				let _ = vfs.remove_file(&tmp);
+			}
+			(to.to_owned(), e)
+		})?;
 		if !flags.mcp {
-			fs::remove_file(&from).map_err(|e| (from, e))?
+			vfs.remove_file(from).map_err(|e| (from.to_owned(), e))?
+		}
+	}
+	Ok(())
+}
+
+/* Hard link the file ‘from’ to ‘to’ for --link-dest, replacing whatever file
+is already at ‘to’ unless the two are one and the same.  Renaming a link onto
+another link to the same file does nothing at all, which would leave the
+temporary link behind. */
+fn link_over(vfs: &dyn Filesystem, from: &Path, to: &Path) -> io::Result<()> {
+	if let (Ok(a), Ok(b)) = (fs::symlink_metadata(from), fs::symlink_metadata(to)) {
+		if (a.dev(), a.ino()) == (b.dev(), b.ino()) {
+			return Ok(());
+		}
+	}
+	if !vfs.renames() {
+		return match vfs.hard_link(from, to) {
+			Err(e) if e.kind() == io::ErrorKind::AlreadyExists => {
+				vfs.remove_file(to)?;
+				vfs.hard_link(from, to)
+			}
+			res => res,
+		};
+	}
+	let tmp = temp_name(vfs, to);
+	vfs.hard_link(from, &tmp)?;
+	vfs.rename(&tmp, to).inspect_err(|_| {
+		let _ = vfs.remove_file(&tmp);
+	})
+}
+
+/* A name that is not taken in the directory of ‘to’, to write its contents
+under until they are complete.  The name of ‘to’ is left out, as it may
+already be as long as names get. */
+fn temp_name(vfs: &dyn Filesystem, to: &Path) -> PathBuf {
+	static N: AtomicUsize = AtomicUsize::new(0);
+	loop {
+		let n = N.fetch_add(1, Ordering::Relaxed);
+		let tmp = to.with_file_name(format!(".mmv-tmp-{}-{n}", process::id()));
+		if vfs.symlink_metadata(&tmp).is_err() {
+			return tmp;
 		}
 	}
+}
+
+/* Move the contents of the directory ‘from’ into the already existing
+directory ‘to’, recursively merging subdirectories that exist in both.
+Files that exist in both are overwritten. */
+fn merge_dirs(
+	flags: &Flags,
+	vfs: &dyn Filesystem,
+	from: &Path,
+	to: &Path,
+) -> Result<(), (PathBuf, io::Error)> {
+	let entries = vfs.read_dir(from).map_err(|e| (from.to_owned(), e))?;
+	for entry in entries {
+		let name = entry.file_name().unwrap();
+		copy_and_remove_file_or_dir(flags, vfs, &entry, &to.join(name))?;
+	}
+	if !flags.mcp {
+		vfs.remove_dir(from).map_err(|e| (from.to_owned(), e))?;
+	}
 	Ok(())
 }
 
-fn is_terminal(nul: bool, b: &u8) -> bool {
-	*b == (b'\0' + b'\n' * !nul as u8)
+fn is_full(e: &io::Error) -> bool {
+	matches!(
+		e.kind(),
+		io::ErrorKind::StorageFull | io::ErrorKind::QuotaExceeded
+	)
+}
+
+/* Running out of space is usually fixed quickly by the user deleting some
+files, which beats starting a large batch over.  With --retry-delay we wait
+for that to happen on our own, otherwise we ask on the terminal (as the
+standard input holds the list of files).  ‘attempts’ is how often copying the
+file was already retried. */
+fn retry_full(flags: &Flags, from: &Path, to: &Path, e: &io::Error, attempts: u32) -> bool {
+	/* Without anyone to free up space, --assume-yes would otherwise retry
+	as fast as the filesystem fails, and forever.  It gives up after --retries
+	attempts, or FULL_RETRIES without it. */
+	let delay = match flags.assume {
+		Some(true) => {
+			let max = if flags.retries > 0 {
+				flags.retries
+			} else {
+				FULL_RETRIES
+			};
+			if attempts >= max {
+				return false;
+			}
+			Some(flags.retry_delay.unwrap_or(Duration::from_secs(1)))
+		}
+		_ => flags
+			.retry_delay
+			.filter(|_| flags.retries == 0 || attempts < flags.retries),
+	};
+	if let Some(d) = delay {
+		warning!(
+			"no-space",
+			["source" => from, "destination" => to],
+			"{}: {e}; retrying in {}ms",
+			to.to_string_lossy(),
+			d.as_millis()
+		);
+		thread::sleep(d);
+		return true;
+	}
+
+	ask(
+		flags,
+		false,
+		format_args!(
+			"{e} while copying ‘{}’ to ‘{}’; retry once space is freed? [y/N] ",
+			disp(from),
+			disp(to)
+		),
+	)
+}
+
+/* Ask a yes-or-no question on the terminal.  The answer is given by
+--assume-yes or --assume-no if either was, and is ‘default’ when running
+without a user to ask, as from cron(8) or CI: when the standard error is not a
+terminal, there is no controlling terminal, or NO_TTY is set.  The standard
+input is not looked at, as it usually holds the list of files. */
+fn ask(flags: &Flags, default: bool, question: fmt::Arguments) -> bool {
+	if let Some(a) = flags.assume {
+		return a;
+	}
+	if env::var_os("NO_TTY").is_some_and(|v| !v.is_empty()) || !io::stderr().is_terminal() {
+		return default;
+	}
+	let Ok(mut tty) = fs::OpenOptions::new()
+		.read(true)
+		.write(true)
+		.open("/dev/tty")
+	else {
+		return default;
+	};
+	if tty.write_fmt(question).is_err() {
+		return false;
+	}
+	let mut line = String::new();
+	io::BufRead::read_line(&mut BufReader::new(tty), &mut line).is_ok()
+		&& line.trim_start().starts_with(['y', 'Y'])
+}
+
+fn terminator(nul: bool) -> u8 {
+	if nul {
+		b'\0'
+	} else {
+		b'\n'
+	}
+}
+
+fn action(flags: &Flags) -> &'static str {
+	match (flags.mcp, flags.mln, flags.symbolic) {
+		(true, _, _) => "copied",
+		(_, true, true) => "symlinked",
+		(_, true, false) => "linked",
+		_ => "renamed",
+	}
+}
+
+fn disp(pb: &Path) -> Display<'_> {
+	pb.display()
+}
+
+/* Report the operations grouped by the directory of their destinations, so
+that moving files into a tree of directories can be reviewed one directory at a
+time instead of having to pick it apart from a long list. */
+fn report_by_dir<'a>(flags: &Flags, pairs: impl Iterator<Item = (&'a Path, &'a Path)>) {
+	if flags.porcelain {
+		for (s, d) in pairs {
+			print_pair(flags, action(flags), s, d);
+		}
+		return;
+	}
+	let mut dirs: BTreeMap<&Path, Vec<(&Path, &Path)>> = BTreeMap::new();
+	for (s, d) in pairs {
+		dirs.entry(d.parent().unwrap_or(Path::new("/")))
+			.or_default()
+			.push((s, d));
+	}
+	for (dir, pairs) in dirs {
+		eprintln!(
+			"‘{}’: {} file{}",
+			disp(dir),
+			pairs.len(),
+			if pairs.len() == 1 { "" } else { "s" }
+		);
+		for (s, d) in pairs {
+			print_indented(
+				flags,
+				"\t",
+				action(flags),
+				s,
+				Path::new(d.file_name().unwrap()),
+			);
+		}
+	}
+}
+
+/* Report an operation on a pair of paths.  When writing to a terminal, the paths
+are shortened to fit on a single line of it, which keeps reviews of deeply
+nested trees readable.  Nothing is ever shortened when writing elsewhere, as
+the output may well be parsed. */
+fn print_pair(flags: &Flags, verb: &str, from: &Path, to: &Path) {
+	if flags.porcelain {
+		print_porcelain(flags, verb, from, to);
+	} else {
+		print_indented(flags, "", verb, from, to);
+	}
+}
+
+fn print_duplicate(flags: &Flags, s: &Path, d: &Path) {
+	if flags.porcelain {
+		print_porcelain(flags, "trashed", s, d);
+	} else {
+		eprintln!("trashed ‘{}’ (identical to ‘{}’)", disp(s), disp(d));
+	}
+}
+
+/* Report an operation on a pair of paths to the standard output for scripts to
+read.  With -0 the verb and paths are each terminated by a NUL byte and are
+otherwise left as they are.  Without it the fields are separated by spaces on a
+line of their own, and paths are quoted in the manner of C string literals
+whenever they contain anything that would make that ambiguous. */
+fn print_porcelain(flags: &Flags, verb: &str, from: &Path, to: &Path) {
+	let (from, to) = (from.as_os_str().as_bytes(), to.as_os_str().as_bytes());
+	let mut rec = Vec::with_capacity(verb.len() + from.len() + to.len() + 3);
+	if flags.nul {
+		for f in [verb.as_bytes(), from, to] {
+			rec.extend_from_slice(f);
+			rec.push(b'\0');
+		}
+	} else {
+		rec.extend_from_slice(verb.as_bytes());
+		for f in [from, to] {
+			rec.push(b' ');
+			c_quote(&mut rec, f);
+		}
+		rec.push(b'\n');
+	}
+	io::stdout().lock().write_all(&rec).unwrap_or_else(|e| {
+		fatal!("io", [], "stdout: {e}");
+	});
+}
+
+fn c_quote(out: &mut Vec<u8>, bs: &[u8]) {
+	let plain = std::str::from_utf8(bs).is_ok_and(|s| {
+		!s.is_empty()
+			&& !s
+				.bytes()
+				.any(|b| b.is_ascii_control() || matches!(b, b' ' | b'"' | b'\\'))
+	});
+	if plain {
+		out.extend_from_slice(bs);
+		return;
+	}
+
+	out.push(b'"');
+	for chunk in bs.utf8_chunks() {
+		for b in chunk.valid().bytes() {
+			match b {
+				b'\x07' => out.extend_from_slice(b"\\a"),
+				b'\x08' => out.extend_from_slice(b"\\b"),
+				b'\t' => out.extend_from_slice(b"\\t"),
+				b'\n' => out.extend_from_slice(b"\\n"),
+				b'\x0B' => out.extend_from_slice(b"\\v"),
+				b'\x0C' => out.extend_from_slice(b"\\f"),
+				b'\r' => out.extend_from_slice(b"\\r"),
+				b'"' | b'\\' => out.extend_from_slice(&[b'\\', b]),
+				b if b.is_ascii_control() => out.extend_from_slice(format!("\\{b:03o}").as_bytes()),
+				b => out.push(b),
+			}
+		}
+		for b in chunk.invalid() {
+			out.extend_from_slice(format!("\\{b:03o}").as_bytes());
+		}
+	}
+	out.push(b'"');
+}
+
+fn print_indented(flags: &Flags, indent: &str, verb: &str, from: &Path, to: &Path) {
+	let (from, to) = (disp(from).to_string(), disp(to).to_string());
+	let width = match rustix::termios::tcgetwinsize(io::stderr()) {
+		Ok(ws) if !flags.full_paths && ws.ws_col > 0 => ws.ws_col as usize,
+		_ => usize::MAX,
+	};
+
+	/* The indent, the verb, a space, two pairs of quotes, and the arrow */
+	let budget = width.saturating_sub(8 * indent.len() + verb.chars().count() + 9);
+	let (n, m) = (from.chars().count(), to.chars().count());
+	let (from, to) = if n + m <= budget {
+		(from, to)
+	} else {
+		/* Paths shorter than their half of the line leave the rest of it to
+		the other path */
+		let half = budget / 2;
+		let (bn, bm) = match (n <= half, m <= half) {
+			(true, _) => (n, budget - n),
+			(_, true) => (budget - m, m),
+			_ => (half, budget - half),
+		};
+		/* The directories both paths share are the first to go, so that
+		what actually changes stays visible */
+		let common = from
+			.chars()
+			.zip(to.chars())
+			.take_while(|(a, b)| a == b)
+			.enumerate()
+			.filter(|(_, (c, _))| *c == '/')
+			.last()
+			.map_or(0, |(i, _)| i + 1);
+		(elide(&from, bn, common), elide(&to, bm, common))
+	};
+	eprintln!("{indent}{verb} ‘{from}’ -> ‘{to}’");
+}
+
+/* Shorten ‘s’ to ‘n’ characters by replacing its middle with an ellipsis.  More
+of the end than of the start is kept, as that is where the filename is, and
+the first ‘common’ characters that the other path has as well are dropped in
+preference to the rest. */
+fn elide(s: &str, n: usize, common: usize) -> String {
+	/* Shortening to a handful of characters would leave nothing to recognize
+	the path by */
+	let n = n.max(8);
+	let len = s.chars().count();
+	if len <= n {
+		return s.to_string();
+	}
+	let cut = len - (n - 1);
+	let head = match (n - 1) / 3 {
+		head if common > head => head.min(common.saturating_sub(cut)),
+		head => head,
+	};
+	s.chars()
+		.take(head)
+		.chain(iter::once('…'))
+		.chain(s.chars().skip(head + cut))
+		.collect()
 }
 
-fn disp(pb: &PathBuf) -> Display {
-	pb.as_path().display()
+#[cfg(test)]
+mod tests {
+	use std::cell::{Cell, RefCell};
+
+	use super::*;
+
+	/* A filesystem of plain files that runs out of space the first ‘full’
+	times a file is copied, leaving part of the copy behind each time */
+	struct Full {
+		files: RefCell<HashSet<PathBuf>>,
+		full: Cell<u32>,
+		copies: Cell<u32>,
+	}
+
+	impl Full {
+		fn new(full: u32) -> Self {
+			Full {
+				files: RefCell::new(HashSet::from([PathBuf::from("/a")])),
+				full: Cell::new(full),
+				copies: Cell::new(0),
+			}
+		}
+
+		fn files(&self) -> Vec<PathBuf> {
+			self.files.borrow().iter().cloned().sorted().collect()
+		}
+	}
+
+	impl Filesystem for Full {
+		fn metadata(&self, path: &Path) -> io::Result<vfs::Metadata> {
+			self.symlink_metadata(path)
+		}
+
+		fn symlink_metadata(&self, path: &Path) -> io::Result<vfs::Metadata> {
+			match self.files.borrow().contains(path) {
+				true => Ok(vfs::Metadata {
+					is_dir: false,
+					is_symlink: false,
+					mode: 0o644,
+				}),
+				false => Err(io::ErrorKind::NotFound.into()),
+			}
+		}
+
+		fn create_dir(&self, _: &Path) -> io::Result<()> {
+			unimplemented!()
+		}
+
+		fn create_dir_all(&self, _: &Path) -> io::Result<()> {
+			unimplemented!()
+		}
+
+		fn set_mode(&self, _: &Path, _: u32) -> io::Result<()> {
+			Ok(())
+		}
+
+		fn read_dir(&self, _: &Path) -> io::Result<Vec<PathBuf>> {
+			unimplemented!()
+		}
+
+		fn read_link(&self, _: &Path) -> io::Result<PathBuf> {
+			unimplemented!()
+		}
+
+		fn copy(&self, _: &Path, to: &Path) -> io::Result<u64> {
+			self.copies.set(self.copies.get() + 1);
+			self.files.borrow_mut().insert(to.to_owned());
+			if self.full.get() == 0 {
+				return Ok(0);
+			}
+			self.full.set(self.full.get() - 1);
+			Err(io::ErrorKind::StorageFull.into())
+		}
+
+		fn rename(&self, from: &Path, to: &Path) -> io::Result<()> {
+			let mut files = self.files.borrow_mut();
+			files.remove(from);
+			files.insert(to.to_owned());
+			Ok(())
+		}
+
+		fn hard_link(&self, _: &Path, _: &Path) -> io::Result<()> {
+			unimplemented!()
+		}
+
+		fn symlink(&self, _: &Path, _: &Path) -> io::Result<()> {
+			unimplemented!()
+		}
+
+		fn remove_file(&self, path: &Path) -> io::Result<()> {
+			self.files.borrow_mut().remove(path);
+			Ok(())
+		}
+
+		fn remove_dir(&self, _: &Path) -> io::Result<()> {
+			unimplemented!()
+		}
+
+		fn remove_dir_all(&self, _: &Path) -> io::Result<()> {
+			unimplemented!()
+		}
+	}
+
+	fn assume_yes(retries: u32) -> Flags {
+		Flags {
+			mcp: true,
+			assume: Some(true),
+			retries,
+			retry_delay: Some(Duration::ZERO),
+			..Flags::default()
+		}
+	}
+
+	#[test]
+	fn gives_up_when_full() {
+		for (retries, tries) in [(3, 4), (0, FULL_RETRIES + 1)] {
+			let fs = Full::new(u32::MAX);
+			let res = copy_and_remove_file_or_dir(
+				&assume_yes(retries),
+				&fs,
+				"/a".as_ref(),
+				"/b".as_ref(),
+			);
+			let (p, e) = res.unwrap_err();
+			assert_eq!(
+				(p.as_path(), e.kind()),
+				(Path::new("/b"), io::ErrorKind::StorageFull)
+			);
+			assert_eq!(fs.copies.get(), tries);
+			assert_eq!(fs.files(), [Path::new("/a")], "partial copy left behind");
+		}
+	}
+
+	#[test]
+	fn retries_until_space_is_freed() {
+		let fs = Full::new(2);
+		copy_and_remove_file_or_dir(&assume_yes(3), &fs, "/a".as_ref(), "/b".as_ref()).unwrap();
+		assert_eq!(fs.copies.get(), 3);
+		assert_eq!(fs.files(), [Path::new("/a"), Path::new("/b")]);
+	}
 }
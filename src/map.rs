@@ -0,0 +1,54 @@
+use std::{
+	env,
+	io::{self, Read, Write},
+};
+
+use crate::subst::Substitution;
+
+/* The mmv-map utility, which applies the ‘s/RE/REPL/FLAGS’ expressions given
+as its arguments to every line of its input in order, like ‘sed -e … -e …’
+would.  It is meant to be the command of mmv(1) on systems without sed(1), and
+works on NUL-terminated records when given -0 or run with MMV_NUL=1. */
+pub fn run() -> io::Result<()> {
+	use lexopt::prelude::*;
+
+	let mut nul = env::var("MMV_NUL").is_ok_and(|v| v == "1");
+	let mut subst = Vec::new();
+	let mut parser = lexopt::Parser::from_env();
+	let parsed = (|| -> Result<(), lexopt::Error> {
+		while let Some(arg) = parser.next()? {
+			match arg {
+				Short('0') | Long("nul") => nul = true,
+				Short('h') | Long("help") => crate::help_for(crate::options::MAP),
+				Long("version") => crate::version(),
+				Value(v) => subst.push(v.parse_with(Substitution::parse)?),
+				_ => return Err(arg.unexpected()),
+			}
+		}
+		Ok(())
+	})();
+	if let Err(e) = parsed {
+		crate::usage(Some(e));
+	}
+	if subst.is_empty() {
+		crate::usage(None);
+	}
+
+	let mut data = Vec::new();
+	io::stdin().lock().read_to_end(&mut data)?;
+	let sep = if nul { b'\0' } else { b'\n' };
+	let data = data.strip_suffix(&[sep]).unwrap_or(&data);
+	if data.is_empty() {
+		return Ok(());
+	}
+
+	let mut out = io::BufWriter::new(io::stdout().lock());
+	for r in data.split(|&b| b == sep) {
+		let r = String::from_utf8(r.to_vec())
+			.map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "input is not valid UTF-8"))?;
+		let r = subst.iter().fold(r, |acc, s| s.apply(&acc).into_owned());
+		out.write_all(r.as_bytes())?;
+		out.write_all(&[sep])?;
+	}
+	out.flush()
+}
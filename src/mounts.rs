@@ -0,0 +1,156 @@
+use std::{
+	ffi::OsString,
+	fs,
+	os::unix::ffi::OsStringExt,
+	path::{Path, PathBuf},
+};
+
+/* Filesystem types of FUSE mounts that expose devices through protocols which
+only support streaming whole files, such as MTP on phones. */
+const DEGRADED_FSTYPES: &[&str] = &[
+	"fuse.gvfsd-fuse",
+	"fuse.jmtpfs",
+	"fuse.simple-mtpfs",
+	"fuse.go-mtpfs",
+	"fuse.aft-mtp-mount",
+];
+
+pub struct Mount {
+	pub point: PathBuf,
+	pub fstype: String,
+	pub source: String,
+}
+
+impl Mount {
+	pub fn is_degraded(&self) -> bool {
+		DEGRADED_FSTYPES.contains(&self.fstype.as_str())
+	}
+}
+
+/* Parse the mount table of the current process.  If it cannot be read (we are
+not on Linux, /proc is not mounted, etc.) we simply know of no mounts. */
+pub fn mounts() -> Vec<Mount> {
+	match fs::read_to_string("/proc/self/mountinfo") {
+		Ok(table) => parse(&table),
+		Err(_) => Vec::new(),
+	}
+}
+
+/* Parse a mount table in the format of /proc/self/mountinfo */
+fn parse(table: &str) -> Vec<Mount> {
+	table
+		.lines()
+		.filter_map(|line| {
+			let (pre, post) = line.split_once(" - ")?;
+			let point = pre.split(' ').nth(4)?;
+			let mut post = post.split(' ');
+			let fstype = post.next()?;
+			let source = post.next()?;
+			Some(Mount {
+				point: unescape(point),
+				fstype: fstype.to_string(),
+				source: unescape(source).to_string_lossy().into_owned(),
+			})
+		})
+		.collect()
+}
+
+/* Find the mount that the given absolute path lives on.  The path does not
+need to exist. */
+pub fn mount_of<'a>(mounts: &'a [Mount], path: &Path) -> Option<&'a Mount> {
+	mounts
+		.iter()
+		.filter(|m| path.starts_with(&m.point))
+		.max_by_key(|m| m.point.components().count())
+}
+
+/* The mount points of the degraded mounts that any of ‘paths’ are on, each
+once */
+pub fn degraded<'a>(mounts: &'a [Mount], paths: impl Iterator<Item = &'a Path>) -> Vec<&'a Path> {
+	let mut points = Vec::new();
+	for m in paths.filter_map(|p| mount_of(mounts, p)) {
+		if m.is_degraded() && !points.contains(&m.point.as_path()) {
+			points.push(m.point.as_path());
+		}
+	}
+	points
+}
+
+/* The warning given once files were moved to or from the degraded mount at
+‘point’ */
+pub fn degraded_warning(point: &Path) -> String {
+	format!(
+		"“{}” only supports streaming copies; file permissions were not preserved",
+		point.to_string_lossy()
+	)
+}
+
+/* The kernel escapes spaces, tabs, newlines, and backslashes in mount points
+as three-digit octal sequences. */
+fn unescape(s: &str) -> PathBuf {
+	let bs = s.as_bytes();
+	let mut out = Vec::with_capacity(bs.len());
+	let mut i = 0;
+	while i < bs.len() {
+		let b = match bs.get(i..i + 4) {
+			Some([b'\\', o @ ..]) if o.iter().all(|c| (b'0'..=b'7').contains(c)) => {
+				i += 4;
+				o.iter()
+					.fold(0u8, |acc, c| acc.wrapping_mul(8).wrapping_add(c - b'0'))
+			}
+			_ => {
+				i += 1;
+				bs[i - 1]
+			}
+		};
+		out.push(b);
+	}
+	PathBuf::from(OsString::from_vec(out))
+}
+
+#[cfg(test)]
+mod tests {
+	use std::path::Path;
+
+	use super::*;
+
+	const TABLE: &str = "\
+22 1 8:2 / / rw,relatime shared:1 - ext4 /dev/sda2 rw
+45 22 0:41 / /run/user/1000/gvfs rw,nosuid,nodev shared:24 - fuse.gvfsd-fuse gvfsd-fuse rw,user_id=1000
+46 22 0:42 / /media/My\\040Phone rw,nosuid,nodev shared:25 - fuse.jmtpfs jmtpfs rw,user_id=1000
+";
+
+	#[test]
+	fn parses_fuse_mounts() {
+		let ms = parse(TABLE);
+		assert_eq!(ms.len(), 3);
+		assert_eq!(ms[1].point, Path::new("/run/user/1000/gvfs"));
+		assert_eq!(ms[1].fstype, "fuse.gvfsd-fuse");
+		assert_eq!(ms[1].source, "gvfsd-fuse");
+		assert_eq!(ms[2].point, Path::new("/media/My Phone"));
+		assert!(!ms[0].is_degraded() && ms[1].is_degraded() && ms[2].is_degraded());
+	}
+
+	#[test]
+	fn warns_about_degraded_mounts() {
+		let ms = parse(TABLE);
+		let paths = [
+			Path::new("/home/user/a"),
+			Path::new("/run/user/1000/gvfs/mtp:host=phone/DCIM/a"),
+			Path::new("/run/user/1000/gvfs/mtp:host=phone/DCIM/b"),
+			Path::new("/media/My Phone/Music/c"),
+		];
+		let points = degraded(&ms, paths.into_iter());
+		assert_eq!(
+			points,
+			[
+				Path::new("/run/user/1000/gvfs"),
+				Path::new("/media/My Phone")
+			]
+		);
+		assert_eq!(
+			degraded_warning(points[0]),
+			"“/run/user/1000/gvfs” only supports streaming copies; file permissions were not preserved"
+		);
+	}
+}
@@ -0,0 +1,138 @@
+use std::{
+	fs, io,
+	path::{Path, PathBuf},
+	time::UNIX_EPOCH,
+};
+
+/* A template for --nest-by naming the subdirectory of its parent directory
+that a file is moved into.  Text is copied as is, apart from these keys:
+
+	{ext}        the extension of the file, without the dot
+	{date}       the date the file was last modified on, as ‘%Y-%m-%d’
+	{date:fmt}   the same, formatted with the strftime(3) conversions %Y, %m,
+				 %d, %H, %M, %S, and %j in UTC
+	{{ and }}    literal braces */
+pub struct Template(Vec<Part>);
+
+enum Part {
+	Text(String),
+	Ext,
+	Date(String),
+}
+
+impl Template {
+	pub fn parse(s: &str) -> Result<Template, String> {
+		let mut parts = Vec::new();
+		let mut text = String::new();
+		let mut it = s.chars();
+		while let Some(c) = it.next() {
+			match c {
+				'{' if it.as_str().starts_with('{') => {
+					it.next();
+					text.push('{');
+				}
+				'}' if it.as_str().starts_with('}') => {
+					it.next();
+					text.push('}');
+				}
+				'{' => {
+					let rest = it.as_str();
+					let end = rest.find('}').ok_or("unterminated key")?;
+					let part = match rest[..end].split_once(':') {
+						None if &rest[..end] == "ext" => Part::Ext,
+						None if &rest[..end] == "date" => Part::Date("%Y-%m-%d".to_string()),
+						Some(("date", fmt)) => Part::Date(fmt.to_string()),
+						_ => return Err(format!("unknown key ‘{}’", &rest[..end])),
+					};
+					if !text.is_empty() {
+						parts.push(Part::Text(std::mem::take(&mut text)));
+					}
+					parts.push(part);
+					it = rest[end + 1..].chars();
+				}
+				'}' => return Err("unmatched ‘}’".to_string()),
+				c => text.push(c),
+			}
+		}
+		if !text.is_empty() {
+			parts.push(Part::Text(text));
+		}
+		Ok(Template(parts))
+	}
+
+	/* The path ‘src’ is moved to, or None if the template uses a key that the
+	file has no value for, such as the extension of a file without one */
+	pub fn apply(&self, src: &Path) -> io::Result<Option<PathBuf>> {
+		let mut dir = String::new();
+		for p in self.0.iter() {
+			match p {
+				Part::Text(s) => dir.push_str(s),
+				Part::Ext => {
+					let ext = match src.file_name().and_then(|n| n.to_str()) {
+						Some(n) => match n.rfind('.') {
+							Some(0) | None => None,
+							Some(i) => Some(&n[i + 1..]),
+						},
+						None => None,
+					};
+					match ext {
+						Some(e) if !e.is_empty() => dir.push_str(e),
+						_ => return Ok(None),
+					}
+				}
+				Part::Date(fmt) => {
+					let t = fs::symlink_metadata(src)?.modified()?;
+					let secs = match t.duration_since(UNIX_EPOCH) {
+						Ok(d) => d.as_secs() as i64,
+						Err(e) => -(e.duration().as_secs() as i64),
+					};
+					strftime(&mut dir, fmt, secs);
+				}
+			}
+		}
+		let Some(name) = src.file_name() else {
+			return Ok(None);
+		};
+		let parent = src.parent().unwrap_or(Path::new(""));
+		Ok(Some(parent.join(dir).join(name)))
+	}
+}
+
+pub fn strftime(out: &mut String, fmt: &str, secs: i64) {
+	let (days, rem) = (secs.div_euclid(86400), secs.rem_euclid(86400));
+	let (y, m, d) = civil_from_days(days);
+	let yday = days - crate::directives::days_from_civil(y, 1, 1) + 1;
+	let mut it = fmt.chars();
+	while let Some(c) = it.next() {
+		if c != '%' {
+			out.push(c);
+			continue;
+		}
+		let s = match it.next() {
+			Some('Y') => format!("{y:04}"),
+			Some('m') => format!("{m:02}"),
+			Some('d') => format!("{d:02}"),
+			Some('H') => format!("{:02}", rem / 3600),
+			Some('M') => format!("{:02}", rem / 60 % 60),
+			Some('S') => format!("{:02}", rem % 60),
+			Some('j') => format!("{yday:03}"),
+			Some('%') => "%".to_string(),
+			Some(c) => format!("%{c}"),
+			None => "%".to_string(),
+		};
+		out.push_str(&s);
+	}
+}
+
+/* The inverse of days_from_civil() */
+fn civil_from_days(z: i64) -> (i64, i64, i64) {
+	let z = z + 719468;
+	let era = z.div_euclid(146097);
+	let doe = z - era * 146097;
+	let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+	let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+	let mp = (5 * doy + 2) / 153;
+	let d = doy - (153 * mp + 2) / 5 + 1;
+	let m = if mp < 10 { mp + 3 } else { mp - 9 };
+	(yoe + era * 400 + (m <= 2) as i64, m, d)
+}
@@ -0,0 +1,80 @@
+use std::{
+	env, io,
+	path::Path,
+	process::{Command, Stdio},
+	sync::{
+		atomic::{AtomicBool, Ordering},
+		Mutex,
+	},
+};
+
+use crate::report::warning;
+
+/* Whether to send a desktop notification once the run is over, for --notify,
+and what was done by then.  This is global state because runs end in fatal
+errors from all over the place, long after the flags are out of reach. */
+static ENABLED: AtomicBool = AtomicBool::new(false);
+static SUMMARY: Mutex<String> = Mutex::new(String::new());
+
+pub fn enable() {
+	ENABLED.store(true, Ordering::Relaxed);
+}
+
+/* Set what the notification of a successful run says was done */
+pub fn summarize(s: String) {
+	*SUMMARY.lock().unwrap() = s;
+}
+
+pub fn succeeded() {
+	let s = SUMMARY.lock().unwrap().clone();
+	send(false, "finished", if s.is_empty() { "Done" } else { &s });
+}
+
+pub fn failed(msg: &str) {
+	send(true, "failed", msg);
+}
+
+/* Notifications go through notify-send(1) from libnotify, which speaks the
+freedesktop.org notification protocol, and through osascript(1) on macOS.
+Failing to send one is only worth a warning, as the run is over by then. */
+fn send(urgent: bool, what: &str, body: &str) {
+	if !ENABLED.swap(false, Ordering::Relaxed) {
+		return;
+	}
+	let argv0 = env::args_os().next().unwrap_or_default();
+	let title = format!(
+		"{} {what}",
+		Path::new(&argv0)
+			.file_name()
+			.unwrap_or_default()
+			.to_string_lossy()
+	);
+	let (cmd, args) = if cfg!(target_os = "macos") {
+		let quote = |s: &str| format!("\"{}\"", s.replace('\\', "\\\\").replace('"', "\\\""));
+		let script = format!(
+			"display notification {} with title {}",
+			quote(body),
+			quote(&title)
+		);
+		("osascript", vec!["-e".to_string(), script])
+	} else {
+		let urgency = if urgent { "critical" } else { "normal" };
+		(
+			"notify-send",
+			vec![format!("--urgency={urgency}"), title, body.to_string()],
+		)
+	};
+	let res = Command::new(cmd)
+		.args(args)
+		.stdin(Stdio::null())
+		.stdout(Stdio::null())
+		.status()
+		.map_err(|e| io::Error::new(e.kind(), format!("{cmd}: {e}")))
+		.and_then(|s| match s.success() {
+			true => Ok(()),
+			false => Err(io::Error::other(format!("{cmd} failed with {s}"))),
+		});
+	if let Err(e) = res {
+		warning!("notify", [], "Could not send a notification: {e}");
+	}
+}
@@ -0,0 +1,399 @@
+use std::io::{self, Write};
+
+/* The utilities an option applies to */
+pub const MMV: u8 = 1 << 0;
+pub const MCP: u8 = 1 << 1;
+pub const MLN: u8 = 1 << 2;
+pub const MRM: u8 = 1 << 3;
+pub const MAP: u8 = 1 << 4;
+const ALL: u8 = MMV | MCP | MLN | MRM;
+
+/* Every option that Flags::parse() accepts as listed by --help, as the flags
+and argument, the utilities it applies to, and what it does.  Flags::parse()
+refuses the options that this table does not list for the utility, so the two
+cannot disagree, and anything else listing the options, such as shell
+completions, is to be generated from it too.  mln has no -s for --substitute,
+as that makes symbolic links instead. */
+pub const OPTIONS: &[(&str, u8, &str)] = &[
+	(
+		"-0, --nul",
+		ALL | MAP,
+		"read and write NUL-terminated filenames",
+	),
+	(
+		"    --allow-length-mismatch",
+		ALL,
+		"let --numbered commands leave out files",
+	),
+	(
+		"    --allow-weird-names",
+		ALL,
+		"allow control characters in output files",
+	),
+	("    --assume-no", ALL, "answer no to every question"),
+	("    --assume-yes", ALL, "answer yes to every question"),
+	(
+		"-b, --basename",
+		ALL,
+		"only map the basenames of the input files",
+	),
+	(
+		"    --changed=policy",
+		MMV | MCP | MLN,
+		"handle inputs changed while mapping",
+	),
+	(
+		"    --check",
+		MMV | MCP | MLN,
+		"check the output files once done",
+	),
+	(
+		"    --check-contents",
+		MMV | MCP | MLN,
+		"also compare the contents of copies",
+	),
+	(
+		"    --check-open",
+		MMV | MCP | MRM,
+		"refuse inputs open for writing",
+	),
+	(
+		"    --ci-duplicates",
+		MMV | MCP | MLN,
+		"refuse outputs only differing in case",
+	),
+	(
+		"-C, --chdir dir",
+		MMV | MCP | MLN,
+		"resolve output paths against dir",
+	),
+	(
+		"    --conservative",
+		ALL,
+		"never rename, only stream whole files",
+	),
+	(
+		"    --copy-threshold size",
+		MMV,
+		"ask before copying larger files",
+	),
+	(
+		"    --dir-mode mode",
+		MMV | MCP,
+		"give created directories this mode",
+	),
+	(
+		"-d, --dry-run",
+		ALL,
+		"print what would be done, and do nothing",
+	),
+	(
+		"    --emit-script=shell",
+		ALL,
+		"print a sh or pwsh script instead",
+	),
+	("-e, --encode", ALL, "encode newlines in filenames"),
+	(
+		"    --encoding=version",
+		ALL,
+		"encode filenames as of this version",
+	),
+	(
+		"    --errors=format",
+		ALL,
+		"print diagnostics as text or json",
+	),
+	(
+		"    --expect-digest digest",
+		MMV | MCP | MLN,
+		"refuse plans with another digest",
+	),
+	(
+		"    --fix-symlinks",
+		MMV,
+		"keep relative links in moved trees working",
+	),
+	(
+		"    --flatten dir",
+		MMV | MCP | MLN,
+		"move every file under the inputs into dir",
+	),
+	(
+		"    --force-chain",
+		MMV,
+		"with --undo, also undo the later runs",
+	),
+	(
+		"    --format=format",
+		ALL,
+		"pass filenames to the command as format",
+	),
+	#[cfg(feature = "clipboard")]
+	(
+		"    --from-clipboard",
+		ALL,
+		"read the input files from the clipboard",
+	),
+	(
+		"    --from-file file",
+		ALL,
+		"read the input files from file",
+	),
+	("    --full-paths", ALL, "never shorten the paths reported"),
+	(
+		"    --group group",
+		MMV | MCP,
+		"change the group of the output files",
+	),
+	(
+		"    --group-by-dir",
+		MMV | MCP | MLN,
+		"report files by directory",
+	),
+	("-h, --help", ALL | MAP, "print this help and exit"),
+	("    --history", MMV, "list the runs kept in the history"),
+	(
+		"    --import format file",
+		MMV | MCP | MLN,
+		"read the plan from file",
+	),
+	(
+		"-i, --individual",
+		ALL,
+		"run the command once for every file",
+	),
+	("    --input fd", ALL, "read the input files from fd"),
+	(
+		"    --interactive",
+		MMV | MCP | MLN,
+		"ask before replacing existing files",
+	),
+	(
+		"    --into-dirs",
+		ALL,
+		"move files into existing directories",
+	),
+	("-j, --jobs n", ALL, "with -i, run up to n commands at once"),
+	(
+		"    --join table",
+		MMV | MCP | MLN,
+		"give the command the rows of table",
+	),
+	(
+		"    --key column",
+		MMV | MCP | MLN,
+		"look up --join rows by column",
+	),
+	(
+		"    --limit n",
+		MMV | MCP | MLN,
+		"only rename the first n files",
+	),
+	(
+		"    --link-dest dir",
+		MCP,
+		"hard link files identical to those in dir",
+	),
+	(
+		"    --lint rules",
+		MMV | MCP | MLN,
+		"check filenames against rules",
+	),
+	(
+		"    --map-prefix from=to",
+		MMV | MCP | MLN,
+		"replace the prefix of outputs",
+	),
+	(
+		"    --map-sources",
+		MMV | MCP | MLN,
+		"also map the prefixes of inputs",
+	),
+	(
+		"    --merge",
+		MMV | MCP,
+		"merge directories into existing ones",
+	),
+	(
+		"    --merge-identical",
+		MMV,
+		"trash identical files with the same output",
+	),
+	(
+		"    --metadata",
+		MMV | MCP,
+		"read directives after output files",
+	),
+	(
+		"    --missing=policy",
+		ALL,
+		"handle input files that do not exist",
+	),
+	("    --mode mode", MCP, "give the copies this mode"),
+	(
+		"    --nest-by template",
+		MMV | MCP | MLN,
+		"move files into subdirectories",
+	),
+	(
+		"-n, --no-trash",
+		MRM,
+		"remove files instead of trashing them",
+	),
+	("-n, --no-backup", MMV, "do not back up the input files"),
+	#[cfg(feature = "notify")]
+	("    --notify", ALL, "send a desktop notification once done"),
+	#[cfg(feature = "indexer")]
+	(
+		"    --notify-indexer",
+		MMV | MCP | MLN,
+		"tell the search indexer about outputs",
+	),
+	(
+		"    --numbered",
+		ALL,
+		"number the filenames given to the command",
+	),
+	(
+		"    --order=order",
+		MMV | MCP,
+		"choose the order files are staged in",
+	),
+	(
+		"    --owner user",
+		MMV | MCP,
+		"change the owner of the output files",
+	),
+	(
+		"    --partial-apply",
+		ALL,
+		"with -i, offer to apply the mapped files",
+	),
+	(
+		"    --porcelain",
+		MMV | MCP | MLN,
+		"report operations in a stable format",
+	),
+	("    --prune-empty", MMV, "remove directories left empty"),
+	(
+		"    --quarantine=policy",
+		MMV | MCP,
+		"handle the macOS quarantine flag",
+	),
+	(
+		"    --relative-to srcroot dstroot",
+		MMV | MCP | MLN,
+		"mirror a tree",
+	),
+	("    --restore dir", MMV | MCP, "undo an interrupted run"),
+	(
+		"    --restrict dir",
+		MMV | MCP | MLN,
+		"refuse outputs outside of dir",
+	),
+	("    --resume dir", MMV | MCP, "finish an interrupted run"),
+	("    --retries n", ALL, "retry failed operations n times"),
+	(
+		"    --retry-delay ms",
+		ALL,
+		"wait ms before retrying when out of space",
+	),
+	(
+		"    --review-stats",
+		MMV | MCP | MLN,
+		"summarize the changes first",
+	),
+	(
+		"    --sample n",
+		MMV | MCP | MLN,
+		"only rename n files picked at random",
+	),
+	(
+		"    --session name",
+		MMV,
+		"record the run as part of a session",
+	),
+	(
+		"    --snapshot",
+		MMV,
+		"snapshot the filesystems instead of backing up",
+	),
+	("-s, --symbolic", MLN, "create symbolic links"),
+	(
+		"-s, --substitute expression",
+		ALL & !MLN,
+		"map filenames with s/re/repl/",
+	),
+	(
+		"    --substitute expression",
+		MLN,
+		"map filenames with s/re/repl/",
+	),
+	("    --timings", ALL, "report the slowest operations"),
+	#[cfg(feature = "clipboard")]
+	(
+		"    --to-clipboard",
+		MMV | MCP | MLN,
+		"with -d, copy the plan to the clipboard",
+	),
+	(
+		"    --tree",
+		MMV | MCP | MLN,
+		"print the affected directories",
+	),
+	("    --undo id | name", MMV, "undo a run from the history"),
+	("    --uri", ALL, "read the input files as file:// URIs"),
+	("-v, --verbose", ALL, "report every operation"),
+	("    --version", ALL | MAP, "print the version and exit"),
+	(
+		"    --watch dir",
+		MMV | MCP | MLN,
+		"process files as they appear in dir",
+	),
+];
+
+/* Write the options that apply to the utility ‘util’ for --help */
+pub fn list(out: &mut dyn Write, util: u8) -> io::Result<()> {
+	let opts = OPTIONS.iter().filter(|(_, u, _)| u & util != 0);
+	let width = opts.clone().map(|(h, _, _)| h.len()).max().unwrap_or(0);
+	for (head, _, help) in opts {
+		writeln!(out, "  {head:width$}  {help}")?;
+	}
+	Ok(())
+}
+
+/* Whether the option ‘arg’ is listed for the utility ‘util’.  Values are not
+options, so they always are. */
+pub fn applies(arg: &lexopt::Arg, util: u8) -> bool {
+	let names = |head: &'static str| {
+		let (short, long) = match head.trim_start().split_once(", ") {
+			Some((s, l)) => (s.chars().nth(1), l),
+			None => (None, head.trim_start()),
+		};
+		let long = long.trim_start_matches('-').split([' ', '=']).next();
+		(short, long.unwrap_or_default())
+	};
+	let matches = |head| match (arg, names(head)) {
+		(lexopt::Arg::Short(c), (s, _)) => s == Some(*c),
+		(lexopt::Arg::Long(n), (_, l)) => *n == l,
+		(lexopt::Arg::Value(_), _) => true,
+	};
+	OPTIONS
+		.iter()
+		.any(|(head, u, _)| u & util != 0 && matches(head))
+}
+
+/* The optional parts of the utilities that this build has */
+pub fn features() -> Vec<&'static str> {
+	let mut fs = Vec::new();
+	if cfg!(feature = "clipboard") {
+		fs.push("clipboard");
+	}
+	if cfg!(feature = "indexer") {
+		fs.push("indexer");
+	}
+	if cfg!(feature = "notify") {
+		fs.push("notify");
+	}
+	fs
+}
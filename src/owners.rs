@@ -0,0 +1,36 @@
+use std::{fs, io, os::unix::fs::lchown, path::Path};
+
+/* Look up a user or group either by its numeric ID or by its name in
+/etc/passwd or /etc/group.  Users and groups only known to other NSS sources
+such as LDAP must be given by ID. */
+pub fn user(s: &str) -> Result<u32, &'static str> {
+	lookup("/etc/passwd", s).ok_or("unknown user")
+}
+
+pub fn group(s: &str) -> Result<u32, &'static str> {
+	lookup("/etc/group", s).ok_or("unknown group")
+}
+
+fn lookup(db: &str, s: &str) -> Option<u32> {
+	if let Ok(id) = s.parse() {
+		return Some(id);
+	}
+	fs::read_to_string(db).ok()?.lines().find_map(|l| {
+		let mut fields = l.split(':');
+		let name = fields.next()?;
+		let id = fields.nth(1)?;
+		(name == s).then(|| id.parse().ok()).flatten()
+	})
+}
+
+/* Give ‘path’ and, if it is a directory, everything inside of it the given
+owner and group.  Symbolic links are changed themselves and never followed. */
+pub fn chown_all(path: &Path, uid: Option<u32>, gid: Option<u32>) -> io::Result<()> {
+	lchown(path, uid, gid)?;
+	if fs::symlink_metadata(path)?.is_dir() {
+		for e in fs::read_dir(path)? {
+			chown_all(&e?.path(), uid, gid)?;
+		}
+	}
+	Ok(())
+}
@@ -0,0 +1,272 @@
+use std::{
+	collections::{HashMap, HashSet},
+	fs, io,
+	os::unix::fs::MetadataExt,
+	path::{Path, PathBuf},
+	process,
+};
+
+use rustix::{
+	fs::{accessat, Access, AtFlags, CWD},
+	io::Errno,
+};
+
+/* Find every path among the sources and destinations of a run that we will
+not be able to modify, so that they can all be reported before anything is
+touched instead of one-by-one as the run fails part way through.  Moving or
+removing a file requires write access to its containing directory, and fails
+outright if the file is immutable or on a read-only mount; copying only reads
+its sources. */
+pub fn check<'a>(
+	copy: bool,
+	srcs: impl Iterator<Item = &'a Path>,
+	dsts: impl Iterator<Item = &'a Path>,
+) -> Vec<(PathBuf, io::Error)> {
+	let mut errs = Vec::new();
+	for s in srcs {
+		let res = if copy {
+			access(s, Access::READ_OK)
+		} else {
+			modifiable(s).and_then(|_| parent(s).map_or(Ok(()), writable))
+		};
+		if let Err(e) = res {
+			errs.push((s.to_owned(), e));
+		}
+	}
+	for d in dsts {
+		/* Destinations that do not exist yet are only reported through their
+		parent directory. */
+		let res = match access(d, Access::EXISTS) {
+			Ok(()) => modifiable(d),
+			Err(_) => Ok(()),
+		}
+		.and_then(|_| match parent(d) {
+			Some(p) if access(p, Access::EXISTS).is_ok() => writable(p),
+			_ => Ok(()),
+		});
+		if let Err(e) = res {
+			errs.push((d.to_owned(), e));
+		}
+	}
+	errs
+}
+
+fn parent(p: &Path) -> Option<&Path> {
+	p.parent().filter(|p| !p.as_os_str().is_empty())
+}
+
+fn access(p: &Path, mode: Access) -> io::Result<()> {
+	accessat(CWD, p, mode, AtFlags::EACCESS).map_err(io::Error::from)
+}
+
+fn writable(dir: &Path) -> io::Result<()> {
+	access(dir, Access::WRITE_OK).map_err(|e| {
+		io::Error::new(
+			e.kind(),
+			format!(
+				"cannot modify the directory “{}”: {e}",
+				dir.to_string_lossy()
+			),
+		)
+	})
+}
+
+/* Lacking write permission on a file itself does not stop us from moving it,
+but the immutable flag and read-only mounts do, and the kernel reports those
+as distinct errors. */
+fn modifiable(p: &Path) -> io::Result<()> {
+	match accessat(
+		CWD,
+		p,
+		Access::WRITE_OK,
+		AtFlags::EACCESS | AtFlags::SYMLINK_NOFOLLOW,
+	) {
+		Err(e) if e == Errno::PERM || e == Errno::ROFS => Err(e.into()),
+		_ => Ok(()),
+	}
+}
+
+/* Find the destinations that are existing files other than the sources, which
+the run replaces or fails to write over.  On filesystems that fold case or
+normalize Unicode a destination may name an existing file spelled differently,
+which is returned along with it.  Such a file is told apart from a source being
+renamed to another spelling of its own name by its inode.  Empty directories
+lose nothing by being replaced, and with ‘merge’ directories are merged into
+instead of replaced, so those are left out. */
+pub fn bystanders<'a>(
+	merge: bool,
+	srcs: impl Iterator<Item = &'a Path>,
+	dsts: impl Iterator<Item = &'a Path>,
+) -> Vec<(&'a Path, Option<PathBuf>)> {
+	let ids: HashSet<(u64, u64)> = srcs
+		.filter_map(|s| fs::symlink_metadata(s).ok())
+		.map(|m| (m.dev(), m.ino()))
+		.collect();
+	dsts.filter_map(|d| {
+		let m = fs::symlink_metadata(d).ok()?;
+		if ids.contains(&(m.dev(), m.ino()))
+			|| m.is_dir() && (merge || fs::read_dir(d).is_ok_and(|mut es| es.next().is_none()))
+		{
+			return None;
+		}
+		let (Some(dir), Some(name)) = (parent(d), d.file_name()) else {
+			return Some((d, None));
+		};
+		let es = fs::read_dir(dir)
+			.into_iter()
+			.flatten()
+			.filter_map(Result::ok)
+			.collect::<Vec<_>>();
+		if es.iter().any(|e| e.file_name() == name) {
+			return Some((d, None));
+		}
+		let other = es
+			.iter()
+			.find(|e| e.metadata().is_ok_and(|x| x.ino() == m.ino()))
+			.map(|e| e.path());
+		Some((d, other))
+	})
+	.collect()
+}
+
+/* Find the sources that another process has open for writing, along with the
+ID of one such process, by going through the file descriptors listed in /proc.
+A directory counts as open when a file anywhere inside of it is.  Processes we
+are not allowed to look into are skipped, so this is only thorough for root. */
+pub fn open_for_writing<'a>(
+	srcs: impl Iterator<Item = &'a Path>,
+) -> io::Result<Vec<(&'a Path, u32)>> {
+	let mut inodes = HashMap::new();
+	let mut paths = Vec::new();
+	for e in fs::read_dir("/proc")? {
+		let e = e?;
+		let Some(pid) = e.file_name().to_str().and_then(|s| s.parse().ok()) else {
+			continue;
+		};
+		if pid == process::id() {
+			continue;
+		}
+		let Ok(fds) = fs::read_dir(e.path().join("fd")) else {
+			continue;
+		};
+		for fd in fds.filter_map(Result::ok) {
+			if !writing(&e.path().join("fdinfo").join(fd.file_name())) {
+				continue;
+			}
+			/* Pipes, sockets, and terminals are of no interest */
+			match fs::metadata(fd.path()) {
+				Ok(m) if m.is_file() => inodes.insert((m.dev(), m.ino()), pid),
+				_ => continue,
+			};
+			if let Ok(p) = fs::read_link(fd.path()) {
+				paths.push((p, pid));
+			}
+		}
+	}
+
+	Ok(srcs
+		.filter_map(|s| {
+			let m = fs::symlink_metadata(s).ok()?;
+			if let Some(&pid) = inodes.get(&(m.dev(), m.ino())) {
+				return Some((s, pid));
+			}
+			if !m.is_dir() {
+				return None;
+			}
+			let dir = fs::canonicalize(s).ok()?;
+			paths
+				.iter()
+				.find(|(p, _)| p.starts_with(&dir))
+				.map(|&(_, pid)| (s, pid))
+		})
+		.collect())
+}
+
+/* Whether the file descriptor described by the /proc fdinfo file at ‘path’
+was opened with O_WRONLY or O_RDWR */
+fn writing(path: &Path) -> bool {
+	fs::read_to_string(path)
+		.ok()
+		.and_then(|s| {
+			let flags = s.lines().find_map(|l| l.strip_prefix("flags:"))?;
+			u32::from_str_radix(flags.trim(), 8).ok()
+		})
+		.is_some_and(|f| f & 0o3 != 0)
+}
+
+/* Find the destinations that do not lie under ‘root’ once every symbolic link
+along the way has been resolved; a destination need not exist yet, in which
+case its nearest existing ancestor is resolved instead.  ‘root’ must already
+be canonical. */
+pub fn escaping<'a>(root: &Path, dsts: impl Iterator<Item = &'a Path>) -> Vec<&'a Path> {
+	dsts.filter(|d| !resolve(d).starts_with(root)).collect()
+}
+
+fn resolve(p: &Path) -> PathBuf {
+	for a in p.ancestors() {
+		if let Ok(c) = fs::canonicalize(a) {
+			return match p.strip_prefix(a) {
+				Ok(rest) if !rest.as_os_str().is_empty() => c.join(rest),
+				_ => c,
+			};
+		}
+	}
+	p.to_owned()
+}
+
+/* Of the pairs of destinations that differ only in case that Plan::validate()
+found, keep those that end up as the same file because they are on filesystems
+that look like they are case-insensitive, or all of them if ‘force’ is set. */
+pub fn case_collisions<'a>(
+	force: bool,
+	pairs: impl Iterator<Item = (&'a Path, &'a Path)>,
+) -> Vec<(&'a Path, &'a Path)> {
+	let mut insensitive = HashMap::new();
+	pairs
+		.filter(|(_, d)| {
+			let dir = d
+				.ancestors()
+				.skip(1)
+				.find(|a| fs::symlink_metadata(a).is_ok())
+				.unwrap_or(Path::new("/"));
+			force
+				|| *insensitive
+					.entry(dir.to_owned())
+					.or_insert_with(|| case_insensitive(dir))
+		})
+		.collect()
+}
+
+/* There is no portable way to ask a filesystem whether it folds case, so look
+for a file in ‘dir’ (or ‘dir’ itself) with letters in its name and check if
+the same file is found when changing their case. */
+fn case_insensitive(dir: &Path) -> bool {
+	let same = |p: &Path| {
+		let name = p.file_name()?.to_str()?;
+		let swapped = name
+			.chars()
+			.map(|c| match c.is_lowercase() {
+				true => c.to_uppercase().next().unwrap(),
+				false => c.to_lowercase().next().unwrap(),
+			})
+			.collect::<String>();
+		if swapped == name {
+			return None;
+		}
+		let (a, b) = (
+			fs::symlink_metadata(p).ok()?,
+			fs::symlink_metadata(p.with_file_name(swapped)),
+		);
+		Some(b.is_ok_and(|b| (a.dev(), a.ino()) == (b.dev(), b.ino())))
+	};
+
+	if let Some(x) = same(dir) {
+		return x;
+	}
+	fs::read_dir(dir)
+		.into_iter()
+		.flatten()
+		.filter_map(Result::ok)
+		.find_map(|e| same(&e.path()))
+		.unwrap_or(false)
+}
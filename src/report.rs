@@ -0,0 +1,114 @@
+use std::{
+	fmt,
+	os::unix::ffi::OsStrExt,
+	path::Path,
+	process,
+	sync::atomic::{AtomicBool, Ordering},
+};
+
+use cerm::{err_code, warn};
+
+/* Whether errors and warnings are written as JSON lines instead of prose.  This
+is global state because diagnostics are emitted from all over the place, often
+long before or after we have access to the parsed flags. */
+static JSON: AtomicBool = AtomicBool::new(false);
+
+pub fn set_json(json: bool) {
+	JSON.store(json, Ordering::Relaxed);
+}
+
+/* Report a fatal error and exit.  Every diagnostic has a stable ‘code’ that
+programs wrapping us can match on, and the paths involved tagged with the
+role they played, so that they need not parse the message itself.  We exit
+with a status of 1 unless told otherwise with a leading ‘exit status,’. */
+macro_rules! fatal {
+	(exit $status:expr, $code:expr, [$($role:expr => $path:expr),* $(,)?], $($fmt:tt)+) => {
+		$crate::report::print_fatal(
+			$status,
+			$code,
+			&[$(($role, ::std::path::Path::new($path))),*],
+			format_args!($($fmt)+),
+		)
+	};
+	($code:expr, [$($role:expr => $path:expr),* $(,)?], $($fmt:tt)+) => {
+		$crate::report::print_fatal(
+			1,
+			$code,
+			&[$(($role, ::std::path::Path::new($path))),*],
+			format_args!($($fmt)+),
+		)
+	};
+}
+
+/* Like fatal!, but carry on afterwards */
+macro_rules! warning {
+	($code:expr, [$($role:expr => $path:expr),* $(,)?], $($fmt:tt)+) => {
+		$crate::report::print_warning(
+			$code,
+			&[$(($role, ::std::path::Path::new($path))),*],
+			format_args!($($fmt)+),
+		)
+	};
+}
+
+pub(crate) use {fatal, warning};
+
+pub fn print_fatal(status: i32, code: &str, paths: &[(&str, &Path)], msg: fmt::Arguments) -> ! {
+	#[cfg(feature = "notify")]
+	crate::notify::failed(&msg.to_string());
+	if JSON.load(Ordering::Relaxed) {
+		eprintln!("{}", json("error", code, paths, msg));
+		process::exit(status);
+	}
+	err_code!(status, "{msg}");
+}
+
+pub fn print_warning(code: &str, paths: &[(&str, &Path)], msg: fmt::Arguments) {
+	if JSON.load(Ordering::Relaxed) {
+		eprintln!("{}", json("warning", code, paths, msg));
+	} else {
+		warn!("{msg}");
+	}
+}
+
+/* Paths are given as arrays of bytes, as they need not be valid UTF-8 and JSON
+strings cannot hold anything else. */
+fn json(level: &str, code: &str, paths: &[(&str, &Path)], msg: fmt::Arguments) -> String {
+	let paths = paths
+		.iter()
+		.map(|(role, p)| {
+			let bytes = p
+				.as_os_str()
+				.as_bytes()
+				.iter()
+				.map(u8::to_string)
+				.collect::<Vec<_>>()
+				.join(",");
+			format!("{{\"role\":{},\"path\":[{bytes}]}}", string(role))
+		})
+		.collect::<Vec<_>>()
+		.join(",");
+	format!(
+		"{{\"level\":{},\"code\":{},\"message\":{},\"paths\":[{paths}]}}",
+		string(level),
+		string(code),
+		string(&msg.to_string())
+	)
+}
+
+pub fn string(s: &str) -> String {
+	let mut out = String::with_capacity(s.len() + 2);
+	out.push('"');
+	for c in s.chars() {
+		match c {
+			'"' => out.push_str("\\\""),
+			'\\' => out.push_str("\\\\"),
+			'\n' => out.push_str("\\n"),
+			'\t' => out.push_str("\\t"),
+			c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+			c => out.push(c),
+		}
+	}
+	out.push('"');
+	out
+}
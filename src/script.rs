@@ -0,0 +1,136 @@
+use std::path::Path;
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Shell {
+	Sh,
+	Pwsh,
+}
+
+/* A path in a script, which is either an ordinary path or the name of a file in
+the staging directory that the script creates for itself. */
+pub enum Arg<'a> {
+	Path(&'a Path),
+	Stage(&'a Path),
+}
+
+/* A shell script performing the same operations as a run would, for users to
+review or to run themselves on machines without these utilities. */
+pub struct Script {
+	shell: Shell,
+	staged: bool,
+	out: String,
+}
+
+impl Script {
+	pub fn new(shell: Shell, staged: bool) -> Script {
+		let mut out = String::new();
+		match shell {
+			Shell::Sh => {
+				out.push_str("#!/bin/sh\n\nset -e\n");
+				if staged {
+					out.push_str("tmp=\"$(mktemp -d)\"\n");
+				}
+			}
+			Shell::Pwsh => {
+				out.push_str("$ErrorActionPreference = 'Stop'\n");
+				if staged {
+					out.push_str(concat!(
+						"$tmp = Join-Path ([IO.Path]::GetTempPath()) ",
+						"([IO.Path]::GetRandomFileName())\n",
+						"New-Item -ItemType Directory -Path $tmp | Out-Null\n",
+					));
+				}
+			}
+		}
+		out.push('\n');
+		Script { shell, staged, out }
+	}
+
+	pub fn rename(&mut self, from: Arg, to: Arg) {
+		let line = match self.shell {
+			Shell::Sh => format!("mv -- {} {}", self.arg(from), self.arg(to)),
+			Shell::Pwsh => format!(
+				"Move-Item -LiteralPath {} -Destination {}",
+				self.arg(from),
+				self.arg(to)
+			),
+		};
+		self.line(line);
+	}
+
+	pub fn copy(&mut self, from: Arg, to: Arg) {
+		let line = match self.shell {
+			Shell::Sh => format!("cp -R -- {} {}", self.arg(from), self.arg(to)),
+			Shell::Pwsh => format!(
+				"Copy-Item -Recurse -LiteralPath {} -Destination {}",
+				self.arg(from),
+				self.arg(to)
+			),
+		};
+		self.line(line);
+	}
+
+	pub fn link(&mut self, symbolic: bool, from: Arg, to: Arg) {
+		let line = match self.shell {
+			Shell::Sh => format!(
+				"ln {}-- {} {}",
+				if symbolic { "-s " } else { "" },
+				self.arg(from),
+				self.arg(to)
+			),
+			Shell::Pwsh => format!(
+				"New-Item -ItemType {} -Path {} -Target {} | Out-Null",
+				if symbolic { "SymbolicLink" } else { "HardLink" },
+				self.arg(to),
+				self.arg(from)
+			),
+		};
+		self.line(line);
+	}
+
+	pub fn remove(&mut self, path: Arg) {
+		let line = match self.shell {
+			Shell::Sh => format!("rm -rf -- {}", self.arg(path)),
+			Shell::Pwsh => format!(
+				"Remove-Item -Recurse -Force -LiteralPath {}",
+				self.arg(path)
+			),
+		};
+		self.line(line);
+	}
+
+	pub fn finish(mut self) -> String {
+		if self.staged {
+			self.out.push('\n');
+			self.out.push_str(match self.shell {
+				Shell::Sh => "rmdir -- \"$tmp\"\n",
+				Shell::Pwsh => "Remove-Item -LiteralPath $tmp\n",
+			});
+		}
+		self.out
+	}
+
+	fn line(&mut self, line: String) {
+		self.out.push_str(&line);
+		self.out.push('\n');
+	}
+
+	fn arg(&self, a: Arg) -> String {
+		match (a, self.shell) {
+			(Arg::Path(p), _) => self.quote(&p.to_string_lossy()),
+			(Arg::Stage(p), Shell::Sh) => format!("\"$tmp\"/{}", self.quote(&p.to_string_lossy())),
+			(Arg::Stage(p), Shell::Pwsh) => {
+				format!("(Join-Path $tmp {})", self.quote(&p.to_string_lossy()))
+			}
+		}
+	}
+
+	/* Both shells take single-quoted strings literally, except for the quote
+	itself. */
+	fn quote(&self, s: &str) -> String {
+		match self.shell {
+			Shell::Sh => format!("'{}'", s.replace('\'', "'\\''")),
+			Shell::Pwsh => format!("'{}'", s.replace('\'', "''")),
+		}
+	}
+}
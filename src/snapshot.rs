@@ -0,0 +1,96 @@
+use std::{
+	fs, io,
+	os::unix::fs::MetadataExt,
+	path::{Path, PathBuf},
+	process::{Command, Stdio},
+};
+
+use itertools::Itertools;
+
+use crate::mounts;
+
+enum Target {
+	Btrfs(PathBuf),
+	Zfs(String),
+}
+
+/* Take a read-only snapshot of every btrfs subvolume and ZFS dataset that the
+files ‘paths’ are on, for --snapshot, and return the names of the snapshots.
+Snapshots of btrfs subvolumes are taken into ‘.mmv-name’ at the root of the
+subvolume, and those of ZFS datasets are named ‘dataset@mmv-name’. */
+pub fn take<'a>(paths: impl Iterator<Item = &'a PathBuf>, name: &str) -> io::Result<Vec<String>> {
+	let ms = mounts::mounts();
+	let targets = paths
+		.map(|p| {
+			let m = mounts::mount_of(&ms, p);
+			match m.map(|m| m.fstype.as_str()) {
+				Some("btrfs") => subvolume(p).map(Target::Btrfs),
+				Some("zfs") => Ok(Target::Zfs(m.unwrap().source.clone())),
+				_ => Err(io::Error::new(
+					io::ErrorKind::Unsupported,
+					format!("{}: not on a btrfs or ZFS filesystem", p.display()),
+				)),
+			}
+		})
+		.collect::<io::Result<Vec<_>>>()?;
+
+	let mut taken = Vec::new();
+	for t in targets.into_iter().unique_by(|t| match t {
+		Target::Btrfs(p) => p.to_string_lossy().into_owned(),
+		Target::Zfs(d) => format!("{d}@"),
+	}) {
+		let (cmd, args, snap) = match t {
+			Target::Btrfs(p) => {
+				let snap = p.join(format!(".mmv-{name}"));
+				let args = vec![
+					"subvolume".into(),
+					"snapshot".into(),
+					"-r".into(),
+					p.into_os_string(),
+					snap.clone().into_os_string(),
+				];
+				("btrfs", args, snap.to_string_lossy().into_owned())
+			}
+			Target::Zfs(d) => {
+				let snap = format!("{d}@mmv-{name}");
+				("zfs", vec!["snapshot".into(), snap.clone().into()], snap)
+			}
+		};
+		let out = Command::new(cmd)
+			.args(args)
+			.stdin(Stdio::null())
+			.stdout(Stdio::null())
+			.stderr(Stdio::piped())
+			.output()
+			.map_err(|e| io::Error::new(e.kind(), format!("{cmd}: {e}")))?;
+		if !out.status.success() {
+			return Err(io::Error::other(format!(
+				"{cmd} failed with {}: {}",
+				out.status,
+				String::from_utf8_lossy(&out.stderr).trim_end()
+			)));
+		}
+		taken.push(snap);
+	}
+	Ok(taken)
+}
+
+/* The root of the btrfs subvolume that the directory entry ‘p’ is in.  Every
+subvolume has a device number of its own, and its root the inode number 256. */
+fn subvolume(p: &Path) -> io::Result<PathBuf> {
+	let parent = p.parent().unwrap_or(p);
+	let dev = fs::symlink_metadata(parent)?.dev();
+	for a in parent.ancestors() {
+		let md = fs::symlink_metadata(a)?;
+		if md.dev() != dev {
+			break;
+		}
+		if md.ino() == 256 {
+			return Ok(a.to_owned());
+		}
+	}
+	Err(io::Error::new(
+		io::ErrorKind::NotFound,
+		format!("{}: no btrfs subvolume found", p.display()),
+	))
+}
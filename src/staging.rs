@@ -0,0 +1,365 @@
+use std::{
+	ffi::OsString,
+	fs::{self, File, OpenOptions},
+	io::{self, BufWriter, Write},
+	os::unix::ffi::{OsStrExt, OsStringExt},
+	path::{Path, PathBuf},
+};
+
+const MANIFEST: &str = "manifest";
+const DONE: &str = "done";
+const DUPLICATE: &str = "duplicate";
+const SKIPPED: &str = "skipped";
+const MAGIC: &str = "mmv-staging 2";
+const MAGIC_1: &str = "mmv-staging 1";
+const COMMIT: &str = "commit";
+const STAGED: &str = "staged";
+
+/* A record of what a staging directory is used for, so that interrupted runs
+can be picked up again (or undone) by reading it back.  It is a plain text
+file so that it also helps with recovering files by hand:
+
+	mmv-staging 2
+	move	ef3e3778
+	0	/path/to/source	/path/to/destination	3b9f8037
+	1	…
+	duplicate	/path/to/source	/path/to/destination	1f36a7dd
+	commit	3	e8b4491f
+	done	0	5b3b0e6d
+	skipped	1	8d426cd5
+	staged	ac71efa3
+	done	0	5b3b0e6d
+
+Duplicate lines list the sources that --merge-identical found to be identical
+to another file moved to the same destination, and which are trashed once
+everything else is in place.  Paths are escaped so that backslashes, tabs, and
+newlines in filenames cannot break the format.  The ‘staged’ line is only
+written once every source has been moved into the staging directory.
+
+As a run goes on, the entries whose files were moved are recorded by their
+numbers, first into the staging directory and then, after the ‘staged’ line,
+on to their destinations, so that an interrupted run can be picked up exactly
+where it stopped.  Entries left alone because their source changed are
+recorded as skipped.
+
+The file is only ever appended to, and every record but the first ends in the
+CRC-32 of the rest of it, so that a manifest cut short or otherwise damaged by a
+crash is noticed instead of being acted upon.  The ‘commit’ record closes the
+list of entries and duplicates with their number, so one that was not written
+in full is never mistaken for a shorter one.  Only the last record may be
+damaged, as that is what an interrupted write leaves behind; it is then
+ignored, which is safe as the files are found by which paths exist anyway.
+Manifests of the first version have no checksums and no ‘commit’ record. */
+pub struct Manifest {
+	pub copy: bool,
+	pub duplicates: Vec<(PathBuf, PathBuf)>,
+	pub entries: Vec<Entry>,
+	pub staged: bool,
+	/* Whether the last record was damaged and thus ignored */
+	pub torn: bool,
+	/* Whether the manifest is of the first version, which has to be upgraded
+	before records can be added to it */
+	pub legacy: bool,
+}
+
+pub struct Entry {
+	pub stage: PathBuf,
+	pub src: PathBuf,
+	pub dst: PathBuf,
+	/* Whether the file was moved in the current phase */
+	pub done: bool,
+	pub skipped: bool,
+}
+
+pub fn write(
+	dir: &Path,
+	copy: bool,
+	entries: &[(&Path, &Path, &Path)],
+	duplicates: &[(PathBuf, PathBuf)],
+) -> io::Result<()> {
+	let mut f = BufWriter::new(File::create(dir.join(MANIFEST))?);
+	writeln!(f, "{MAGIC}")?;
+	record(&mut f, &[if copy { b"copy" } else { b"move" }])?;
+	for (s, t, d) in entries {
+		let name = t.file_name().unwrap_or_default();
+		record(
+			&mut f,
+			&[
+				&escape(name.as_bytes()),
+				&escape(s.as_os_str().as_bytes()),
+				&escape(d.as_os_str().as_bytes()),
+			],
+		)?;
+	}
+	for (s, d) in duplicates {
+		record(
+			&mut f,
+			&[
+				DUPLICATE.as_bytes(),
+				&escape(s.as_os_str().as_bytes()),
+				&escape(d.as_os_str().as_bytes()),
+			],
+		)?;
+	}
+	let n = (entries.len() + duplicates.len()).to_string();
+	record(&mut f, &[COMMIT.as_bytes(), n.as_bytes()])?;
+	f.flush()?;
+	f.get_ref().sync_all()
+}
+
+/* Rewrite a manifest of the first version as one of the current version that
+records the same progress, replacing it atomically */
+pub fn upgrade(dir: &Path) -> io::Result<()> {
+	let data = fs::read(dir.join(MANIFEST))?;
+	let mut lines = data.split(|&b| b == b'\n').filter(|l| !l.is_empty());
+	if lines.next() != Some(MAGIC_1.as_bytes()) {
+		return Err(malformed());
+	}
+	let is_progress = |l: &&[u8]| {
+		*l == STAGED.as_bytes() || l.starts_with(b"done\t") || l.starts_with(b"skipped\t")
+	};
+
+	let tmp = dir.join(format!("{MANIFEST}.new"));
+	let mut f = BufWriter::new(File::create(&tmp)?);
+	writeln!(f, "{MAGIC}")?;
+	let mut n = 0usize;
+	let mut committed = false;
+	for (i, l) in lines.enumerate() {
+		if i > 0 && !committed && is_progress(&l) {
+			record(&mut f, &[COMMIT.as_bytes(), n.to_string().as_bytes()])?;
+			committed = true;
+		}
+		if i > 0 && !committed {
+			n += 1;
+		}
+		record(&mut f, &[l])?;
+	}
+	if !committed {
+		record(&mut f, &[COMMIT.as_bytes(), n.to_string().as_bytes()])?;
+	}
+	f.flush()?;
+	f.get_ref().sync_all()?;
+	fs::rename(tmp, dir.join(MANIFEST))
+}
+
+/* Cut the damaged last record off of the manifest, so that records can be
+added after it again */
+pub fn drop_torn(dir: &Path) -> io::Result<()> {
+	let path = dir.join(MANIFEST);
+	let data = fs::read(&path)?;
+	let end = data
+		.strip_suffix(b"\n")
+		.unwrap_or(&data)
+		.iter()
+		.rposition(|&b| b == b'\n')
+		.map_or(0, |i| i + 1);
+	let f = OpenOptions::new().write(true).open(&path)?;
+	f.set_len(end as u64)?;
+	f.sync_all()
+}
+
+pub fn mark_staged(dir: &Path) -> io::Result<()> {
+	let mut f = OpenOptions::new().append(true).open(dir.join(MANIFEST))?;
+	record(&mut f, &[STAGED.as_bytes()])?;
+	f.sync_all()
+}
+
+/* Record that the file of entry ‘i’ was moved in the current phase.  This is
+not synced to disk, as that would slow down runs over many small files for the
+sake of recovering from a crash of the whole system, after which the temporary
+directory holding the staged files may well be gone anyway. */
+pub fn mark_done(dir: &Path, i: usize) -> io::Result<()> {
+	let mut f = OpenOptions::new().append(true).open(dir.join(MANIFEST))?;
+	record(&mut f, &[DONE.as_bytes(), i.to_string().as_bytes()])
+}
+
+pub fn mark_skipped(dir: &Path, i: usize) -> io::Result<()> {
+	let mut f = OpenOptions::new().append(true).open(dir.join(MANIFEST))?;
+	record(&mut f, &[SKIPPED.as_bytes(), i.to_string().as_bytes()])
+}
+
+/* Write the record of the tab-separated ‘fields’ followed by its checksum in
+a single call, so that records appended to the manifest are never interleaved
+with anything else.  The history of runs is framed the same way. */
+pub fn record(f: &mut impl Write, fields: &[&[u8]]) -> io::Result<()> {
+	let mut line = fields.join(&b'\t');
+	let sum = format!("\t{:08x}\n", crc32(&line));
+	line.extend_from_slice(sum.as_bytes());
+	f.write_all(&line)
+}
+
+/* Split the checksum off of the record ‘line’, or None if it does not match */
+pub fn verify(line: &[u8]) -> Option<&[u8]> {
+	let (rest, sum) = line.split_at(line.len().checked_sub(9)?);
+	let sum = std::str::from_utf8(sum.strip_prefix(b"\t")?).ok()?;
+	(u32::from_str_radix(sum, 16).ok()? == crc32(rest)).then_some(rest)
+}
+
+/* The CRC-32 of ‘bs’ as used by zlib and many others */
+fn crc32(bs: &[u8]) -> u32 {
+	let mut crc = !0u32;
+	for &b in bs {
+		crc ^= b as u32;
+		for _ in 0..8 {
+			crc = (crc >> 1) ^ (0xedb88320 & (crc & 1).wrapping_neg());
+		}
+	}
+	!crc
+}
+
+fn malformed() -> io::Error {
+	io::Error::new(io::ErrorKind::InvalidData, "malformed staging manifest")
+}
+
+pub fn read(dir: &Path) -> io::Result<Manifest> {
+	let bad = malformed;
+
+	let data = fs::read(dir.join(MANIFEST))?;
+	let mut lines = data.split(|&b| b == b'\n');
+	let legacy = match lines.next() {
+		Some(l) if l == MAGIC.as_bytes() => false,
+		Some(l) if l == MAGIC_1.as_bytes() => true,
+		_ => return Err(bad()),
+	};
+	let mut lines = lines.collect::<Vec<_>>();
+	let mut torn = false;
+	if !legacy {
+		if data.ends_with(b"\n") {
+			lines.pop();
+		}
+		let n = lines.len();
+		for (i, l) in lines.iter_mut().enumerate() {
+			match verify(l) {
+				Some(r) => *l = r,
+				None if i + 1 == n => torn = true,
+				None => {
+					return Err(io::Error::new(
+						io::ErrorKind::InvalidData,
+						format!("damaged record on line {} of the staging manifest", i + 2),
+					))
+				}
+			}
+		}
+		if torn {
+			lines.pop();
+		}
+	}
+	let mut lines = lines.into_iter();
+	let copy = match lines.next() {
+		Some(b"copy") => true,
+		Some(b"move") => false,
+		_ => return Err(bad()),
+	};
+
+	let mut m = Manifest {
+		copy,
+		duplicates: Vec::new(),
+		entries: Vec::new(),
+		staged: false,
+		torn,
+		legacy,
+	};
+	let mut committed = false;
+	for line in lines.filter(|l| !l.is_empty()) {
+		if let Some(n) = line.strip_prefix(COMMIT.as_bytes()) {
+			let n = std::str::from_utf8(n.strip_prefix(b"\t").ok_or_else(bad)?)
+				.ok()
+				.and_then(|n| n.parse::<usize>().ok());
+			if legacy || committed || n != Some(m.entries.len() + m.duplicates.len()) {
+				return Err(bad());
+			}
+			committed = true;
+			continue;
+		}
+
+		/* Progress is only ever recorded once the entries are committed */
+		let started = legacy || committed;
+		if started && line == STAGED.as_bytes() && !m.staged {
+			m.staged = true;
+			for e in m.entries.iter_mut() {
+				e.done = false;
+			}
+			continue;
+		}
+
+		let progress = match line.split(|&b| b == b'\t').collect::<Vec<_>>()[..] {
+			[k, i] if started && (k == DONE.as_bytes() || k == SKIPPED.as_bytes()) => Some((k, i)),
+			_ => None,
+		};
+		if let Some((k, i)) = progress {
+			let e = std::str::from_utf8(i)
+				.ok()
+				.and_then(|i| i.parse::<usize>().ok())
+				.and_then(|i| m.entries.get_mut(i))
+				.ok_or_else(bad)?;
+			match k == DONE.as_bytes() {
+				true => e.done = true,
+				false if !m.staged => e.skipped = true,
+				false => return Err(bad()),
+			}
+			continue;
+		}
+		if m.staged || committed {
+			return Err(bad());
+		}
+
+		let fields = line
+			.split(|&b| b == b'\t')
+			.map(unescape)
+			.collect::<Option<Vec<_>>>()
+			.ok_or_else(bad)?;
+		let [name, src, dst] = <[Vec<u8>; 3]>::try_from(fields).map_err(|_| bad())?;
+		if name == DUPLICATE.as_bytes() {
+			m.duplicates.push((
+				PathBuf::from(OsString::from_vec(src)),
+				PathBuf::from(OsString::from_vec(dst)),
+			));
+			continue;
+		}
+		m.entries.push(Entry {
+			stage: dir.join(OsString::from_vec(name)),
+			src: PathBuf::from(OsString::from_vec(src)),
+			dst: PathBuf::from(OsString::from_vec(dst)),
+			done: false,
+			skipped: false,
+		});
+	}
+	if !legacy && !committed {
+		return Err(io::Error::new(
+			io::ErrorKind::InvalidData,
+			"the staging manifest was never written in full",
+		));
+	}
+
+	Ok(m)
+}
+
+pub fn escape(bs: &[u8]) -> Vec<u8> {
+	let mut out = Vec::with_capacity(bs.len());
+	for &b in bs {
+		match b {
+			b'\\' => out.extend_from_slice(b"\\\\"),
+			b'\t' => out.extend_from_slice(b"\\t"),
+			b'\n' => out.extend_from_slice(b"\\n"),
+			_ => out.push(b),
+		}
+	}
+	out
+}
+
+pub fn unescape(bs: &[u8]) -> Option<Vec<u8>> {
+	let mut out = Vec::with_capacity(bs.len());
+	let mut it = bs.iter();
+	while let Some(&b) = it.next() {
+		out.push(match b {
+			b'\\' => match it.next()? {
+				b'\\' => b'\\',
+				b't' => b'\t',
+				b'n' => b'\n',
+				_ => return None,
+			},
+			_ => b,
+		});
+	}
+	Some(out)
+}
@@ -0,0 +1,168 @@
+use std::borrow::Cow;
+
+use regex::{Captures, Regex, RegexBuilder};
+
+use crate::join::Table;
+
+/* A single sed-style ‘s/RE/REPL/FLAGS’ expression.  The regular expression
+uses the syntax of the regex crate, and the replacement string may refer to
+capture groups as ‘$1’, ‘${1}’, or ‘${name}’. */
+pub struct Substitution {
+	re: Regex,
+	repl: String,
+	global: bool,
+}
+
+impl Substitution {
+	pub fn parse(expr: &str) -> Result<Substitution, String> {
+		let mut chars = expr.chars();
+		if chars.next() != Some('s') {
+			return Err("expression must begin with ‘s’".to_string());
+		}
+		let delim = match chars.next() {
+			Some(c) if c != '\\' && c != '\n' && !c.is_alphanumeric() => c,
+			Some(c) => return Err(format!("invalid delimiter ‘{c}’")),
+			None => return Err("missing delimiter".to_string()),
+		};
+
+		let re = split_part(&mut chars, delim).ok_or("unterminated regular expression")?;
+		let repl = split_part(&mut chars, delim).ok_or("unterminated replacement")?;
+
+		let mut builder = RegexBuilder::new(&re);
+		let mut global = false;
+		for c in chars {
+			match c {
+				'g' => global = true,
+				'i' => {
+					builder.case_insensitive(true);
+				}
+				_ => return Err(format!("unknown flag ‘{c}’")),
+			}
+		}
+
+		let re = builder.build().map_err(|e| e.to_string())?;
+		Ok(Substitution { re, repl, global })
+	}
+
+	pub fn apply<'a>(&self, s: &'a str) -> Cow<'a, str> {
+		if self.global {
+			self.re.replace_all(s, self.repl.as_str())
+		} else {
+			self.re.replace(s, self.repl.as_str())
+		}
+	}
+
+	/* Like apply(), but with the replacement string also referring to the
+	columns of the row of ‘table’ for each match.  The row is the one keyed
+	by the text matched by the capture group named ‘key’ if there is one, and
+	by ‘name’ otherwise.  Capture groups take precedence over columns of the
+	same name. */
+	pub fn apply_joined<'a>(
+		&self,
+		s: &'a str,
+		name: &str,
+		table: &Table,
+	) -> Result<Cow<'a, str>, String> {
+		let mut err = None;
+		let replacer = |caps: &Captures| {
+			let key = caps.name("key").map_or(name, |m| m.as_str());
+			let mut row = None;
+			let repl = expand_columns(&self.repl, |col| {
+				if self.re.capture_names().flatten().any(|n| n == col) {
+					return None;
+				}
+				let i = table.column(col)?;
+				match row.or_else(|| table.row(key)) {
+					Some(r) => {
+						row = Some(r);
+						Some(r[i].replace('$', "$$"))
+					}
+					None => {
+						err.get_or_insert_with(|| format!("no row with the key ‘{key}’"));
+						Some(String::new())
+					}
+				}
+			});
+			let mut out = String::new();
+			caps.expand(&repl, &mut out);
+			out
+		};
+
+		let out = if self.global {
+			self.re.replace_all(s, replacer)
+		} else {
+			self.re.replace(s, replacer)
+		};
+		match err {
+			Some(e) => Err(e),
+			None => Ok(out),
+		}
+	}
+}
+
+/* Replace the references to names in ‘repl’ for which ‘lookup’ returns a
+value, in the same syntax as references to capture groups.  Everything else
+is left as it is for Captures::expand(). */
+fn expand_columns(repl: &str, mut lookup: impl FnMut(&str) -> Option<String>) -> String {
+	let mut out = String::with_capacity(repl.len());
+	let mut rest = repl;
+	while let Some(i) = rest.find('$') {
+		out.push_str(&rest[..i]);
+		rest = &rest[i + 1..];
+
+		let (name, len) = if let Some(r) = rest.strip_prefix('{') {
+			match r.find('}') {
+				Some(j) => (&r[..j], j + 2),
+				None => ("", 0),
+			}
+		} else {
+			let j = rest
+				.find(|c: char| c != '_' && !c.is_ascii_alphanumeric())
+				.unwrap_or(rest.len());
+			(&rest[..j], j)
+		};
+
+		let value = match name {
+			"" => None,
+			n if n.bytes().all(|b| b.is_ascii_digit()) => None,
+			n => lookup(n),
+		};
+		match value {
+			Some(v) => out.push_str(&v),
+			None if rest.starts_with('$') => {
+				out.push_str("$$");
+				rest = &rest[1..];
+				continue;
+			}
+			None => {
+				out.push('$');
+				out.push_str(&rest[..len]);
+			}
+		}
+		rest = &rest[len..];
+	}
+	out.push_str(rest);
+	out
+}
+
+/* Consume characters up to the next unescaped delimiter.  An escaped delimiter
+is unescaped; every other escape sequence is left untouched for the regex
+engine to interpret. */
+fn split_part(chars: &mut std::str::Chars, delim: char) -> Option<String> {
+	let mut part = String::new();
+	while let Some(c) = chars.next() {
+		match c {
+			'\\' => match chars.next() {
+				Some(c) if c == delim => part.push(c),
+				Some(c) => {
+					part.push('\\');
+					part.push(c);
+				}
+				None => part.push('\\'),
+			},
+			_ if c == delim => return Some(part),
+			_ => part.push(c),
+		}
+	}
+	None
+}
@@ -0,0 +1,90 @@
+use std::{
+	fs, io,
+	os::unix,
+	path::{Component, Path, PathBuf},
+};
+
+/* A symbolic link that will point to the wrong place once the batch has been
+moved, along with the location it will have been moved to and what it
+should point to from there. */
+pub struct Fix {
+	pub link: PathBuf,
+	pub target: PathBuf,
+}
+
+/* Find the relative symbolic links inside of the moved directories whose
+targets will no longer be reachable by the same relative path after every
+source has been moved to its destination.  Targets that are themselves being
+moved are followed to their new location.  This has to be called before
+anything is moved, and all paths must be absolute and normalized. */
+pub fn plan(pairs: &[(&Path, &Path)]) -> io::Result<Vec<Fix>> {
+	let mut fixes = Vec::new();
+	for (s, _) in pairs.iter() {
+		let mut stack = vec![s.to_path_buf()];
+		while let Some(p) = stack.pop() {
+			let data = fs::symlink_metadata(&p)?;
+			if data.is_dir() {
+				for e in fs::read_dir(&p)? {
+					stack.push(e?.path());
+				}
+				continue;
+			}
+			if !data.file_type().is_symlink() {
+				continue;
+			}
+
+			let old = fs::read_link(&p)?;
+			if old.is_absolute() {
+				continue;
+			}
+			let link = relocate(pairs, &p);
+			let target = relocate(
+				pairs,
+				&crate::normalize_path(&p.parent().unwrap().join(&old)),
+			);
+			let new = relative(link.parent().unwrap(), &target);
+			if new != old {
+				fixes.push(Fix { link, target: new });
+			}
+		}
+	}
+	Ok(fixes)
+}
+
+/* Point the link at its new target.  Links that were replaced by something
+else in the meantime are left alone. */
+pub fn apply(fix: &Fix) -> io::Result<()> {
+	if !fs::symlink_metadata(&fix.link)?.file_type().is_symlink() {
+		return Ok(());
+	}
+	fs::remove_file(&fix.link)?;
+	unix::fs::symlink(&fix.target, &fix.link)
+}
+
+/* Where ‘p’ will be once the batch has been moved */
+fn relocate(pairs: &[(&Path, &Path)], p: &Path) -> PathBuf {
+	pairs
+		.iter()
+		.filter(|(s, _)| p.starts_with(s))
+		.max_by_key(|(s, _)| s.components().count())
+		.map(|(s, d)| d.join(p.strip_prefix(s).unwrap()))
+		.unwrap_or_else(|| p.to_owned())
+}
+
+/* The relative path from the directory ‘from’ to ‘to’ */
+fn relative(from: &Path, to: &Path) -> PathBuf {
+	let (mut fs, mut ts) = (from.components().peekable(), to.components().peekable());
+	while fs.peek().is_some() && fs.peek() == ts.peek() {
+		fs.next();
+		ts.next();
+	}
+	let p = fs
+		.map(|_| Component::ParentDir)
+		.chain(ts)
+		.collect::<PathBuf>();
+	if p.as_os_str().is_empty() {
+		PathBuf::from(".")
+	} else {
+		p
+	}
+}
@@ -0,0 +1,48 @@
+use std::{
+	cmp::Reverse,
+	path::{Path, PathBuf},
+	sync::Mutex,
+	time::Duration,
+};
+
+/* How long every operation took, for --timings */
+static TIMINGS: Mutex<Vec<(Duration, &'static str, PathBuf, PathBuf)>> = Mutex::new(Vec::new());
+
+/* How many of the slowest operations are reported */
+const SLOWEST: usize = 10;
+
+pub fn record(took: Duration, action: &'static str, from: &Path, to: &Path) {
+	TIMINGS
+		.lock()
+		.unwrap()
+		.push((took, action, from.to_owned(), to.to_owned()));
+}
+
+/* Report the slowest of the operations recorded, along with what they turned
+out to be.  A move that takes long is usually one that was copied because it
+crossed a device boundary, or one on a slow network mount. */
+pub fn report() {
+	let mut ts = std::mem::take(&mut *TIMINGS.lock().unwrap());
+	if ts.is_empty() {
+		return;
+	}
+	let total: Duration = ts.iter().map(|(d, ..)| *d).sum();
+	ts.sort_by_key(|(d, ..)| Reverse(*d));
+	eprintln!(
+		"{} operation(s) took {}; the slowest were:",
+		ts.len(),
+		millis(total)
+	);
+	for (d, action, from, to) in ts.iter().take(SLOWEST) {
+		eprintln!(
+			"{:>12}  {action} ‘{}’ -> ‘{}’",
+			millis(*d),
+			from.display(),
+			to.display()
+		);
+	}
+}
+
+fn millis(d: Duration) -> String {
+	format!("{:.3}ms", d.as_secs_f64() * 1000.0)
+}
@@ -0,0 +1,196 @@
+use std::{
+	env,
+	fs::{self, OpenOptions},
+	io::{self, Write},
+	mem,
+	os::unix::{
+		ffi::OsStrExt,
+		fs::{DirBuilderExt, MetadataExt, PermissionsExt},
+	},
+	path::{Path, PathBuf},
+	time::{SystemTime, UNIX_EPOCH},
+};
+
+/* Move the file or directory at the absolute path ‘path’ into a trash as
+described by the FreeDesktop.org trash specification, returning the location
+it was moved to.  Files on the filesystem of the home trash go there, and those
+on any other filesystem into the trash at the top directory of their own, as
+renaming them into the home trash would mean copying them. */
+pub fn trash(path: &Path) -> io::Result<PathBuf> {
+	let home = home_trash()?;
+	let dev = fs::symlink_metadata(path)?.dev();
+	let (dir, relative_to) = if device_of(&home)? == dev {
+		(home, None)
+	} else {
+		let top = top_dir(path, dev)?;
+		(top_trash(&top)?, Some(top))
+	};
+	let files = dir.join("files");
+	let info = dir.join("info");
+	fs::create_dir_all(&files)?;
+	fs::create_dir_all(&info)?;
+
+	let name = path
+		.file_name()
+		.ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "path has no basename"))?;
+
+	/* Reserve a unique name by exclusively creating its info file, as
+	required by the specification. */
+	let mut n = 0;
+	let (mut infofile, dst) = loop {
+		let mut base = name.to_os_string();
+		if n > 0 {
+			base.push(format!(".{n}"));
+		}
+		let mut infoname = base.clone();
+		infoname.push(".trashinfo");
+		match OpenOptions::new()
+			.write(true)
+			.create_new(true)
+			.open(info.join(&infoname))
+		{
+			Ok(f) => break (f, (info.join(infoname), files.join(base))),
+			Err(e) if e.kind() == io::ErrorKind::AlreadyExists => n += 1,
+			Err(e) => return Err(e),
+		}
+	};
+
+	/* The trashes at the top directories name files relative to them, so
+	that they still make sense when the filesystem is mounted elsewhere */
+	let shown = match &relative_to {
+		Some(top) => path.strip_prefix(top).unwrap_or(path),
+		None => path,
+	};
+	let res = write!(
+		infofile,
+		"[Trash Info]\nPath={}\nDeletionDate={}\n",
+		percent_encode(shown),
+		deletion_date()
+	)
+	.and_then(|_| fs::rename(path, &dst.1));
+	if let Err(e) = res {
+		let _ = fs::remove_file(&dst.0);
+		return Err(if e.kind() == io::ErrorKind::CrossesDevices {
+			io::Error::new(e.kind(), "cannot trash files across filesystems")
+		} else {
+			e
+		});
+	}
+
+	Ok(dst.1)
+}
+
+/* The device number of ‘p’, or of its closest ancestor that exists for trash
+directories that are yet to be created */
+fn device_of(p: &Path) -> io::Result<u64> {
+	for a in p.ancestors() {
+		match fs::symlink_metadata(a) {
+			Ok(m) => return Ok(m.dev()),
+			Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+			Err(e) => return Err(e),
+		}
+	}
+	Err(io::Error::new(
+		io::ErrorKind::NotFound,
+		"no trash directory",
+	))
+}
+
+/* The top directory of the filesystem with the device number ‘dev’ that ‘path’
+is on, which is the last of its ancestors to still be on that device */
+fn top_dir(path: &Path, dev: u64) -> io::Result<PathBuf> {
+	let mut top = path.parent().unwrap_or(path);
+	for a in path.ancestors().skip(2) {
+		if fs::symlink_metadata(a)?.dev() != dev {
+			break;
+		}
+		top = a;
+	}
+	Ok(top.to_owned())
+}
+
+/* The trash of the user in the top directory ‘top’.  An administrator may
+provide ‘$top/.Trash’ as a sticky directory holding a trash for every user,
+which is only to be used if it is not a symbolic link, and otherwise every user
+gets a ‘$top/.Trash-$uid’ of their own. */
+fn top_trash(top: &Path) -> io::Result<PathBuf> {
+	let uid = rustix::process::getuid().as_raw();
+	let shared = top.join(".Trash");
+	let sticky = fs::symlink_metadata(&shared)
+		.is_ok_and(|m| m.is_dir() && m.permissions().mode() & 0o1000 != 0);
+	if sticky {
+		let dir = shared.join(uid.to_string());
+		if fs::DirBuilder::new().mode(0o700).create(&dir).is_ok() || dir.is_dir() {
+			return Ok(dir);
+		}
+	}
+
+	let dir = top.join(format!(".Trash-{uid}"));
+	match fs::DirBuilder::new().mode(0o700).create(&dir) {
+		Ok(()) => Ok(dir),
+		Err(e) if e.kind() == io::ErrorKind::AlreadyExists => {
+			let m = fs::symlink_metadata(&dir)?;
+			if m.is_dir() && m.uid() == uid {
+				Ok(dir)
+			} else {
+				Err(io::Error::new(
+					io::ErrorKind::PermissionDenied,
+					format!("{}: not a trash directory of ours", dir.display()),
+				))
+			}
+		}
+		Err(e) => Err(io::Error::new(e.kind(), format!("{}: {e}", dir.display()))),
+	}
+}
+
+fn home_trash() -> io::Result<PathBuf> {
+	match env::var_os("XDG_DATA_HOME") {
+		Some(s) if !s.is_empty() => Ok(PathBuf::from(s).join("Trash")),
+		_ => match env::var_os("HOME") {
+			Some(s) => Ok([s.as_os_str(), ".local/share/Trash".as_ref()]
+				.iter()
+				.collect()),
+			None => Err(io::Error::new(
+				io::ErrorKind::NotFound,
+				"One of the XDG_DATA_HOME or HOME variables must be set",
+			)),
+		},
+	}
+}
+
+fn percent_encode(path: &Path) -> String {
+	path.as_os_str()
+		.as_bytes()
+		.iter()
+		.map(|&b| match b {
+			b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' | b'/' => {
+				(b as char).to_string()
+			}
+			_ => format!("%{b:02X}"),
+		})
+		.collect()
+}
+
+/* The deletion date in local time, as the specification asks for */
+fn deletion_date() -> String {
+	let secs = SystemTime::now()
+		.duration_since(UNIX_EPOCH)
+		.map(|d| d.as_secs())
+		.unwrap_or(0) as libc::time_t;
+	/* SAFETY: ‘tm’ is plain old data for localtime_r() to fill in, which can
+	only fail for times whose year overflows an int */
+	let tm = unsafe {
+		let mut tm = mem::zeroed::<libc::tm>();
+		libc::localtime_r(&secs, &mut tm);
+		tm
+	};
+	format!(
+		"{:04}-{:02}-{:02}T{:02}:{:02}:{:02}",
+		tm.tm_year + 1900,
+		tm.tm_mon + 1,
+		tm.tm_mday,
+		tm.tm_hour,
+		tm.tm_min,
+		tm.tm_sec
+	)
+}
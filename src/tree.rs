@@ -0,0 +1,112 @@
+use std::{
+	collections::{BTreeMap, BTreeSet, HashMap},
+	ffi::{OsStr, OsString},
+	fs, io,
+	path::{Path, PathBuf},
+};
+
+/* Render the directories that the moves between ‘pairs’ touch as they will be
+afterwards, in the style of tree(1).  Nothing but the directories that files
+are moved out of or into is shown in full, as a preview of hundreds of renames
+in one directory is only useful if it is not drowned out by everything else.
+
+As files are moved by way of the staging directory, every destination ends up
+at exactly the given path, so the new contents of a directory are its old
+contents without its sources plus its destinations.  Directories that are
+themselves moved take their contents along. */
+pub fn render(pairs: &[(&Path, &Path)], moved: bool) -> io::Result<String> {
+	let srcs: HashMap<&Path, &Path> = pairs.iter().copied().collect();
+	let dsts: HashMap<&Path, &Path> = pairs.iter().map(|&(s, d)| (d, s)).collect();
+
+	let mut dirs = BTreeMap::new();
+	for &(s, d) in pairs {
+		for p in [Some(d), Some(s).filter(|_| moved)].into_iter().flatten() {
+			if let Some(parent) = p.parent() {
+				dirs.entry(parent.to_path_buf())
+					.or_insert_with(BTreeSet::new);
+			}
+		}
+	}
+
+	for (dir, names) in dirs.iter_mut() {
+		if let Some(origin) = origin(dir, &srcs, &dsts, moved) {
+			match fs::read_dir(&origin) {
+				Ok(it) => {
+					for e in it {
+						let name = e?.file_name();
+						if !moved || !srcs.contains_key(origin.join(&name).as_path()) {
+							names.insert(name);
+						}
+					}
+				}
+				Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+				Err(e) => return Err(e),
+			}
+		}
+		for d in dsts.keys().filter(|d| d.parent() == Some(dir.as_path())) {
+			names.extend(d.file_name().map(OsStr::to_os_string));
+		}
+	}
+
+	let root = dirs
+		.keys()
+		.cloned()
+		.reduce(|a, b| common_ancestor(&a, &b))
+		.unwrap_or_default();
+	let mut out = format!("{}\n", root.display());
+	draw(&root, &dirs, "", &mut out);
+	Ok(out)
+}
+
+/* Find where the contents of ‘dir’ come from, if anywhere */
+fn origin(
+	dir: &Path,
+	srcs: &HashMap<&Path, &Path>,
+	dsts: &HashMap<&Path, &Path>,
+	moved: bool,
+) -> Option<PathBuf> {
+	for a in dir.ancestors() {
+		if let Some(s) = dsts.get(a) {
+			return Some(s.join(dir.strip_prefix(a).unwrap()));
+		}
+		if moved && srcs.contains_key(a) {
+			return None;
+		}
+	}
+	Some(dir.to_path_buf())
+}
+
+fn draw(dir: &Path, dirs: &BTreeMap<PathBuf, BTreeSet<OsString>>, indent: &str, out: &mut String) {
+	/* Directories are only shown in full if they are affected, but the ones
+	leading up to them are always shown. */
+	let mut names = dirs.get(dir).cloned().unwrap_or_default();
+	names.extend(
+		dirs.keys()
+			.filter(|p| p.starts_with(dir) && *p != dir)
+			.filter_map(|p| p.strip_prefix(dir).unwrap().iter().next())
+			.map(OsStr::to_os_string),
+	);
+
+	let n = names.len();
+	for (i, name) in names.into_iter().enumerate() {
+		let (branch, next) = if i + 1 == n {
+			("└── ", "    ")
+		} else {
+			("├── ", "│   ")
+		};
+		out.push_str(&format!("{indent}{branch}{}\n", name.to_string_lossy()));
+
+		let p = dir.join(&name);
+		if dirs.keys().any(|d| d.starts_with(&p)) {
+			draw(&p, dirs, &format!("{indent}{next}"), out);
+		}
+	}
+}
+
+fn common_ancestor(a: &Path, b: &Path) -> PathBuf {
+	a.components()
+		.zip(b.components())
+		.take_while(|(x, y)| x == y)
+		.map(|(x, _)| x)
+		.collect()
+}
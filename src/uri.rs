@@ -0,0 +1,49 @@
+/* The path named by the file URI ‘s’ for --uri, as found in text/uri-list
+lists such as those that file managers put on the clipboard or hand over on
+drag-and-drop.  Such lists may hold comments, which are None, and end their
+lines with CRLF.  Only files on this host are accepted, named either by an
+empty host or by ‘localhost’, or by no host at all as in ‘file:/path’. */
+pub fn decode(s: &str) -> Result<Option<String>, String> {
+	let s = s.strip_suffix('\r').unwrap_or(s);
+	if s.is_empty() || s.starts_with('#') {
+		return Ok(None);
+	}
+	let Some(rest) = s
+		.get(..5)
+		.filter(|p| p.eq_ignore_ascii_case("file:"))
+		.map(|_| &s[5..])
+	else {
+		return Err("not a file URI".to_string());
+	};
+	let path = match rest.strip_prefix("//") {
+		None if rest.starts_with('/') => rest,
+		None => return Err("not an absolute path".to_string()),
+		Some(rest) => match rest.find('/') {
+			Some(0) => rest,
+			Some(i) if rest[..i].eq_ignore_ascii_case("localhost") => &rest[i..],
+			Some(i) => return Err(format!("file on the remote host ‘{}’", &rest[..i])),
+			None => return Err("no path given".to_string()),
+		},
+	};
+
+	/* The query and fragment are no part of the path */
+	let path = &path[..path.find(['?', '#']).unwrap_or(path.len())];
+	let mut bs = Vec::with_capacity(path.len());
+	let mut it = path.bytes();
+	while let Some(b) = it.next() {
+		if b != b'%' {
+			bs.push(b);
+			continue;
+		}
+		let hex = |b: Option<u8>| b.and_then(|b| (b as char).to_digit(16));
+		match (hex(it.next()), hex(it.next())) {
+			(Some(h), Some(l)) => bs.push((h * 16 + l) as u8),
+			_ => return Err("invalid percent-escape".to_string()),
+		}
+	}
+	match String::from_utf8(bs) {
+		Ok(p) if p.contains('\0') => Err("NUL byte in path".to_string()),
+		Ok(p) => Ok(Some(p)),
+		Err(_) => Err("path is not valid UTF-8".to_string()),
+	}
+}
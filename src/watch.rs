@@ -0,0 +1,54 @@
+use std::{
+	ffi::OsStr,
+	fs::File,
+	io::{self, Read},
+	os::{fd::AsFd, unix::ffi::OsStrExt},
+	path::{Path, PathBuf},
+};
+
+use rustix::fs::inotify::{self, CreateFlags, WatchFlags};
+
+/* The size of a struct inotify_event without the trailing name */
+const EVENT_SIZE: usize = 16;
+
+/* A directory being watched for files that appear in it, either by being
+written to and closed, or by being moved into it. */
+pub struct Watcher {
+	fd: File,
+	buf: Vec<u8>,
+}
+
+impl Watcher {
+	pub fn new(dir: &Path) -> io::Result<Watcher> {
+		let fd = inotify::inotify_init(CreateFlags::CLOEXEC)?;
+		inotify::inotify_add_watch(
+			fd.as_fd(),
+			dir,
+			WatchFlags::CLOSE_WRITE | WatchFlags::MOVED_TO | WatchFlags::ONLYDIR,
+		)?;
+		Ok(Watcher {
+			fd: File::from(fd),
+			buf: vec![0; 64 * 1024],
+		})
+	}
+
+	/* Block until files appear, returning the names of all of those that the
+	kernel has reported so far. */
+	pub fn wait(&mut self) -> io::Result<Vec<PathBuf>> {
+		let n = self.fd.read(&mut self.buf)?;
+		let mut names = Vec::new();
+		let mut i = 0;
+		while i + EVENT_SIZE <= n {
+			let len = u32::from_ne_bytes(self.buf[i + 12..i + 16].try_into().unwrap()) as usize;
+			let name = &self.buf[i + EVENT_SIZE..(i + EVENT_SIZE + len).min(n)];
+
+			/* The name is padded with NUL bytes */
+			let name = name.split(|&b| b == 0).next().unwrap_or_default();
+			if !name.is_empty() {
+				names.push(PathBuf::from(OsStr::from_bytes(name)));
+			}
+			i += EVENT_SIZE + len;
+		}
+		Ok(names)
+	}
+}